@@ -0,0 +1,447 @@
+//! Generates the typed messages of the service from its WSDL.
+//!
+//! Every element declared in the schemas of the WSDL becomes a struct
+//! with a field per attribute and per child element. Nested anonymous
+//! types are named after the parent struct and the element, e.g.
+//! `NdsRequest2Np`. The structs can be built from and converted to
+//! `xmltree::Element`, and the input messages of the operations also
+//! to `rpser::Method`.
+
+extern crate xmltree;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use xmltree::Element;
+
+const WSDL: &'static str = "wsdl/FNSNDSCAWS_2.wsdl";
+const OUTPUT: &'static str = "fnsndscaws2.rs";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    String,
+    Int,
+    Long,
+}
+
+#[derive(Debug)]
+enum Field {
+    Attribute {
+        xml_name: String,
+        scalar: Scalar,
+        optional: bool,
+    },
+    Child {
+        xml_name: String,
+        struct_name: String,
+        min_occurs: usize,
+        max_occurs: Option<usize>,
+    },
+}
+
+#[derive(Debug)]
+struct Struct {
+    name: String,
+    xml_name: String,
+    namespace: String,
+    fields: Vec<Field>,
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", WSDL);
+
+    let file = File::open(WSDL).expect("WSDL of the service");
+    let wsdl = Element::parse(file).expect("valid WSDL");
+
+    let schemas: Vec<&Element> = children(&wsdl, "types")
+        .into_iter()
+        .flat_map(|types| children(types, "schema"))
+        .collect();
+
+    let mut simple_types = HashMap::new();
+    for schema in &schemas {
+        for simple_type in children(schema, "simpleType") {
+            let base = children(simple_type, "restriction")
+                .first()
+                .map(|restriction| attr(restriction, "base"))
+                .unwrap_or_else(|| panic!("simpleType {} without restriction", attr(simple_type, "name")));
+            let scalar = scalar(&base, &simple_types);
+            simple_types.insert(attr(simple_type, "name"), scalar);
+        }
+    }
+
+    let mut structs = vec![];
+    for schema in &schemas {
+        let namespace = attr(schema, "targetNamespace");
+        for element in children(schema, "element") {
+            let name = camel_case(&attr(element, "name"));
+            collect(&name, element, &namespace, &simple_types, &mut structs);
+        }
+    }
+
+    // Operations by the elements of their input messages
+    let messages: HashMap<String, String> = children(&wsdl, "message")
+        .into_iter()
+        .filter_map(|message| {
+            children(message, "part")
+                .first()
+                .map(|part| (attr(message, "name"), local_name(&attr(part, "element")).to_string()))
+        })
+        .collect();
+    let mut operations = vec![];
+    for port_type in children(&wsdl, "portType") {
+        for operation in children(port_type, "operation") {
+            let input = children(operation, "input")
+                .first()
+                .map(|input| local_name(&attr(input, "message")).to_string())
+                .unwrap_or_else(|| panic!("operation {} without input", attr(operation, "name")));
+            let element = messages
+                .get(&input)
+                .unwrap_or_else(|| panic!("message {} not found", input));
+            operations.push((attr(operation, "name"), element.clone()));
+        }
+    }
+
+    let mut code = String::new();
+    writeln!(code, "// Generated by build.rs from {}, do not edit.", WSDL).unwrap();
+    generate(&mut code, &structs, &operations);
+
+    let path = Path::new(&env::var("OUT_DIR").unwrap()).join(OUTPUT);
+    File::create(&path)
+        .and_then(|mut file| file.write_all(code.as_bytes()))
+        .expect("generated messages are written");
+}
+
+/// Collects the struct of the element and of its nested elements.
+fn collect(
+    name: &str,
+    element: &Element,
+    namespace: &str,
+    simple_types: &HashMap<String, Scalar>,
+    structs: &mut Vec<Struct>,
+) {
+    let complex_type = match children(element, "complexType").into_iter().next() {
+        Some(complex_type) => complex_type,
+        None => panic!("element {} without complexType", attr(element, "name")),
+    };
+
+    let mut fields = vec![];
+    for attribute in children(complex_type, "attribute") {
+        fields.push(Field::Attribute {
+            xml_name: attr(attribute, "name"),
+            scalar: scalar(&attr(attribute, "type"), simple_types),
+            optional: attribute.attributes.get("use").map_or(true, |u| u != "required"),
+        });
+    }
+
+    let sequences = children(complex_type, "sequence");
+    for child in sequences.iter().flat_map(|sequence| children(sequence, "element")) {
+        let xml_name = attr(child, "name");
+        let struct_name = format!("{}{}", name, camel_case(&xml_name));
+        let min_occurs = match child.attributes.get("minOccurs") {
+            Some(value) => value.parse().expect("numeric minOccurs"),
+            None => 1,
+        };
+        let max_occurs = match child.attributes.get("maxOccurs").map(|s| s.as_str()) {
+            Some("unbounded") => None,
+            Some(value) => Some(value.parse().expect("numeric maxOccurs")),
+            None => Some(1),
+        };
+
+        collect(&struct_name, child, namespace, simple_types, structs);
+        fields.push(Field::Child {
+            xml_name: xml_name,
+            struct_name: struct_name,
+            min_occurs: min_occurs,
+            max_occurs: max_occurs,
+        });
+    }
+
+    structs.push(Struct {
+        name: name.to_string(),
+        xml_name: attr(element, "name"),
+        namespace: namespace.to_string(),
+        fields: fields,
+    });
+}
+
+fn generate(code: &mut String, structs: &[Struct], operations: &[(String, String)]) {
+    writeln!(code, "
+fn qualified(prefix: &str, name: &str) -> String {{
+    if prefix.is_empty() {{
+        name.to_string()
+    }} else {{
+        format!(\"{{}}:{{}}\", prefix, name)
+    }}
+}}").unwrap();
+
+    for s in structs {
+        writeln!(code, "\n/// `{}` element of `{}`", s.xml_name, s.namespace).unwrap();
+        writeln!(code, "#[derive(Debug, Clone, PartialEq, Default)]").unwrap();
+        writeln!(code, "pub struct {} {{", s.name).unwrap();
+        for field in &s.fields {
+            match *field {
+                Field::Attribute { ref xml_name, scalar, optional } => {
+                    writeln!(code, "    /// `{}` attribute", xml_name).unwrap();
+                    let ty = rust_type(scalar);
+                    if optional {
+                        writeln!(code, "    pub {}: Option<{}>,", field_name(xml_name), ty).unwrap();
+                    } else {
+                        writeln!(code, "    pub {}: {},", field_name(xml_name), ty).unwrap();
+                    }
+                }
+                Field::Child { ref xml_name, ref struct_name, max_occurs, min_occurs } => {
+                    match max_occurs {
+                        Some(1) if min_occurs == 0 => {
+                            writeln!(code, "    /// `{}` element", xml_name).unwrap();
+                            writeln!(code, "    pub {}: Option<{}>,", field_name(xml_name), struct_name).unwrap();
+                        }
+                        Some(1) => {
+                            writeln!(code, "    /// `{}` element", xml_name).unwrap();
+                            writeln!(code, "    pub {}: {},", field_name(xml_name), struct_name).unwrap();
+                        }
+                        Some(max) => {
+                            writeln!(code, "    /// `{}` elements, at most {}", xml_name, max).unwrap();
+                            writeln!(code, "    pub {}: Vec<{}>,", field_name(xml_name), struct_name).unwrap();
+                        }
+                        None => {
+                            writeln!(code, "    /// `{}` elements", xml_name).unwrap();
+                            writeln!(code, "    pub {}: Vec<{}>,", field_name(xml_name), struct_name).unwrap();
+                        }
+                    }
+                }
+            }
+        }
+        writeln!(code, "}}").unwrap();
+
+        for field in &s.fields {
+            if let Field::Child { ref xml_name, max_occurs: Some(max), .. } = *field {
+                if max > 1 {
+                    writeln!(code, "\n/// The maximum number of `{}` elements in `{}`", xml_name, s.xml_name).unwrap();
+                    writeln!(
+                        code,
+                        "pub const {}_{}_MAX_OCCURS: usize = {};",
+                        field_name(&s.xml_name).to_uppercase(),
+                        field_name(xml_name).to_uppercase(),
+                        max
+                    ).unwrap();
+                }
+            }
+        }
+
+        generate_to_element(code, s);
+        generate_from_element(code, s);
+    }
+
+    for &(ref operation, ref element) in operations {
+        let s = structs
+            .iter()
+            .find(|s| s.name == camel_case(element))
+            .unwrap_or_else(|| panic!("element {} of operation {} not found", element, operation));
+        if s.fields.iter().any(|field| match *field {
+            Field::Attribute { .. } => true,
+            _ => false,
+        }) {
+            panic!("attributes of the input element {} are not supported", element);
+        }
+
+        writeln!(code, "
+impl {name} {{
+    /// The `{operation}` operation called with the message.
+    ///
+    /// `prefix` is the prefix the namespace of the request is bound to.
+    pub fn to_method(&self, prefix: &str) -> Method {{
+        let mut method = Method::new(\"{operation}\");
+        method.args = self.to_element(prefix).children;
+        method
+    }}
+}}", name = s.name, operation = operation).unwrap();
+    }
+}
+
+fn generate_to_element(code: &mut String, s: &Struct) {
+    let binding = if s.fields.is_empty() { "let" } else { "let mut" };
+    writeln!(code, "
+impl {} {{
+    /// Builds the `{}` element, `prefix` is the prefix of its namespace.
+    pub fn to_element(&self, prefix: &str) -> Element {{
+        {} element = Element::node(qualified(prefix, \"{}\"));", s.name, s.xml_name, binding, s.xml_name).unwrap();
+
+    for field in &s.fields {
+        match *field {
+            Field::Attribute { ref xml_name, optional, .. } => {
+                let name = field_name(xml_name);
+                if optional {
+                    writeln!(code, "        if let Some(ref value) = self.{} {{
+            element = element.with_attr(\"{}\", value.to_string());
+        }}", name, xml_name).unwrap();
+                } else {
+                    writeln!(code, "        element = element.with_attr(\"{}\", self.{}.to_string());", xml_name, name).unwrap();
+                }
+            }
+            Field::Child { ref xml_name, max_occurs, min_occurs, .. } => {
+                let name = field_name(xml_name);
+                let result = match max_occurs {
+                    Some(1) if min_occurs == 0 => writeln!(code, "        if let Some(ref child) = self.{} {{
+            element = element.with_child(child.to_element(prefix));
+        }}", name),
+                    Some(1) => writeln!(code, "        element = element.with_child(self.{}.to_element(prefix));", name),
+                    _ => writeln!(code, "        for child in &self.{} {{
+            element = element.with_child(child.to_element(prefix));
+        }}", name),
+                };
+                result.unwrap();
+            }
+        }
+    }
+
+    writeln!(code, "        element
+    }}
+}}").unwrap();
+}
+
+fn generate_from_element(code: &mut String, s: &Struct) {
+    let has_children = s.fields.iter().any(|field| match *field {
+        Field::Child { .. } => true,
+        _ => false,
+    });
+
+    writeln!(code, "
+impl FromElement for {name} {{
+    /// Reads the `{xml_name}` element. Missing required attributes
+    /// are read as empty strings, unknown child elements are skipped.
+    fn from_element(element: Element) -> Result<{name}> {{
+        {binding} value = {name} {{",
+        name = s.name,
+        xml_name = s.xml_name,
+        binding = if has_children { "let mut" } else { "let" }).unwrap();
+
+    for field in &s.fields {
+        if let Field::Attribute { ref xml_name, scalar, optional } = *field {
+            let name = field_name(xml_name);
+            let result = match (scalar, optional) {
+                (Scalar::String, false) => writeln!(code, "            {}: element.get_attr(\"{}\"),", name, xml_name),
+                (Scalar::String, true) => writeln!(code, "            {}: element.attributes.get(\"{}\").cloned(),", name, xml_name),
+                (_, false) => writeln!(code, "            {}: element.get_attr(\"{}\").parse()?,", name, xml_name),
+                (_, true) => writeln!(code, "            {}: match element.attributes.get(\"{}\") {{
+                Some(value) => Some(value.parse()?),
+                None => None,
+            }},", name, xml_name),
+            };
+            result.unwrap();
+        }
+    }
+
+    if !has_children {
+        writeln!(code, "        }};
+
+        Ok(value)
+    }}
+}}").unwrap();
+        return;
+    }
+
+    writeln!(code, "            ..{}::default()
+        }};
+
+        for child in element.children {{
+            match child.name.as_str() {{", s.name).unwrap();
+
+    for field in &s.fields {
+        if let Field::Child { ref xml_name, ref struct_name, max_occurs, min_occurs } = *field {
+            let name = field_name(xml_name);
+            let result = match max_occurs {
+                Some(1) if min_occurs == 0 => writeln!(code, "                \"{}\" => value.{} = Some({}::from_element(child)?),", xml_name, name, struct_name),
+                Some(1) => writeln!(code, "                \"{}\" => value.{} = {}::from_element(child)?,", xml_name, name, struct_name),
+                _ => writeln!(code, "                \"{}\" => value.{}.push({}::from_element(child)?),", xml_name, name, struct_name),
+            };
+            result.unwrap();
+        }
+    }
+
+    writeln!(code, "                _ => {{}}
+            }}
+        }}
+
+        Ok(value)
+    }}
+}}").unwrap();
+}
+
+fn children<'a>(element: &'a Element, name: &str) -> Vec<&'a Element> {
+    element.children.iter().filter(|child| child.name == name).collect()
+}
+
+fn attr(element: &Element, name: &str) -> String {
+    element
+        .attributes
+        .get(name)
+        .cloned()
+        .unwrap_or_else(|| panic!("attribute {} of {} is missing", name, element.name))
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn scalar(type_name: &str, simple_types: &HashMap<String, Scalar>) -> Scalar {
+    match local_name(type_name) {
+        "string" | "date" | "dateTime" | "token" | "normalizedString" => Scalar::String,
+        "int" | "integer" | "short" | "byte" => Scalar::Int,
+        "long" => Scalar::Long,
+        name => *simple_types
+            .get(name)
+            .unwrap_or_else(|| panic!("unsupported type {}", type_name)),
+    }
+}
+
+fn rust_type(scalar: Scalar) -> &'static str {
+    match scalar {
+        Scalar::String => "String",
+        Scalar::Int => "i32",
+        Scalar::Long => "i64",
+    }
+}
+
+/// `DTActFL` -> `dt_act_fl`
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut result = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).map_or(false, |next| next.is_lowercase());
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_lower) {
+                result.push('_');
+            }
+        }
+        result.extend(c.to_lowercase());
+    }
+    result
+}
+
+/// `NP` -> `Np`, `NdsRequest2` -> `NdsRequest2`
+fn camel_case(name: &str) -> String {
+    snake_case(name)
+        .split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn field_name(name: &str) -> String {
+    let name = snake_case(name);
+    match name.as_str() {
+        "type" | "ref" | "match" | "mod" | "use" | "where" | "in" | "as" => format!("{}_", name),
+        _ => name,
+    }
+}
@@ -0,0 +1,246 @@
+//! Checks of the decisions to suspend operations on the bank accounts
+//! of the taxpayer through the service
+//! [https://service.nalog.ru/bi.do](https://service.nalog.ru/bi.do)
+//!
+//! Available with the `bi` feature. The service is queried by INN of the
+//! taxpayer and BIK of the bank and returns the active decisions of the
+//! tax authorities, if any.
+
+use chrono::prelude::*;
+
+use super::{error, Result};
+use service::{self, parse_date, Call, Service, ServiceConfig};
+
+/// The default connection point of the service
+pub const BI_URL: &'static str = "https://service.nalog.ru/bi2-proc.json";
+
+const METHOD: &'static str = "bi";
+
+/// Request of the decisions on the accounts of the taxpayer in the bank
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockingRequest {
+    pub inn: String,
+    /// BIK of the bank, 9 digits
+    pub bik: String,
+}
+
+/// Active decision to suspend operations on the accounts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockingDecision {
+    /// Number of the decision
+    pub number: String,
+    /// Date of the decision
+    pub date: Option<NaiveDate>,
+    /// Code of the tax office that made the decision
+    pub tax_office: String,
+    /// BIK of the bank the decision was sent to
+    pub bik: String,
+    /// Date and time the bank received the decision
+    pub received_at: Option<NaiveDateTime>,
+}
+
+/// Answer of the service for the taxpayer and the bank
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockingResult {
+    pub inn: String,
+    pub bik: String,
+    /// Date and time of the data the answer is based on
+    pub checked_at: Option<NaiveDateTime>,
+    /// The active decisions, empty if the accounts are not blocked
+    pub decisions: Vec<BlockingDecision>,
+}
+
+/// Answer of the service, as sent
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Reply {
+    #[serde(rename = "datePRS")]
+    date: Option<String>,
+    rows: Vec<Row>,
+}
+
+/// Decision, as sent by the service
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Row {
+    #[serde(rename = "NOMER")]
+    number: String,
+    #[serde(rename = "DATA")]
+    date: Option<String>,
+    #[serde(rename = "IFNS")]
+    tax_office: String,
+    #[serde(rename = "BIK")]
+    bik: String,
+    #[serde(rename = "DATABI")]
+    received_at: Option<String>,
+}
+
+/// Client of the service of the bank account blocking
+#[derive(Debug)]
+pub struct BiClient {
+    service: Service,
+}
+
+/// Builder of the `BiClient`
+#[derive(Debug, Clone)]
+pub struct BiClientBuilder {
+    config: ServiceConfig,
+}
+
+impl BlockingRequest {
+    pub fn new<I, B>(inn: I, bik: B) -> BlockingRequest
+    where
+        I: Into<String>,
+        B: Into<String>,
+    {
+        BlockingRequest {
+            inn: inn.into(),
+            bik: bik.into(),
+        }
+    }
+
+    /// Checks the INN and the BIK locally.
+    fn validate(&self) -> Result<()> {
+        service::check_inn(&self.inn, error::Error::BiError)?;
+
+        if self.bik.len() != 9 || !self.bik.chars().all(|c| c.is_ascii_digit()) {
+            return Err(error::Error::BiError(format!("Invalid BIK {}", self.bik)));
+        }
+
+        Ok(())
+    }
+}
+
+impl BlockingResult {
+    /// Returns `true` if operations on the accounts are suspended.
+    pub fn is_blocked(&self) -> bool {
+        !self.decisions.is_empty()
+    }
+}
+
+impl Default for BiClientBuilder {
+    fn default() -> BiClientBuilder {
+        BiClientBuilder {
+            config: ServiceConfig::new(BI_URL),
+        }
+    }
+}
+
+service_client!(BiClient, BiClientBuilder);
+
+impl BiClientBuilder {
+    /// Create the client.
+    pub fn build(self) -> Result<BiClient> {
+        Ok(BiClient {
+            service: self.config.connect()?,
+        })
+    }
+}
+
+impl BiClient {
+    /// The active decisions on the accounts of the taxpayer in the bank.
+    pub fn check_blocking(&self, request: &BlockingRequest) -> Result<BlockingResult> {
+        let rsp = request.validate().and_then(|_| self.call(request));
+        if let Err(ref e) = rsp {
+            self.service.metrics.error(METHOD, e);
+        }
+
+        rsp
+    }
+
+    fn call(&self, request: &BlockingRequest) -> Result<BlockingResult> {
+        let form = [
+            ("requestType", "FINDPRS"),
+            ("innPRS", request.inn.as_str()),
+            ("bikPRS", request.bik.as_str()),
+        ];
+
+        let reply: Reply = self.service.call_json(METHOD, "", Call::Form(&form), |body| {
+            service::reported_error(body).map(error::Error::BiError)
+        })?;
+
+        Ok(BlockingResult {
+            inn: request.inn.clone(),
+            bik: request.bik.clone(),
+            checked_at: reply.date.as_ref().and_then(|date| parse_date_time(date)),
+            decisions: reply
+                .rows
+                .into_iter()
+                .map(|row| BlockingDecision {
+                    number: row.number,
+                    date: row.date.as_ref().and_then(|date| parse_date(date)),
+                    tax_office: row.tax_office,
+                    bik: row.bik,
+                    received_at: row.received_at
+                        .as_ref()
+                        .and_then(|date| parse_date_time(date)),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Parses `dd.mm.yyyy HH:MM:SS` or `yyyy-mm-dd HH:MM:SS`,
+/// a date without the time is the start of the day.
+fn parse_date_time(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    NaiveDateTime::parse_from_str(value, "%d.%m.%Y %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+        .ok()
+        .or_else(|| parse_date(value).map(|date| date.and_hms(0, 0, 0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error;
+    use mock::MockBiServer;
+
+    fn decision() -> BlockingDecision {
+        BlockingDecision {
+            number: "12345".into(),
+            date: Some(NaiveDate::from_ymd(2018, 3, 15)),
+            tax_office: "7736".into(),
+            bik: "044525225".into(),
+            received_at: Some(NaiveDate::from_ymd(2018, 3, 16).and_hms(10, 30, 0)),
+        }
+    }
+
+    #[test]
+    fn returns_the_decisions() {
+        let server = MockBiServer::start().unwrap();
+        server.add_decision("6648185610", decision());
+        let client = server.client().unwrap();
+
+        let result = client
+            .check_blocking(&BlockingRequest::new("6648185610", "044525225"))
+            .unwrap();
+        assert!(result.is_blocked());
+        assert_eq!(result.decisions, vec![decision()]);
+        assert!(result.checked_at.is_some());
+
+        let result = client
+            .check_blocking(&BlockingRequest::new("7707083893", String::from("044525225")))
+            .unwrap();
+        assert!(!result.is_blocked());
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].body.contains("innPRS=6648185610"));
+    }
+
+    #[test]
+    fn reports_service_errors() {
+        let server = MockBiServer::start().unwrap();
+        server.set_captcha_required(true);
+
+        match server
+            .client()
+            .unwrap()
+            .check_blocking(&BlockingRequest::new("6648185610", "044525225"))
+        {
+            Err(Error::BiError(ref msg)) => assert!(!msg.is_empty()),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
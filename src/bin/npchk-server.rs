@@ -0,0 +1,214 @@
+//! JSON gateway in front of the service [http://npchk.nalog.ru/](http://npchk.nalog.ru/)
+
+extern crate futures;
+extern crate futures_cpupool;
+extern crate hyper;
+extern crate npchk;
+
+use std::env;
+use std::net::SocketAddr;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{future, Future, Stream};
+use futures_cpupool::CpuPool;
+use hyper::StatusCode;
+use hyper::header::{ContentLength, ContentType};
+use hyper::server::{Http, Request, Response, Service};
+
+use npchk::gateway::{Gateway, GatewayResponse, DEFAULT_CACHE_CAPACITY};
+use npchk::metrics::PrometheusMetrics;
+use npchk::{NpchkClient, RetryPolicy};
+
+const USAGE: &'static str = "\
+Usage: npchk-server [OPTIONS]
+
+Serves the checks of contractors through the service http://npchk.nalog.ru/
+as JSON:
+
+    POST /check                         {\"partners\": [{\"inn\", \"kpp\", \"date\"}]}
+    GET  /partner/{inn}/{kpp}?date=     one partner
+    GET  /metrics                       metrics in the Prometheus text format
+
+Options:
+    -l, --listen ADDR    address to listen on [default: 127.0.0.1:8080]
+        --url URL        connection point of the service
+        --ttl SECONDS    time to keep the results in the cache [default: 86400]
+        --cache-size N   largest number of the results in the cache [default: 100000]
+        --timeout SECONDS
+                         timeout of a request to the service [default: 60]
+        --threads N      number of the threads calling the service [default: 8]
+        --max-body BYTES largest accepted body of a request [default: 1048576]
+    -h, --help           print this help";
+
+#[derive(Debug)]
+struct Args {
+    listen: SocketAddr,
+    url: Option<String>,
+    ttl: u64,
+    cache_size: usize,
+    timeout: u64,
+    threads: usize,
+    max_body: u64,
+}
+
+struct GatewayService {
+    gateway: Arc<Gateway>,
+    pool: CpuPool,
+    max_body: u64,
+}
+
+/// Failure of reading the body of the request
+enum BodyError {
+    TooLarge,
+    Http(hyper::Error),
+}
+
+impl Service for GatewayService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, request: Request) -> Self::Future {
+        let gateway = self.gateway.clone();
+        let pool = self.pool.clone();
+        let method = request.method().to_string();
+        let path = request.path().to_string();
+        let query = request.query().map(|query| query.to_string());
+        let max_body = self.max_body;
+
+        let declared = request.headers().get::<ContentLength>().map(|length| length.0);
+        if declared.map_or(false, |length| length > max_body) {
+            return Box::new(future::ok(response(GatewayResponse::body_too_large(max_body))));
+        }
+
+        let body = request
+            .body()
+            .map_err(BodyError::Http)
+            .fold(vec![], move |mut body, chunk| {
+                if (body.len() + chunk.len()) as u64 > max_body {
+                    return Err(BodyError::TooLarge);
+                }
+                body.extend_from_slice(&chunk);
+                Ok(body)
+            });
+
+        Box::new(body.then(move |body| -> Self::Future {
+            match body {
+                Ok(body) => Box::new(
+                    pool.spawn_fn(move || {
+                        let query = query.as_ref().map(|query| query.as_str());
+                        Ok::<_, hyper::Error>(gateway.handle(&method, &path, query, &body))
+                    }).map(response),
+                ),
+                Err(BodyError::TooLarge) => {
+                    Box::new(future::ok(response(GatewayResponse::body_too_large(max_body))))
+                }
+                Err(BodyError::Http(e)) => Box::new(future::err(e)),
+            }
+        }))
+    }
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(msg) = run(args) {
+        eprintln!("npchk-server: {}", msg);
+        process::exit(1);
+    }
+}
+
+fn parse_args(argv: Vec<String>) -> Result<Args, String> {
+    let mut args = Args {
+        listen: "127.0.0.1:8080".parse().unwrap(),
+        url: None,
+        ttl: 86_400,
+        cache_size: DEFAULT_CACHE_CAPACITY,
+        timeout: 60,
+        threads: 8,
+        max_body: 1024 * 1024,
+    };
+
+    let mut argv = argv.into_iter();
+    while let Some(arg) = argv.next() {
+        let mut value = |name: &str| argv.next().ok_or(format!("Missing value of {}", name));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "-l" | "--listen" => {
+                let listen = value("--listen")?;
+                args.listen = listen
+                    .parse()
+                    .map_err(|e| format!("Invalid address {}: {}", listen, e))?;
+            }
+            "--url" => args.url = Some(value("--url")?),
+            "--ttl" => args.ttl = parse_number(&value("--ttl")?)?,
+            "--cache-size" => args.cache_size = parse_number(&value("--cache-size")?)? as usize,
+            "--timeout" => args.timeout = parse_number(&value("--timeout")?)?,
+            "--threads" => args.threads = parse_number(&value("--threads")?)? as usize,
+            "--max-body" => args.max_body = parse_number(&value("--max-body")?)?,
+            other => return Err(format!("Unknown option {}", other)),
+        }
+    }
+
+    Ok(args)
+}
+
+fn parse_number(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|e| format!("Invalid number {}: {}", value, e))
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let metrics = Arc::new(PrometheusMetrics::new());
+    let mut builder = NpchkClient::builder()
+        .timeout(Duration::from_secs(args.timeout))
+        .retry(RetryPolicy::default())
+        .metrics(metrics.clone());
+    if let Some(url) = args.url {
+        builder = builder.url(url);
+    }
+    let client = builder.build().map_err(|e| e.to_string())?;
+
+    let gateway = Gateway::new(client, Duration::from_secs(args.ttl))
+        .with_cache_capacity(args.cache_size)
+        .with_metrics(metrics);
+    let gateway = Arc::new(gateway);
+    let pool = CpuPool::new(args.threads.max(1));
+    let max_body = args.max_body;
+
+    let server = Http::new()
+        .bind(&args.listen, move || {
+            Ok(GatewayService {
+                gateway: gateway.clone(),
+                pool: pool.clone(),
+                max_body: max_body,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+
+    eprintln!("npchk-server: listening on http://{}", args.listen);
+    server.run().map_err(|e| e.to_string())
+}
+
+fn response(rsp: GatewayResponse) -> Response {
+    let status = StatusCode::try_from(rsp.status).unwrap_or(StatusCode::InternalServerError);
+    let content_type = rsp.content_type.parse().unwrap();
+
+    Response::new()
+        .with_status(status)
+        .with_header(ContentType(content_type))
+        .with_body(rsp.body)
+}
@@ -0,0 +1,580 @@
+//! Command-line client of the service [http://npchk.nalog.ru/](http://npchk.nalog.ru/)
+
+extern crate chrono;
+extern crate npchk;
+extern crate serde_json;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::process;
+
+use chrono::prelude::*;
+use npchk::*;
+
+/// Every partner has a valid status
+const EXIT_ACTIVE: i32 = 0;
+/// Some partners do not have a valid status
+const EXIT_INACTIVE: i32 = 1;
+/// Invalid arguments or input
+const EXIT_USAGE: i32 = 2;
+/// The service failed to check some partners
+const EXIT_FAILURE: i32 = 3;
+
+const USAGE: &'static str = "\
+Usage: npchk [OPTIONS] [INN[:KPP[:DATE]]...]
+       npchk watch [WATCH OPTIONS] FILE
+
+Checks the status of contractors through the service http://npchk.nalog.ru/
+
+Partners are taken from the arguments, from the CSV file or, if neither
+is given, from the standard input, one INN, KPP and date per line
+separated by colons, semicolons, commas or spaces. The KPP may be left
+empty, as in INN::DATE. The date is dd.mm.yyyy, today by default.
+
+Options:
+    -f, --file FILE      read partners from the CSV file FILE with the INN,
+                         KPP and date columns
+    -d, --delimiter CHAR delimiter of the CSV file [default: ;]
+    -e, --encoding ENC   encoding of the CSV file: utf-8 or windows-1251
+                         [default: utf-8]
+        --headers        the first row of the CSV file holds the names
+                         of the columns
+    -o, --format FORMAT  output format: table, json or csv [default: table]
+        --skip-invalid   do not send partners failing the local validation
+    -h, --help           print this help
+
+Watch options:
+    -s, --schedule CRON  schedule of the checks, a cron expression with
+                         seconds [default: 0 0 9 * * *]
+        --state FILE     file keeping the results and the schedule
+                         between restarts [default: FILE.state.json]
+        --once           check the watchlist once and exit
+        --webhook URL    also send the changes to the webhook
+        --webhook-secret-file FILE
+                         sign the webhook deliveries with the secret kept
+                         in FILE, by default with the secret in the
+                         NPCHK_WEBHOOK_SECRET environment variable
+        --dead-letter FILE
+                         keep the failed webhook deliveries in FILE
+    -o, --format FORMAT  format of the changes: table or json [default: table]
+
+The watchlist FILE holds one INN and optional KPP per line. Every run
+prints the partners whose state changed since the previous run.
+
+Exit codes:
+    0  every partner has a valid status
+    1  some partners do not have a valid status
+    2  invalid arguments or input
+    3  the service failed to check some partners or could not be reached";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(Debug)]
+struct WatchArgs {
+    file: String,
+    schedule: String,
+    state: Option<String>,
+    once: bool,
+    format: Format,
+    webhooks: Vec<String>,
+    webhook_secret: Option<String>,
+    dead_letter: Option<String>,
+}
+
+#[derive(Debug)]
+struct Args {
+    file: Option<String>,
+    csv: csv_io::CsvOptions,
+    format: Format,
+    skip_invalid: bool,
+    partners: Vec<String>,
+}
+
+fn main() {
+    let argv: Vec<String> = env::args().skip(1).collect();
+    if argv.first().map_or(false, |arg| arg == "watch") {
+        watch_main(argv.into_iter().skip(1).collect());
+    }
+
+    let args = match parse_args(argv) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    process::exit(match run(&args) {
+        Ok(code) => code,
+        Err(msg) => {
+            eprintln!("npchk: {}", msg);
+            EXIT_USAGE
+        }
+    });
+}
+
+fn parse_args(argv: Vec<String>) -> Result<Args, String> {
+    let mut args = Args {
+        file: None,
+        csv: csv_io::CsvOptions {
+            has_headers: false,
+            encoding: csv_io::Encoding::Utf8,
+            ..csv_io::CsvOptions::default()
+        },
+        format: Format::Table,
+        skip_invalid: false,
+        partners: vec![],
+    };
+
+    let mut argv = argv.into_iter();
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(EXIT_ACTIVE);
+            }
+            "-f" | "--file" => {
+                args.file = Some(argv.next().ok_or("Missing value of --file")?);
+            }
+            "-d" | "--delimiter" => {
+                let delimiter = argv.next().ok_or("Missing value of --delimiter")?;
+                if delimiter.len() != 1 || !delimiter.is_ascii() {
+                    return Err(format!("Invalid delimiter {:?}", delimiter));
+                }
+                args.csv.delimiter = delimiter.as_bytes()[0];
+            }
+            "-e" | "--encoding" => {
+                args.csv.encoding = match argv.next().as_ref().map(|s| s.as_str()) {
+                    Some("utf-8") | Some("utf8") => csv_io::Encoding::Utf8,
+                    Some("windows-1251") | Some("cp1251") => csv_io::Encoding::Windows1251,
+                    Some(other) => return Err(format!("Unknown encoding {}", other)),
+                    None => return Err("Missing value of --encoding".into()),
+                }
+            }
+            "--headers" => args.csv.has_headers = true,
+            "-o" | "--format" => {
+                args.format = match argv.next().as_ref().map(|s| s.as_str()) {
+                    Some("table") => Format::Table,
+                    Some("json") => Format::Json,
+                    Some("csv") => Format::Csv,
+                    Some(other) => return Err(format!("Unknown format {}", other)),
+                    None => return Err("Missing value of --format".into()),
+                }
+            }
+            "--skip-invalid" => args.skip_invalid = true,
+            other if other.starts_with('-') && other.len() > 1 => {
+                return Err(format!("Unknown option {}", other))
+            }
+            _ => args.partners.push(arg),
+        }
+    }
+
+    Ok(args)
+}
+
+fn watch_main(argv: Vec<String>) -> ! {
+    let args = match parse_watch_args(argv) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    process::exit(match run_watch(&args) {
+        Ok(()) => EXIT_ACTIVE,
+        Err(msg) => {
+            eprintln!("npchk: {}", msg);
+            EXIT_FAILURE
+        }
+    });
+}
+
+fn parse_watch_args(argv: Vec<String>) -> Result<WatchArgs, String> {
+    let mut file = None;
+    let mut args = WatchArgs {
+        file: String::new(),
+        schedule: "0 0 9 * * *".into(),
+        state: None,
+        once: false,
+        format: Format::Table,
+        webhooks: vec![],
+        webhook_secret: None,
+        dead_letter: None,
+    };
+
+    let mut argv = argv.into_iter();
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(EXIT_ACTIVE);
+            }
+            "-s" | "--schedule" => {
+                args.schedule = argv.next().ok_or("Missing value of --schedule")?;
+            }
+            "--state" => {
+                args.state = Some(argv.next().ok_or("Missing value of --state")?);
+            }
+            "--once" => args.once = true,
+            "--webhook" => {
+                args.webhooks.push(argv.next().ok_or("Missing value of --webhook")?);
+            }
+            "--webhook-secret-file" => {
+                let path = argv.next().ok_or("Missing value of --webhook-secret-file")?;
+                let secret = read_secret(&path).map_err(|e| format!("{}: {}", path, e))?;
+                args.webhook_secret = Some(secret);
+            }
+            "--dead-letter" => {
+                let dead_letter = argv.next().ok_or("Missing value of --dead-letter")?;
+                args.dead_letter = Some(dead_letter);
+            }
+            "-o" | "--format" => {
+                args.format = match argv.next().as_ref().map(|s| s.as_str()) {
+                    Some("table") => Format::Table,
+                    Some("json") => Format::Json,
+                    Some(other) => return Err(format!("Unknown format {}", other)),
+                    None => return Err("Missing value of --format".into()),
+                }
+            }
+            other if other.starts_with('-') && other.len() > 1 => {
+                return Err(format!("Unknown option {}", other))
+            }
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+
+    args.file = file.ok_or("Missing watchlist file")?;
+    if args.webhook_secret.is_none() {
+        args.webhook_secret = match env::var("NPCHK_WEBHOOK_SECRET") {
+            Ok(ref secret) if !secret.is_empty() => Some(secret.clone()),
+            _ => None,
+        };
+    }
+    Ok(args)
+}
+
+/// Reads the secret from the file, without the trailing line break.
+fn read_secret(path: &str) -> io::Result<String> {
+    let mut secret = String::new();
+    File::open(path)?.read_to_string(&mut secret)?;
+
+    let secret = secret.trim_right_matches(|c| c == '\r' || c == '\n');
+    if secret.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "The secret is empty"));
+    }
+    Ok(secret.to_string())
+}
+
+fn run_watch(args: &WatchArgs) -> Result<(), String> {
+    let watchlist = watch::load_watchlist(&args.file).map_err(|e| format!("{}: {}", args.file, e))?;
+    if watchlist.is_empty() {
+        return Err("No partners to watch".into());
+    }
+
+    let state = match args.state {
+        Some(ref state) => state.clone(),
+        None => format!("{}.state.json", args.file),
+    };
+    let client = NpchkClient::builder()
+        .retry(RetryPolicy::default())
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut watcher = watch::Watcher::new(client, watchlist, &args.schedule, state)
+        .map_err(|e| e.to_string())?;
+
+    let mut notifier = webhook::Notifier::builder();
+    for url in &args.webhooks {
+        notifier = notifier.webhook(webhook::Webhook {
+            url: url.clone(),
+            secret: args.webhook_secret.clone(),
+        });
+    }
+    if let Some(ref dead_letter) = args.dead_letter {
+        notifier = notifier.dead_letter(dead_letter.as_str());
+    }
+    let notifier = notifier.build().map_err(|e| e.to_string())?;
+    notifier.redeliver().map_err(|e| e.to_string())?;
+
+    let format = args.format;
+    let sink = move |events: &[history::ChangeEvent], _: &NdsResponse| {
+        if let Err(e) = print_events(events, format) {
+            eprintln!("npchk: {}", e);
+        }
+        if let Err(e) = notifier.notify(events) {
+            eprintln!("npchk: {}", e);
+        }
+    };
+
+    if args.once {
+        let events = watcher.run_once().map_err(|e| e.to_string())?;
+        if let Some(ref rsp) = watcher.state().last_response {
+            watch::EventSink::on_changes(&sink, &events, rsp);
+        }
+        return Ok(());
+    }
+
+    watcher.run(&sink).map_err(|e| e.to_string())
+}
+
+fn print_events(events: &[history::ChangeEvent], format: Format) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let now = Local::now().format("%d.%m.%Y %H:%M:%S");
+
+    for event in events {
+        match format {
+            Format::Json => {
+                serde_json::to_writer(&mut out, event)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                writeln!(out)?;
+            }
+            _ => writeln!(out, "{}  {}", now, event)?,
+        }
+    }
+
+    out.flush()
+}
+
+fn run(args: &Args) -> Result<i32, String> {
+    let mut partners: Vec<Partner<'static>> = vec![];
+    for arg in &args.partners {
+        partners.push(parse_partner(arg)?);
+    }
+
+    if let Some(ref path) = args.file {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let import =
+            csv_io::read_partners(file, &args.csv).map_err(|e| format!("{}: {}", path, e))?;
+        if let Some(row) = import.errors.first() {
+            return Err(format!("{}: line {}: {}", path, row.line, row.message));
+        }
+        partners.extend(import.partners);
+    } else if args.partners.is_empty() {
+        let stdin = io::stdin();
+        partners.extend(read_partners(stdin.lock())?);
+    }
+
+    if partners.is_empty() {
+        return Err("No partners to check".into());
+    }
+
+    let options = CheckOptions {
+        skip_invalid: args.skip_invalid,
+    };
+    let batch = match check_fns_batched(partners, &options) {
+        Ok(batch) => batch,
+        Err(e) => {
+            eprintln!("npchk: {}", e);
+            return Ok(EXIT_FAILURE);
+        }
+    };
+
+    for failure in &batch.failures {
+        eprintln!(
+            "npchk: chunk {} of {} partners failed: {}",
+            failure.index,
+            failure.partners.len(),
+            failure.error
+        );
+    }
+
+    if let Some(ref rsp) = batch.response {
+        print_response(rsp, args.format).map_err(|e| e.to_string())?;
+    }
+
+    if !batch.is_complete() {
+        return Ok(EXIT_FAILURE);
+    }
+
+    let all_active = batch.response.map_or(false, |rsp| {
+        rsp.partners
+            .iter()
+            .all(|p| p.state.map_or(false, |state| state.is_active()))
+    });
+
+    Ok(if all_active { EXIT_ACTIVE } else { EXIT_INACTIVE })
+}
+
+/// Parses `INN[:KPP[:DATE]]`, the parts may also be separated
+/// by semicolons, commas or spaces.
+///
+/// The parts are taken by position, so an empty KPP is kept
+/// as in `INN::DATE` or `INN;;DATE`.
+fn parse_partner(value: &str) -> Result<Partner<'static>, String> {
+    let value = value.trim();
+    let parts: Vec<&str> = match [':', ';', ','].iter().find(|&&c| value.contains(c)) {
+        Some(&separator) => value.split(separator).map(|s| s.trim()).collect(),
+        None => value.split_whitespace().collect(),
+    };
+    if parts.len() > 3 {
+        return Err(format!("Unexpected parts in {:?}", value));
+    }
+
+    let inn = match parts.get(0) {
+        Some(inn) if !inn.is_empty() => inn.to_string(),
+        _ => return Err(format!("Missing INN in {:?}", value)),
+    };
+    let kpp = parts.get(1).map_or(String::new(), |kpp| kpp.to_string());
+    let dt = match parts.get(2) {
+        Some(date) if !date.is_empty() => {
+            let date = NaiveDate::parse_from_str(date, "%d.%m.%Y")
+                .map_err(|e| format!("Invalid date {:?}: {}", date, e))?;
+            Utc.from_utc_date(&date).and_hms(0, 0, 0)
+        }
+        _ => Utc::today().and_hms(0, 0, 0),
+    };
+
+    Ok(Partner::new(inn, kpp, dt))
+}
+
+fn read_partners<R: BufRead>(reader: R) -> Result<Vec<Partner<'static>>, String> {
+    let mut partners = vec![];
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        partners.push(parse_partner(line).map_err(|e| format!("line {}: {}", number + 1, e))?);
+    }
+
+    Ok(partners)
+}
+
+fn print_response(rsp: &NdsResponse, format: Format) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, rsp)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            writeln!(out)
+        }
+        Format::Csv => {
+            let options = csv_io::CsvOptions {
+                encoding: csv_io::Encoding::Utf8,
+                ..csv_io::CsvOptions::default()
+            };
+            csv_io::write_response(out, rsp, &options)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+        }
+        Format::Table => {
+            writeln!(
+                out,
+                "Data actuality: individual entrepreneurs {}, legal entities {}\n",
+                rsp.dtact_fl.format("%d.%m.%Y"),
+                rsp.dtact_ul.format("%d.%m.%Y")
+            )?;
+            writeln!(
+                out,
+                "{:<12}  {:<9}  {:<10}  {:>5}  {}",
+                "INN", "KPP", "DATE", "STATE", "DESCRIPTION"
+            )?;
+            for p in &rsp.partners {
+                let (code, description) = state_columns(p);
+                writeln!(
+                    out,
+                    "{:<12}  {:<9}  {:<10}  {:>5}  {}",
+                    p.inn,
+                    p.kpp,
+                    p.dt.format("%d.%m.%Y"),
+                    code,
+                    description
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn state_columns(p: &Partner) -> (String, &'static str) {
+    match p.state {
+        Some(state) => (state.code().to_string(), state.description()),
+        None => (String::new(), ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32, month: u32, year: i32) -> DateTime<Utc> {
+        Utc.ymd(year, month, day).and_hms(0, 0, 0)
+    }
+
+    #[test]
+    fn partner_parts_are_taken_by_position() {
+        for value in &["7707083893:773601001:01.02.2018", "7707083893; 773601001; 01.02.2018",
+                       "7707083893,773601001,01.02.2018", " 7707083893 773601001 01.02.2018 "]
+        {
+            let p = parse_partner(value).unwrap();
+            assert_eq!(p.inn, "7707083893", "{}", value);
+            assert_eq!(p.kpp, "773601001", "{}", value);
+            assert_eq!(p.dt, date(1, 2, 2018), "{}", value);
+        }
+    }
+
+    #[test]
+    fn missing_kpp_is_empty() {
+        let today = Utc::today().and_hms(0, 0, 0);
+
+        let p = parse_partner("500100732259").unwrap();
+        assert_eq!((&*p.kpp, p.dt), ("", today));
+        let p = parse_partner("500100732259::01.02.2018").unwrap();
+        assert_eq!((&*p.kpp, p.dt), ("", date(1, 2, 2018)));
+        let p = parse_partner("500100732259;;").unwrap();
+        assert_eq!((&*p.kpp, p.dt), ("", today));
+    }
+
+    #[test]
+    fn empty_fields_are_rejected() {
+        for value in &["", "   ", ":773601001", ";773601001;01.02.2018", ","] {
+            let e = parse_partner(value).unwrap_err();
+            assert!(e.contains("Missing INN"), "{:?}: {}", value, e);
+        }
+
+        let e = parse_partner("7707083893:773601001:01.02.2018:1").unwrap_err();
+        assert!(e.contains("Unexpected parts"), "{}", e);
+    }
+
+    #[test]
+    fn bad_dates_are_rejected() {
+        for value in &["7707083893:773601001:31.02.2018", "7707083893:773601001:2018-02-01",
+                       "7707083893::01/02/2018"]
+        {
+            let e = parse_partner(value).unwrap_err();
+            assert!(e.contains("Invalid date"), "{:?}: {}", value, e);
+        }
+    }
+
+    #[test]
+    fn partners_are_read_by_lines() {
+        let input = "# INN:KPP:DATE\n7707083893:773601001:01.02.2018\n\n  500100732259  \n";
+
+        let partners = read_partners(input.as_bytes()).unwrap();
+
+        assert_eq!(partners.len(), 2);
+        assert_eq!(partners[0].kpp, "773601001");
+        assert_eq!(partners[1].inn, "500100732259");
+        assert_eq!(partners[1].kpp, "");
+    }
+
+    #[test]
+    fn bad_line_is_reported_by_number() {
+        let input = "7707083893:773601001\n\n7707083893:773601001:32.01.2018\n";
+
+        let e = read_partners(input.as_bytes()).unwrap_err();
+
+        assert!(e.starts_with("line 3: Invalid date"), "{}", e);
+    }
+}
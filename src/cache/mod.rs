@@ -0,0 +1,522 @@
+//! Cache of the check results in front of the service.
+//!
+//! Results are keyed by INN, KPP and date of the check. The answer of the
+//! service can not change until FNS refreshes its data, so an entry stays
+//! valid until the service reports newer data actuality dates than the ones
+//! the entry was checked with, or until its time to live expires.
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteCache;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use chrono;
+
+use super::{CheckOptions, NpchkClient, Partner, PartnerState, Result, MAX_RECORDS};
+use client::answered_locally;
+use correlate::correlate;
+
+/// Key of the cached result
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub inn: String,
+    pub kpp: String,
+    pub date: NaiveDate,
+}
+
+/// Cached answer of the service for one partner
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    /// The state code returned by the service
+    pub state: i32,
+    /// Data actuality date for individual entrepreneurs at the time of the check
+    pub dtact_fl: DateTime<Utc>,
+    /// Data actuality date for legal entities at the time of the check
+    pub dtact_ul: DateTime<Utc>,
+    /// Time of the check
+    pub checked_at: DateTime<Utc>,
+}
+
+/// Storage of the cached results
+pub trait Cache {
+    /// Returns the entry stored for the key.
+    fn get(&self, key: &CacheKey) -> Result<Option<CacheEntry>>;
+
+    /// Stores the entry, replacing the previous one.
+    fn put(&self, key: &CacheKey, entry: &CacheEntry) -> Result<()>;
+
+    /// The newest data actuality dates reported by the service,
+    /// individual entrepreneurs first.
+    fn actuality(&self) -> Result<Option<(DateTime<Utc>, DateTime<Utc>)>>;
+
+    /// Stores the newest data actuality dates reported by the service.
+    fn set_actuality(&self, dtact_fl: DateTime<Utc>, dtact_ul: DateTime<Utc>) -> Result<()>;
+}
+
+/// The partner with the flag showing where the result came from
+#[derive(Debug, Clone)]
+pub struct CachedPartner<'a> {
+    pub partner: Partner<'a>,
+    /// The result was taken from the cache without calling the service
+    pub from_cache: bool,
+}
+
+/// Check results, partially taken from the cache
+#[derive(Debug, Clone)]
+pub struct CachedResponse<'a> {
+    /// The earliest data actuality date for individual entrepreneurs
+    /// among the results.
+    pub dtact_fl: DateTime<Utc>,
+    /// The earliest data actuality date for legal entities among the results.
+    pub dtact_ul: DateTime<Utc>,
+    /// Partners in the order of the request
+    pub partners: Vec<CachedPartner<'a>>,
+}
+
+/// Cache kept in memory for the lifetime of the process
+///
+/// Unbounded unless created `with_capacity`.
+#[derive(Debug, Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+    actuality: Mutex<Option<(DateTime<Utc>, DateTime<Utc>)>>,
+    capacity: Option<usize>,
+}
+
+impl CacheKey {
+    pub fn from_partner(p: &Partner) -> CacheKey {
+        CacheKey {
+            inn: p.inn.to_string(),
+            kpp: p.kpp.to_string(),
+            date: p.dt.date().naive_utc(),
+        }
+    }
+}
+
+impl CacheEntry {
+    /// Returns `true` if the entry is older than the time to live
+    /// or was checked with older data than the service has now.
+    pub fn is_stale(
+        &self,
+        ttl: Duration,
+        actuality: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    ) -> bool {
+        let expired = match chrono::Duration::from_std(ttl) {
+            Ok(ttl) => Utc::now().signed_duration_since(self.checked_at) > ttl,
+            Err(_) => false,
+        };
+        let outdated = match actuality {
+            Some((dtact_fl, dtact_ul)) => self.dtact_fl < dtact_fl || self.dtact_ul < dtact_ul,
+            None => false,
+        };
+
+        expired || outdated
+    }
+}
+
+impl MemoryCache {
+    pub fn new() -> MemoryCache {
+        MemoryCache::default()
+    }
+
+    /// Creates the cache keeping at most `capacity` entries.
+    ///
+    /// When the cache is full, the tenth of the entries checked the longest
+    /// time ago is dropped to make room for the new ones.
+    pub fn with_capacity(capacity: usize) -> MemoryCache {
+        MemoryCache {
+            capacity: Some(capacity.max(1)),
+            ..MemoryCache::default()
+        }
+    }
+
+    /// The number of the entries kept.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Returns `true` if no entries are kept.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Drops `count` entries checked the longest time ago.
+fn evict_oldest(entries: &mut HashMap<CacheKey, CacheEntry>, count: usize) {
+    let mut checked: Vec<(DateTime<Utc>, CacheKey)> = entries
+        .iter()
+        .map(|(key, entry)| (entry.checked_at, key.clone()))
+        .collect();
+    checked.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (_, key) in checked.into_iter().take(count) {
+        entries.remove(&key);
+    }
+}
+
+impl Cache for MemoryCache {
+    fn get(&self, key: &CacheKey) -> Result<Option<CacheEntry>> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+
+    fn put(&self, key: &CacheKey, entry: &CacheEntry) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(capacity) = self.capacity {
+            if entries.len() >= capacity && !entries.contains_key(key) {
+                evict_oldest(&mut entries, capacity / 10 + 1);
+            }
+        }
+
+        entries.insert(key.clone(), entry.clone());
+        Ok(())
+    }
+
+    fn actuality(&self) -> Result<Option<(DateTime<Utc>, DateTime<Utc>)>> {
+        Ok(*self.actuality.lock().unwrap())
+    }
+
+    fn set_actuality(&self, dtact_fl: DateTime<Utc>, dtact_ul: DateTime<Utc>) -> Result<()> {
+        *self.actuality.lock().unwrap() = Some((dtact_fl, dtact_ul));
+        Ok(())
+    }
+}
+
+impl NpchkClient {
+    /// Checks of contractors, taking the results from the cache where
+    /// they are still valid. Only the cache misses are sent to the service.
+    ///
+    /// If the service reports newer data actuality dates than the cached
+    /// results were checked with, those results are checked again.
+    pub fn check_fns_cached<'a, C: Cache>(
+        &self,
+        cache: &C,
+        partners: Vec<Partner<'a>>,
+        ttl: Duration,
+        options: &CheckOptions,
+    ) -> Result<CachedResponse<'a>> {
+        let mut actuality = cache.actuality()?;
+        let mut slots: Vec<Option<(CachedPartner<'a>, CacheEntry)>> = vec![];
+        let mut misses: Vec<(usize, Partner<'a>)> = vec![];
+
+        for (index, mut p) in partners.into_iter().enumerate() {
+            let entry = cache
+                .get(&CacheKey::from_partner(&p))?
+                .and_then(|entry| if entry.is_stale(ttl, actuality) {
+                    None
+                } else {
+                    Some(entry)
+                });
+
+            match entry {
+                Some(entry) => {
+                    p.state = Some(PartnerState::from_code(p.kind(), entry.state));
+                    slots.push(Some((
+                        CachedPartner {
+                            partner: p,
+                            from_cache: true,
+                        },
+                        entry,
+                    )));
+                }
+                None => {
+                    slots.push(None);
+                    misses.push((index, p));
+                }
+            }
+        }
+
+        self.fetch_misses(cache, &mut slots, misses, &mut actuality, options)?;
+
+        // Results checked with older data than the service has just reported
+        let stale: Vec<(usize, Partner<'a>)> = slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| match *slot {
+                Some((ref cached, ref entry))
+                    if cached.from_cache && entry.is_stale(ttl, actuality) =>
+                {
+                    Some((index, cached.partner.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        self.fetch_misses(cache, &mut slots, stale, &mut actuality, options)?;
+
+        let mut dtact: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+        let mut result = vec![];
+        for slot in slots {
+            if let Some((cached, entry)) = slot {
+                dtact = Some(match dtact {
+                    Some((fl, ul)) => (fl.min(entry.dtact_fl), ul.min(entry.dtact_ul)),
+                    None => (entry.dtact_fl, entry.dtact_ul),
+                });
+                result.push(cached);
+            }
+        }
+
+        let (dtact_fl, dtact_ul) = match dtact.or(actuality) {
+            Some(dtact) => dtact,
+            None => (Utc::now(), Utc::now()),
+        };
+
+        Ok(CachedResponse {
+            dtact_fl: dtact_fl,
+            dtact_ul: dtact_ul,
+            partners: result,
+        })
+    }
+
+    fn fetch_misses<'a, C: Cache>(
+        &self,
+        cache: &C,
+        slots: &mut Vec<Option<(CachedPartner<'a>, CacheEntry)>>,
+        misses: Vec<(usize, Partner<'a>)>,
+        actuality: &mut Option<(DateTime<Utc>, DateTime<Utc>)>,
+        options: &CheckOptions,
+    ) -> Result<()> {
+        let mut misses = misses.into_iter().peekable();
+        while misses.peek().is_some() {
+            let chunk: Vec<(usize, Partner<'a>)> = misses.by_ref().take(MAX_RECORDS).collect();
+            let request: Vec<Partner<'a>> = chunk.iter().map(|&(_, ref p)| p.clone()).collect();
+            let local = answered_locally(&request, options);
+            let rsp = self.check_fns_with_options(request, options)?;
+
+            // Only the service reports the actuality of its data, the dates
+            // of a response of the local validation are the time of the check
+            let dtact = match *actuality {
+                Some(dtact) if local => dtact,
+                _ => (rsp.dtact_fl, rsp.dtact_ul),
+            };
+            if !local {
+                // Each date only moves forward, a response may be newer in one
+                // of them and older in the other
+                let newest = match *actuality {
+                    Some((fl, ul)) => (fl.max(rsp.dtact_fl), ul.max(rsp.dtact_ul)),
+                    None => (rsp.dtact_fl, rsp.dtact_ul),
+                };
+                if *actuality != Some(newest) {
+                    cache.set_actuality(newest.0, newest.1)?;
+                    *actuality = Some(newest);
+                }
+            }
+
+            let entry = CacheEntry {
+                state: 0,
+                dtact_fl: dtact.0,
+                dtact_ul: dtact.1,
+                checked_at: Utc::now(),
+            };
+            for checked in correlate(chunk, rsp).partners {
+                let partner = match checked.result {
+                    Some(partner) => partner,
+                    None => checked.request,
+                };
+                let entry = CacheEntry {
+                    state: partner.state.map_or(-1, |state| state.code()),
+                    ..entry.clone()
+                };
+
+                if partner.state.map_or(false, |state| !state.is_input_error()) {
+                    cache.put(&CacheKey::from_partner(&partner), &entry)?;
+                }
+
+                slots[checked.key] = Some((
+                    CachedPartner {
+                        partner: partner,
+                        from_cache: false,
+                    },
+                    entry,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use mock::MockNpchkServer;
+
+    fn partner(inn: &str, kpp: &str) -> Partner<'static> {
+        Partner::new(inn.to_string(), kpp.to_string(), Utc::now())
+    }
+
+    fn check(
+        server: &MockNpchkServer,
+        cache: &MemoryCache,
+        partners: Vec<Partner<'static>>,
+    ) -> CachedResponse<'static> {
+        server
+            .client()
+            .unwrap()
+            .check_fns_cached(cache, partners, Duration::from_secs(3600), &CheckOptions::default())
+            .unwrap()
+    }
+
+    fn from_cache(rsp: &CachedResponse) -> Vec<bool> {
+        rsp.partners.iter().map(|p| p.from_cache).collect()
+    }
+
+    #[test]
+    fn hits_are_not_sent() {
+        let server = MockNpchkServer::start().unwrap();
+        server.set_state("6648185610", "662301001", 3);
+        let cache = MemoryCache::new();
+        let partners = vec![
+            partner("4205036750", "420501001"),
+            partner("6648185610", "662301001"),
+        ];
+
+        let first = check(&server, &cache, partners.clone());
+        let second = check(&server, &cache, partners);
+
+        assert_eq!(from_cache(&first), vec![false, false]);
+        assert_eq!(from_cache(&second), vec![true, true]);
+        assert_eq!(second.partners[1].partner.state, Some(PartnerState::KppMismatch));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn only_misses_are_sent() {
+        let server = MockNpchkServer::start().unwrap();
+        let cache = MemoryCache::new();
+        check(&server, &cache, vec![partner("4205036750", "420501001")]);
+
+        let rsp = check(
+            &server,
+            &cache,
+            vec![
+                partner("6648185610", "662301001"),
+                partner("4205036750", "420501001"),
+                partner("4205036750", "420501002"),
+            ],
+        );
+
+        assert_eq!(from_cache(&rsp), vec![false, true, false]);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[1].body.contains("420501001"));
+    }
+
+    #[test]
+    fn expired_entries_are_checked_again() {
+        let server = MockNpchkServer::start().unwrap();
+        server.set_actuality("01.02.2018", "01.02.2018");
+        let cache = MemoryCache::new();
+        let p = partner("4205036750", "420501001");
+        let dtact = Utc.ymd(2018, 2, 1).and_hms(0, 0, 0);
+        cache
+            .put(
+                &CacheKey::from_partner(&p),
+                &CacheEntry {
+                    state: 0,
+                    dtact_fl: dtact,
+                    dtact_ul: dtact,
+                    checked_at: Utc::now() - chrono::Duration::hours(2),
+                },
+            )
+            .unwrap();
+
+        let rsp = check(&server, &cache, vec![p]);
+
+        assert_eq!(from_cache(&rsp), vec![false]);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn newer_actuality_invalidates_entries() {
+        let server = MockNpchkServer::start().unwrap();
+        server.set_actuality("01.02.2018", "01.02.2018");
+        let cache = MemoryCache::new();
+        check(&server, &cache, vec![partner("4205036750", "420501001")]);
+
+        server.set_actuality("01.02.2018", "05.02.2018");
+        let rsp = check(
+            &server,
+            &cache,
+            vec![
+                partner("4205036750", "420501001"),
+                partner("6648185610", "662301001"),
+            ],
+        );
+
+        assert_eq!(from_cache(&rsp), vec![false, false]);
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(rsp.dtact_ul, Utc.ymd(2018, 2, 5).and_hms(0, 0, 0));
+    }
+
+    #[test]
+    fn full_cache_drops_the_oldest_entries() {
+        let cache = MemoryCache::with_capacity(20);
+        let dtact = Utc.ymd(2018, 2, 1).and_hms(0, 0, 0);
+        let key = |i: usize| CacheKey {
+            inn: format!("{:010}", i),
+            kpp: String::new(),
+            date: dtact.date().naive_utc(),
+        };
+        for i in 0..25 {
+            let entry = CacheEntry {
+                state: 0,
+                dtact_fl: dtact,
+                dtact_ul: dtact,
+                checked_at: dtact + chrono::Duration::minutes(i as i64),
+            };
+            cache.put(&key(i), &entry).unwrap();
+        }
+
+        assert!(cache.len() <= 20, "{} entries", cache.len());
+        assert!(cache.get(&key(0)).unwrap().is_none());
+        assert!(cache.get(&key(24)).unwrap().is_some());
+    }
+
+    #[test]
+    fn invalid_partners_keep_the_actuality() {
+        let server = MockNpchkServer::start().unwrap();
+        server.set_actuality("01.02.2018", "01.02.2018");
+        let client = server.client().unwrap();
+        let cache = MemoryCache::new();
+        let ttl = Duration::from_secs(3600);
+        let options = CheckOptions { skip_invalid: true };
+        let valid = partner("4205036750", "420501001");
+        client
+            .check_fns_cached(&cache, vec![valid.clone()], ttl, &options)
+            .unwrap();
+
+        let rsp = client
+            .check_fns_cached(&cache, vec![partner("42050367", "420501001")], ttl, &options)
+            .unwrap();
+        assert_eq!(rsp.partners[0].partner.state, Some(PartnerState::InvalidInnLength));
+        let dtact = Utc.ymd(2018, 2, 1).and_hms(0, 0, 0);
+        assert_eq!(cache.actuality().unwrap(), Some((dtact, dtact)));
+
+        let rsp = client.check_fns_cached(&cache, vec![valid], ttl, &options).unwrap();
+        assert_eq!(from_cache(&rsp), vec![true]);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn actuality_dates_are_kept_separately() {
+        let server = MockNpchkServer::start().unwrap();
+        server.set_actuality("06.02.2018", "01.02.2018");
+        let cache = MemoryCache::new();
+        let dtact = Utc.ymd(2018, 2, 5).and_hms(0, 0, 0);
+        cache.set_actuality(dtact, dtact).unwrap();
+
+        check(&server, &cache, vec![partner("4205036750", "420501001")]);
+
+        assert_eq!(
+            cache.actuality().unwrap(),
+            Some((Utc.ymd(2018, 2, 6).and_hms(0, 0, 0), dtact))
+        );
+    }
+}
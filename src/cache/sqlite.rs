@@ -0,0 +1,156 @@
+//! Cache stored in an SQLite database.
+
+use chrono::prelude::*;
+use rusqlite::{self, Connection};
+
+use super::{Cache, CacheEntry, CacheKey};
+use super::super::Result;
+use sqlite::parse_datetime;
+
+/// Cache stored in an SQLite database file
+///
+/// Available with the `sqlite` feature.
+pub struct SqliteCache {
+    conn: Connection,
+}
+
+sqlite_store!(SqliteCache);
+
+impl SqliteCache {
+    fn from_connection(conn: Connection) -> Result<SqliteCache> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS npchk_cache (
+                inn TEXT NOT NULL,
+                kpp TEXT NOT NULL,
+                date TEXT NOT NULL,
+                state INTEGER NOT NULL,
+                dtact_fl TEXT NOT NULL,
+                dtact_ul TEXT NOT NULL,
+                checked_at TEXT NOT NULL,
+                PRIMARY KEY (inn, kpp, date)
+            );
+            CREATE TABLE IF NOT EXISTS npchk_actuality (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                dtact_fl TEXT NOT NULL,
+                dtact_ul TEXT NOT NULL
+            );",
+        )?;
+
+        Ok(SqliteCache { conn: conn })
+    }
+}
+
+impl Cache for SqliteCache {
+    fn get(&self, key: &CacheKey) -> Result<Option<CacheEntry>> {
+        let result = self.conn.query_row(
+            "SELECT state, dtact_fl, dtact_ul, checked_at FROM npchk_cache
+             WHERE inn = ?1 AND kpp = ?2 AND date = ?3",
+            &[&key.inn, &key.kpp, &key.date.to_string()],
+            |row| {
+                (
+                    row.get::<_, i32>(0),
+                    row.get::<_, String>(1),
+                    row.get::<_, String>(2),
+                    row.get::<_, String>(3),
+                )
+            },
+        );
+
+        match result {
+            Ok((state, dtact_fl, dtact_ul, checked_at)) => Ok(Some(CacheEntry {
+                state: state,
+                dtact_fl: parse_datetime(&dtact_fl)?,
+                dtact_ul: parse_datetime(&dtact_ul)?,
+                checked_at: parse_datetime(&checked_at)?,
+            })),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn put(&self, key: &CacheKey, entry: &CacheEntry) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO npchk_cache
+             (inn, kpp, date, state, dtact_fl, dtact_ul, checked_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            &[
+                &key.inn,
+                &key.kpp,
+                &key.date.to_string(),
+                &entry.state,
+                &entry.dtact_fl.to_rfc3339(),
+                &entry.dtact_ul.to_rfc3339(),
+                &entry.checked_at.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn actuality(&self) -> Result<Option<(DateTime<Utc>, DateTime<Utc>)>> {
+        let result = self.conn.query_row(
+            "SELECT dtact_fl, dtact_ul FROM npchk_actuality WHERE id = 1",
+            &[],
+            |row| (row.get::<_, String>(0), row.get::<_, String>(1)),
+        );
+
+        match result {
+            Ok((dtact_fl, dtact_ul)) => Ok(Some((
+                parse_datetime(&dtact_fl)?,
+                parse_datetime(&dtact_ul)?,
+            ))),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set_actuality(&self, dtact_fl: DateTime<Utc>, dtact_ul: DateTime<Utc>) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO npchk_actuality (id, dtact_fl, dtact_ul)
+             VALUES (1, ?1, ?2)",
+            &[&dtact_fl.to_rfc3339(), &dtact_ul.to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_and_actuality_round_trip() {
+        let cache = SqliteCache::open_in_memory().unwrap();
+        let key = CacheKey {
+            inn: "7707083893".into(),
+            kpp: "773601001".into(),
+            date: NaiveDate::from_ymd(2018, 2, 1),
+        };
+        let entry = CacheEntry {
+            state: 3,
+            dtact_fl: Utc.ymd(2018, 1, 30).and_hms(0, 0, 0),
+            dtact_ul: Utc.ymd(2018, 1, 31).and_hms(0, 0, 0),
+            checked_at: Utc.ymd(2018, 2, 1).and_hms(9, 30, 0),
+        };
+
+        assert!(cache.get(&key).unwrap().is_none());
+        assert_eq!(cache.actuality().unwrap(), None);
+
+        cache.put(&key, &entry).unwrap();
+        cache.set_actuality(entry.dtact_fl, entry.dtact_ul).unwrap();
+
+        let stored = cache.get(&key).unwrap().unwrap();
+        assert_eq!(stored.state, 3);
+        assert_eq!(stored.dtact_fl, entry.dtact_fl);
+        assert_eq!(stored.dtact_ul, entry.dtact_ul);
+        assert_eq!(stored.checked_at, entry.checked_at);
+        assert_eq!(cache.actuality().unwrap(), Some((entry.dtact_fl, entry.dtact_ul)));
+
+        let other_date = CacheKey {
+            date: NaiveDate::from_ymd(2018, 2, 2),
+            ..key
+        };
+        assert!(cache.get(&other_date).unwrap().is_none());
+    }
+}
@@ -0,0 +1,454 @@
+//! Configurable client of the service.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+use reqwest;
+
+use super::{error, http, rpser, schema, validate, BatchResponse, CheckOptions, ChunkFailure,
+            FromElement, NdsResponse, Partner, PartnerState, Result, RetryPolicy, MAX_RECORDS,
+            V2_API_NAMESPACE, V2_API_REQUEST, V2_API_RPC_PATH};
+use correlate::{correlate, CorrelatedResponse};
+use metrics::{Metrics, NoMetrics};
+use rpser::Method;
+
+/// Client of the service [http://npchk.nalog.ru/](http://npchk.nalog.ru/)
+///
+/// Holds the endpoint settings and one HTTP client reused by every call.
+#[derive(Debug)]
+pub struct NpchkClient {
+    url: String,
+    request_uri: String,
+    namespace: String,
+    user_agent: String,
+    retry: RetryPolicy,
+    metrics: Arc<Metrics>,
+    http: reqwest::Client,
+}
+
+/// Builder of the `NpchkClient`
+#[derive(Debug, Clone)]
+pub struct NpchkClientBuilder {
+    pub(crate) url: String,
+    pub(crate) request_uri: String,
+    pub(crate) namespace: String,
+    pub(crate) user_agent: String,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) proxy: Option<String>,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) metrics: Arc<Metrics>,
+}
+
+impl Default for NpchkClientBuilder {
+    fn default() -> NpchkClientBuilder {
+        NpchkClientBuilder {
+            url: V2_API_RPC_PATH.into(),
+            request_uri: V2_API_REQUEST.into(),
+            namespace: V2_API_NAMESPACE.into(),
+            user_agent: concat!("npchk/", env!("CARGO_PKG_VERSION")).into(),
+            timeout: None,
+            proxy: None,
+            retry: RetryPolicy::none(),
+            metrics: Arc::new(NoMetrics),
+        }
+    }
+}
+
+impl NpchkClientBuilder {
+    /// Create new builder with the settings of the public service.
+    pub fn new() -> NpchkClientBuilder {
+        NpchkClientBuilder::default()
+    }
+
+    /// Set the connection point of the service.
+    pub fn url<S>(mut self, url: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.url = url.into();
+        self
+    }
+
+    /// Set the URI of the request namespace, also used as `SOAPAction` base.
+    pub fn request_uri<S>(mut self, request_uri: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.request_uri = request_uri.into();
+        self
+    }
+
+    /// Set the prefix of the request namespace.
+    pub fn namespace<S>(mut self, namespace: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// Set the `User-Agent` header sent with every request.
+    pub fn user_agent<S>(mut self, user_agent: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Set the timeout of a request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Send every request through the proxy.
+    pub fn proxy<S>(mut self, proxy: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Set the policy of repeating the calls failed with transient errors.
+    ///
+    /// By default a failed call is not repeated.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Set the receiver of the measurements of the calls.
+    ///
+    /// By default nothing is recorded.
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Create the client.
+    pub fn build(self) -> Result<NpchkClient> {
+        let http = http::client(self.timeout, self.proxy.as_ref().map(|s| s.as_str()))?;
+
+        Ok(NpchkClient {
+            url: self.url,
+            request_uri: self.request_uri,
+            namespace: self.namespace,
+            user_agent: self.user_agent,
+            retry: self.retry,
+            metrics: self.metrics,
+            http: http,
+        })
+    }
+}
+
+impl NpchkClient {
+    /// Create new client with the settings of the public service.
+    pub fn new() -> Result<NpchkClient> {
+        NpchkClientBuilder::new().build()
+    }
+
+    /// Create new builder of the client.
+    pub fn builder() -> NpchkClientBuilder {
+        NpchkClientBuilder::new()
+    }
+
+    /// The connection point of the service.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The URI of the request namespace.
+    pub fn request_uri(&self) -> &str {
+        &self.request_uri
+    }
+
+    /// The prefix of the request namespace.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// The `User-Agent` header sent with every request.
+    pub fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
+    /// The policy of repeating the failed calls.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// The receiver of the measurements of the calls.
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Checks of contractors through the service
+    pub fn check_fns<'a>(&self, partners: Vec<Partner<'a>>) -> Result<NdsResponse<'a>> {
+        self.check_fns_with_options(partners, &CheckOptions::default())
+    }
+
+    /// Checks the 1st of the contractor using the service
+    pub fn check_fns_partner<'a>(&self, p: Partner<'a>) -> Result<NdsResponse<'a>> {
+        self.check_fns(vec![p])
+    }
+
+    /// Checks of contractors through the service with the specified options
+    pub fn check_fns_with_options<'a>(
+        &self,
+        partners: Vec<Partner<'a>>,
+        options: &CheckOptions,
+    ) -> Result<NdsResponse<'a>> {
+        self.check_chunk(partners, options).map_err(|(e, _)| e)
+    }
+
+    /// Checks of contractors and pairs every submitted partner
+    /// with its answer, see the `correlate` module.
+    ///
+    /// The key of each partner is opaque to the service and is returned
+    /// along with the result. Partners are sent in requests of at most
+    /// `MAX_RECORDS` partners; the check fails if any of them fails.
+    pub fn check_fns_keyed<'a, K>(
+        &self,
+        partners: Vec<(K, Partner<'a>)>,
+        options: &CheckOptions,
+    ) -> Result<CorrelatedResponse<'a, K>> {
+        let mut partners = partners.into_iter().peekable();
+        let mut merged = self.check_keyed_chunk(&mut partners, options)?;
+        while partners.peek().is_some() {
+            let correlated = self.check_keyed_chunk(&mut partners, options)?;
+            merged.merge(correlated);
+        }
+
+        Ok(merged)
+    }
+
+    /// Checks the next `MAX_RECORDS` keyed partners.
+    fn check_keyed_chunk<'a, K, I>(
+        &self,
+        partners: &mut I,
+        options: &CheckOptions,
+    ) -> Result<CorrelatedResponse<'a, K>>
+    where
+        I: Iterator<Item = (K, Partner<'a>)>,
+    {
+        let chunk: Vec<(K, Partner<'a>)> = partners.take(MAX_RECORDS).collect();
+        let request: Vec<Partner<'a>> = chunk.iter().map(|&(_, ref p)| p.clone()).collect();
+        let response = self.check_fns_with_options(request, options)?;
+
+        Ok(correlate(chunk, response))
+    }
+
+    /// Checks any number of contractors, splitting them into requests
+    /// of at most `MAX_RECORDS` partners.
+    ///
+    /// Chunks are sent in order and their results are merged into one response.
+    /// A failed chunk does not abort the run, it is reported in
+    /// `BatchResponse::failures` instead.
+    pub fn check_fns_batched<'a>(
+        &self,
+        partners: Vec<Partner<'a>>,
+        options: &CheckOptions,
+    ) -> BatchResponse<'a> {
+        let mut batch = BatchResponse {
+            response: None,
+            failures: vec![],
+        };
+
+        let mut partners = partners.into_iter().peekable();
+        let mut index = 0;
+        while partners.peek().is_some() {
+            let chunk: Vec<Partner<'a>> = partners.by_ref().take(MAX_RECORDS).collect();
+
+            match self.check_chunk(chunk, options) {
+                Ok(rsp) => {
+                    batch.response = Some(match batch.response.take() {
+                        Some(mut merged) => {
+                            merged.merge(rsp);
+                            merged
+                        }
+                        None => rsp,
+                    });
+                }
+                Err((e, chunk)) => batch.failures.push(ChunkFailure {
+                    index: index,
+                    partners: chunk,
+                    error: e,
+                }),
+            }
+
+            index += 1;
+        }
+
+        batch
+    }
+
+    /// Checks the partners, giving them back along with the error
+    /// if the check fails, so the caller can report them without a copy.
+    fn check_chunk<'a>(
+        &self,
+        partners: Vec<Partner<'a>>,
+        options: &CheckOptions,
+    ) -> ::std::result::Result<NdsResponse<'a>, (error::Error, Vec<Partner<'a>>)> {
+        if partners.len() > MAX_RECORDS {
+            return Err((error::Error::TooManyRecords, partners));
+        }
+
+        if answered_locally(&partners, options) {
+            let states: Vec<Option<PartnerState>> =
+                partners.iter().map(validate::validate).collect();
+            return Ok(local_response(partners.into_iter().zip(states).collect()));
+        }
+
+        if !options.skip_invalid {
+            let rsp = self.request(&partners);
+            return rsp.map_err(|e| (e, partners));
+        }
+
+        let states: Vec<Option<PartnerState>> = partners.iter().map(validate::validate).collect();
+
+        let rsp = self.request(
+            partners
+                .iter()
+                .zip(&states)
+                .filter(|&(_, state)| state.is_none())
+                .map(|(p, _)| p),
+        );
+        let rsp = match rsp {
+            Ok(rsp) => rsp,
+            Err(e) => return Err((e, partners)),
+        };
+
+        // The answers are matched by INN, KPP and date, the rejected
+        // partners have no answers and keep the state of the validation
+        let correlated = correlate(states.into_iter().zip(partners).collect(), rsp);
+        let mut merged = Vec::with_capacity(correlated.partners.len());
+        for checked in correlated.partners {
+            match checked.key {
+                Some(state) => {
+                    let mut p = checked.request;
+                    p.state = Some(state);
+                    merged.push(p);
+                }
+                None => merged.push(checked.result.unwrap_or(checked.request)),
+            }
+            merged.extend(checked.duplicates);
+        }
+        merged.extend(correlated.unexpected);
+
+        Ok(NdsResponse {
+            dtact_fl: correlated.dtact_fl,
+            dtact_ul: correlated.dtact_ul,
+            partners: merged,
+        })
+    }
+
+    fn request<'a, 'b, 'p: 'b, I>(&self, partners: I) -> Result<NdsResponse<'a>>
+    where
+        I: IntoIterator<Item = &'b Partner<'p>>,
+    {
+        let method = nds_request2(&self.namespace, partners);
+        let name = method.name.clone();
+        self.metrics.batch(&name, method.args.len());
+
+        let rsp = self.call(method)
+            .and_then(|response| NdsResponse::from_element(response.body));
+        record(&*self.metrics, &name, &rsp);
+
+        rsp
+    }
+
+    /// Calls a remote procedure through a Protocol `SOAP`,
+    /// repeating the call according to the retry policy
+    fn call(&self, method: Method) -> Result<rpser::Response> {
+        let envelope = method.as_xml(&self.request_uri, &self.namespace);
+
+        self.retry.run(|| self.call_once(&method.name, &envelope))
+    }
+
+    fn call_once(&self, action: &str, envelope: &str) -> Result<rpser::Response> {
+        let start = Instant::now();
+        let http_response =
+            http::soap_action(&self.http, &self.url, &self.user_agent, action, envelope);
+        let size = http_response.as_ref().map_or(0, |response| response.body.len());
+        self.metrics.http_request(action, start.elapsed(), size);
+
+        soap_response(http_response?)
+    }
+}
+
+/// Builds the `NdsRequest2` method for the partners
+pub(crate) fn nds_request2<'b, 'p: 'b, I>(namespace: &str, partners: I) -> Method
+where
+    I: IntoIterator<Item = &'b Partner<'p>>,
+{
+    let request = schema::NdsRequest2 {
+        np: partners
+            .into_iter()
+            .map(|elem| schema::NdsRequest2Np {
+                dt: Some(elem.dt.format("%d.%m.%Y").to_string()),
+                inn: elem.inn.to_string(),
+                kpp: if elem.kpp.is_empty() {
+                    None
+                } else {
+                    Some(elem.kpp.to_string())
+                },
+            })
+            .collect(),
+    };
+
+    request.to_method(namespace)
+}
+
+/// Reads the SOAP response, reporting the HTTP status
+/// if the body is not a SOAP message.
+pub(crate) fn soap_response(http_response: http::Response) -> Result<rpser::Response> {
+    match rpser::Response::from_xml(&http_response.body) {
+        Ok(response) => Ok(response),
+        Err(e @ rpser::RpcError::Fault { .. }) => Err(e.into()),
+        Err(_) if !http_response.status.is_success() => {
+            Err(error::Error::HttpStatus(http_response.status))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns `true` if the check of the partners is answered without
+/// a request to the service: there are no partners, or every one
+/// of them is rejected by the local validation.
+pub(crate) fn answered_locally(partners: &[Partner], options: &CheckOptions) -> bool {
+    partners.is_empty()
+        || options.skip_invalid && partners.iter().all(|p| validate::validate(p).is_some())
+}
+
+/// Response for the partners rejected by the local validation alone.
+///
+/// The service is not called, so the actuality dates are the time of the check,
+/// see `answered_locally`.
+fn local_response<'a>(partners: Vec<(Partner<'a>, Option<PartnerState>)>) -> NdsResponse<'a> {
+    let now = Utc::now();
+
+    NdsResponse {
+        dtact_fl: now,
+        dtact_ul: now,
+        partners: partners
+            .into_iter()
+            .map(|(mut p, state)| {
+                p.state = state;
+                p
+            })
+            .collect(),
+    }
+}
+
+/// Records the outcome of the call in the metrics
+pub(crate) fn record(metrics: &Metrics, method: &str, rsp: &Result<NdsResponse>) {
+    match *rsp {
+        Ok(ref rsp) => for state in rsp.partners.iter().filter_map(|p| p.state) {
+            metrics.result(state);
+        },
+        Err(ref e) => metrics.error(method, e),
+    }
+}
@@ -0,0 +1,285 @@
+//! Matching of the submitted partners to the service answers.
+//!
+//! The service returns a flat list of `NP` elements. The functions of this
+//! module pair every submitted partner with its answer by INN, KPP and date,
+//! keeping the order of the input and an opaque key of the caller.
+
+use std::collections::{HashMap, VecDeque};
+
+use chrono::prelude::*;
+
+use super::{NdsResponse, Partner};
+
+/// Outcome of matching a submitted partner to the service answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Correlation {
+    /// The service returned exactly one answer for the partner
+    Matched,
+    /// The service did not return an answer for the partner
+    Missing,
+    /// The service returned more answers than partners were submitted,
+    /// the extra answers are kept in `CheckedPartner::duplicates`
+    Duplicated,
+}
+
+/// The submitted partner with the answer of the service
+#[derive(Debug, Clone)]
+pub struct CheckedPartner<'a, K> {
+    /// Key of the caller, for example an id of the record in the ERP system
+    pub key: K,
+    /// The partner as it was submitted
+    pub request: Partner<'a>,
+    /// The answer of the service, `None` if the service dropped the partner
+    pub result: Option<Partner<'a>>,
+    /// Extra answers of the service with the same INN, KPP and date
+    pub duplicates: Vec<Partner<'a>>,
+    /// Whether the service returned exactly one answer for the partner
+    pub correlation: Correlation,
+}
+
+/// The service response matched to the submitted partners
+#[derive(Debug, Clone)]
+pub struct CorrelatedResponse<'a, K> {
+    /// Date on which relevant data for the individual entrepreneur,
+    /// used to check.
+    pub dtact_fl: DateTime<Utc>,
+    /// Date on which relevant data for legal, used to check.
+    pub dtact_ul: DateTime<Utc>,
+    /// Submitted partners in the original order
+    pub partners: Vec<CheckedPartner<'a, K>>,
+    /// Answers that do not match any submitted partner
+    pub unexpected: Vec<Partner<'a>>,
+}
+
+impl<'a, K> CorrelatedResponse<'a, K> {
+    /// Returns `true` if every submitted partner got exactly one answer
+    /// and the service returned nothing else.
+    pub fn is_consistent(&self) -> bool {
+        self.unexpected.is_empty()
+            && self.partners
+                .iter()
+                .all(|p| p.correlation == Correlation::Matched)
+    }
+}
+
+impl<'a, K> CorrelatedResponse<'a, K> {
+    /// Appends the partners of another response.
+    ///
+    /// The earliest of the data actuality dates is kept, as in `NdsResponse::merge`.
+    pub fn merge(&mut self, other: CorrelatedResponse<'a, K>) {
+        if other.dtact_fl < self.dtact_fl {
+            self.dtact_fl = other.dtact_fl;
+        }
+        if other.dtact_ul < self.dtact_ul {
+            self.dtact_ul = other.dtact_ul;
+        }
+        self.partners.extend(other.partners);
+        self.unexpected.extend(other.unexpected);
+    }
+}
+
+type MatchKey = (String, String, NaiveDate);
+
+fn match_key(p: &Partner) -> MatchKey {
+    (p.inn.to_string(), p.kpp.to_string(), p.dt.date().naive_utc())
+}
+
+/// Pairs the submitted partners with the answers of the service.
+///
+/// Partners submitted several times with the same INN, KPP and date
+/// are paired with the answers in the order of the response.
+pub fn correlate<'a, K>(
+    requests: Vec<(K, Partner<'a>)>,
+    response: NdsResponse<'a>,
+) -> CorrelatedResponse<'a, K> {
+    let mut answers: HashMap<MatchKey, VecDeque<Partner<'a>>> = HashMap::new();
+    let mut order: Vec<MatchKey> = vec![];
+    for p in response.partners {
+        let key = match_key(&p);
+        if !answers.contains_key(&key) {
+            order.push(key.clone());
+        }
+        answers.entry(key).or_insert_with(VecDeque::new).push_back(p);
+    }
+
+    let mut last_index: HashMap<MatchKey, usize> = HashMap::new();
+    let mut partners: Vec<CheckedPartner<'a, K>> = Vec::with_capacity(requests.len());
+    for (key, request) in requests {
+        let id = match_key(&request);
+        let result = answers.get_mut(&id).and_then(|queue| queue.pop_front());
+        last_index.insert(id, partners.len());
+
+        partners.push(CheckedPartner {
+            key: key,
+            correlation: if result.is_some() {
+                Correlation::Matched
+            } else {
+                Correlation::Missing
+            },
+            request: request,
+            result: result,
+            duplicates: vec![],
+        });
+    }
+
+    let mut unexpected: Vec<Partner<'a>> = vec![];
+    for id in order {
+        let rest = match answers.remove(&id) {
+            Some(rest) => rest,
+            None => continue,
+        };
+        if rest.is_empty() {
+            continue;
+        }
+
+        match last_index.get(&id) {
+            Some(&index) => {
+                let checked = &mut partners[index];
+                checked.correlation = Correlation::Duplicated;
+                checked.duplicates.extend(rest);
+            }
+            None => unexpected.extend(rest),
+        }
+    }
+
+    CorrelatedResponse {
+        dtact_fl: response.dtact_fl,
+        dtact_ul: response.dtact_ul,
+        partners: partners,
+        unexpected: unexpected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PartnerState;
+
+    fn partner(inn: &'static str, day: u32) -> Partner<'static> {
+        Partner::new(inn, "", Utc.ymd(2018, 2, day).and_hms(0, 0, 0))
+    }
+
+    fn answer(inn: &'static str, day: u32, state: PartnerState) -> Partner<'static> {
+        let mut p = partner(inn, day);
+        p.state = Some(state);
+        p
+    }
+
+    fn state(correlated: &CorrelatedResponse<i32>, index: usize) -> Option<PartnerState> {
+        correlated.partners[index]
+            .result
+            .as_ref()
+            .and_then(|p| p.state)
+    }
+
+    fn response(partners: Vec<Partner<'static>>) -> NdsResponse<'static> {
+        NdsResponse {
+            dtact_fl: Utc.ymd(2018, 2, 1).and_hms(0, 0, 0),
+            dtact_ul: Utc.ymd(2018, 2, 1).and_hms(0, 0, 0),
+            partners: partners,
+        }
+    }
+
+    #[test]
+    fn matches_answers_in_any_order() {
+        let requests = vec![(1, partner("7707083893", 1)), (2, partner("6648185610", 1))];
+        let rsp = response(vec![
+            answer("6648185610", 1, PartnerState::NotRegistered),
+            answer("7707083893", 1, PartnerState::Active),
+        ]);
+
+        let correlated = correlate(requests, rsp);
+
+        assert!(correlated.is_consistent());
+        let keys: Vec<(i32, Option<PartnerState>)> = correlated
+            .partners
+            .iter()
+            .map(|p| (p.key, p.result.as_ref().and_then(|r| r.state)))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (1, Some(PartnerState::Active)),
+                (2, Some(PartnerState::NotRegistered)),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_date_is_a_part_of_the_key() {
+        let requests = vec![(1, partner("7707083893", 1)), (2, partner("7707083893", 2))];
+        let rsp = response(vec![
+            answer("7707083893", 2, PartnerState::Inactive),
+            answer("7707083893", 1, PartnerState::Active),
+        ]);
+
+        let correlated = correlate(requests, rsp);
+
+        assert!(correlated.is_consistent());
+        assert_eq!(state(&correlated, 0), Some(PartnerState::Active));
+        assert_eq!(state(&correlated, 1), Some(PartnerState::Inactive));
+    }
+
+    #[test]
+    fn reports_missing_answers() {
+        let requests = vec![(1, partner("7707083893", 1)), (2, partner("6648185610", 1))];
+        let rsp = response(vec![answer("6648185610", 1, PartnerState::Active)]);
+
+        let correlated = correlate(requests, rsp);
+
+        assert!(!correlated.is_consistent());
+        assert_eq!(correlated.partners[0].correlation, Correlation::Missing);
+        assert!(correlated.partners[0].result.is_none());
+        assert_eq!(correlated.partners[1].correlation, Correlation::Matched);
+    }
+
+    #[test]
+    fn repeated_partners_take_the_answers_in_order() {
+        let requests = vec![(1, partner("7707083893", 1)), (2, partner("7707083893", 1))];
+        let rsp = response(vec![
+            answer("7707083893", 1, PartnerState::Active),
+            answer("7707083893", 1, PartnerState::Inactive),
+        ]);
+
+        let correlated = correlate(requests, rsp);
+
+        assert!(correlated.is_consistent());
+        assert_eq!(state(&correlated, 0), Some(PartnerState::Active));
+        assert_eq!(state(&correlated, 1), Some(PartnerState::Inactive));
+    }
+
+    #[test]
+    fn extra_answers_are_duplicates_of_the_last_partner() {
+        let requests = vec![(1, partner("7707083893", 1)), (2, partner("7707083893", 1))];
+        let rsp = response(vec![
+            answer("7707083893", 1, PartnerState::Active),
+            answer("7707083893", 1, PartnerState::Active),
+            answer("7707083893", 1, PartnerState::Inactive),
+        ]);
+
+        let correlated = correlate(requests, rsp);
+
+        assert!(!correlated.is_consistent());
+        assert_eq!(correlated.partners[0].correlation, Correlation::Matched);
+        assert_eq!(correlated.partners[1].correlation, Correlation::Duplicated);
+        assert_eq!(correlated.partners[1].duplicates.len(), 1);
+        assert_eq!(correlated.partners[1].duplicates[0].state, Some(PartnerState::Inactive));
+        assert!(correlated.unexpected.is_empty());
+    }
+
+    #[test]
+    fn keeps_unexpected_answers() {
+        let requests = vec![(1, partner("7707083893", 1))];
+        let rsp = response(vec![
+            answer("6648185610", 1, PartnerState::Active),
+            answer("7707083893", 1, PartnerState::Active),
+        ]);
+
+        let correlated = correlate(requests, rsp);
+
+        assert!(!correlated.is_consistent());
+        assert_eq!(correlated.partners[0].correlation, Correlation::Matched);
+        assert_eq!(correlated.unexpected.len(), 1);
+        assert_eq!(correlated.unexpected[0].inn, "6648185610");
+    }
+}
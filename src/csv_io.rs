@@ -0,0 +1,474 @@
+//! Import of partner lists and export of check results in CSV.
+//!
+//! Available with the `csv-io` feature. The defaults match the files
+//! exported from spreadsheets: semicolon-separated, Windows-1251 encoding,
+//! dates as `dd.mm.yyyy`.
+
+use std::io::{self, Read, Write};
+use std::str;
+
+use chrono::prelude::*;
+use csv;
+use encoding_rs::WINDOWS_1251;
+
+use super::{error, CheckOptions, ChunkFailure, NdsResponse, NpchkClient, Partner, Result};
+
+/// Encoding of the CSV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Windows1251,
+}
+
+/// Column of the CSV file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    /// Zero-based index of the column
+    Index(usize),
+    /// Name of the column in the header row
+    Name(String),
+}
+
+/// Columns the partners are read from
+#[derive(Debug, Clone)]
+pub struct ColumnMapping {
+    /// Taxpayer identification number
+    pub inn: Column,
+    /// The reason code of registration, empty if not set
+    pub kpp: Option<Column>,
+    /// Date of the check, the current date if not set
+    pub date: Option<Column>,
+}
+
+/// Options of reading and writing CSV files
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    /// The first row contains the names of the columns
+    pub has_headers: bool,
+    pub encoding: Encoding,
+    pub columns: ColumnMapping,
+    /// Format of the dates, see `chrono::format::strftime`
+    pub date_format: String,
+}
+
+/// The row that could not be read
+#[derive(Debug, Clone)]
+pub struct RowError {
+    /// One-based number of the line in the file
+    pub line: u64,
+    pub message: String,
+}
+
+/// Partners read from the CSV file
+#[derive(Debug)]
+pub struct CsvImport {
+    pub partners: Vec<Partner<'static>>,
+    /// Rows skipped because of errors
+    pub errors: Vec<RowError>,
+}
+
+/// Outcome of checking the partners of the CSV file
+#[derive(Debug)]
+pub struct CsvCheckReport {
+    /// Rows of the input skipped because of errors
+    pub errors: Vec<RowError>,
+    /// Chunks of partners the service failed to check
+    pub failures: Vec<ChunkFailure<'static>>,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> ColumnMapping {
+        ColumnMapping {
+            inn: Column::Index(0),
+            kpp: Some(Column::Index(1)),
+            date: Some(Column::Index(2)),
+        }
+    }
+}
+
+impl Default for CsvOptions {
+    fn default() -> CsvOptions {
+        CsvOptions {
+            delimiter: b';',
+            has_headers: true,
+            encoding: Encoding::Windows1251,
+            columns: ColumnMapping::default(),
+            date_format: "%d.%m.%Y".into(),
+        }
+    }
+}
+
+/// Reads the partners from the CSV file.
+///
+/// Rows that can not be read are reported in `CsvImport::errors`
+/// and do not stop the import.
+pub fn read_partners<R: Read>(mut reader: R, options: &CsvOptions) -> Result<CsvImport> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    let text = match options.encoding {
+        Encoding::Utf8 => String::from_utf8_lossy(&bytes).into_owned(),
+        Encoding::Windows1251 => WINDOWS_1251.decode(&bytes).0.into_owned(),
+    };
+    // Files saved by Excel as UTF-8 start with the BOM, which would
+    // become a part of the name of the first column
+    let text = text.trim_left_matches('\u{feff}');
+
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(options.has_headers)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+
+    let headers = if options.has_headers {
+        Some(csv_reader.headers()?.clone())
+    } else {
+        None
+    };
+    let resolve = |column: &Column| -> Result<usize> {
+        match *column {
+            Column::Index(index) => Ok(index),
+            Column::Name(ref name) => headers
+                .as_ref()
+                .and_then(|headers| headers.iter().position(|h| h.trim() == name.as_str()))
+                .ok_or_else(|| error::Error::ColumnNotFound(name.clone())),
+        }
+    };
+    let inn_column = resolve(&options.columns.inn)?;
+    let kpp_column = match options.columns.kpp {
+        Some(ref column) => Some(resolve(column)?),
+        None => None,
+    };
+    let date_column = match options.columns.date {
+        Some(ref column) => Some(resolve(column)?),
+        None => None,
+    };
+
+    let mut import = CsvImport {
+        partners: vec![],
+        errors: vec![],
+    };
+
+    for record in csv_reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                import.errors.push(RowError {
+                    line: e.position().map_or(0, |p| p.line()),
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |p| p.line());
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|index| record.get(index))
+                .map_or("", |value| value.trim())
+        };
+
+        let inn = field(Some(inn_column));
+        if inn.is_empty() {
+            import.errors.push(RowError {
+                line: line,
+                message: "Missing taxpayer identification number".into(),
+            });
+            continue;
+        }
+
+        let date = field(date_column);
+        let dt = if date.is_empty() {
+            Utc::today().and_hms(0, 0, 0)
+        } else {
+            match NaiveDate::parse_from_str(date, &options.date_format) {
+                Ok(date) => Utc.from_utc_date(&date).and_hms(0, 0, 0),
+                Err(e) => {
+                    import.errors.push(RowError {
+                        line: line,
+                        message: format!("Invalid date {:?}: {}", date, e),
+                    });
+                    continue;
+                }
+            }
+        };
+
+        import.partners.push(Partner::new(
+            inn.to_string(),
+            field(kpp_column).to_string(),
+            dt,
+        ));
+    }
+
+    Ok(import)
+}
+
+/// Writes the check results to the CSV file.
+///
+/// Columns: INN, KPP, DT, State, Description, DTActFL, DTActUL.
+/// The rows are encoded and written to `writer` as they are formatted.
+pub fn write_response<W: Write>(writer: W, rsp: &NdsResponse, options: &CsvOptions) -> Result<()> {
+    let builder = {
+        let mut builder = csv::WriterBuilder::new();
+        builder.delimiter(options.delimiter);
+        builder
+    };
+
+    match options.encoding {
+        Encoding::Utf8 => write_records(builder.from_writer(writer), rsp, options),
+        Encoding::Windows1251 => {
+            write_records(builder.from_writer(Windows1251Writer::new(writer)), rsp, options)
+        }
+    }
+}
+
+fn write_records<W: Write>(
+    mut csv_writer: csv::Writer<W>,
+    rsp: &NdsResponse,
+    options: &CsvOptions,
+) -> Result<()> {
+    if options.has_headers {
+        csv_writer.write_record(&[
+            "INN", "KPP", "DT", "State", "Description", "DTActFL", "DTActUL"
+        ])?;
+    }
+
+    let dtact_fl = rsp.dtact_fl.format(&options.date_format).to_string();
+    let dtact_ul = rsp.dtact_ul.format(&options.date_format).to_string();
+    for p in &rsp.partners {
+        let (code, description) = match p.state {
+            Some(state) => (state.code().to_string(), state.description()),
+            None => (String::new(), ""),
+        };
+
+        let dt = p.dt.format(&options.date_format).to_string();
+
+        csv_writer.write_record(&[
+            &*p.inn,
+            &*p.kpp,
+            dt.as_str(),
+            code.as_str(),
+            description,
+            dtact_fl.as_str(),
+            dtact_ul.as_str(),
+        ])?;
+    }
+
+    csv_writer.flush()?;
+    Ok(())
+}
+
+/// Re-encodes the UTF-8 written by the CSV writer into Windows-1251,
+/// holding back a character split between two writes.
+struct Windows1251Writer<W> {
+    inner: W,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Windows1251Writer<W> {
+    fn new(inner: W) -> Windows1251Writer<W> {
+        Windows1251Writer {
+            inner: inner,
+            pending: vec![],
+        }
+    }
+}
+
+impl<W: Write> Write for Windows1251Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid = match str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(ref e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+
+        {
+            let text = str::from_utf8(&self.pending[..valid])
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.inner.write_all(&WINDOWS_1251.encode(text).0)?;
+        }
+        self.pending.drain(..valid);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Incomplete UTF-8 sequence at the end of the output",
+            ));
+        }
+        self.inner.flush()
+    }
+}
+
+/// Reads the partners from the CSV file, checks them and writes
+/// the results to another CSV file in the same format.
+pub fn check_csv<R: Read, W: Write>(
+    client: &NpchkClient,
+    input: R,
+    output: W,
+    options: &CsvOptions,
+    check: &CheckOptions,
+) -> Result<CsvCheckReport> {
+    let import = read_partners(input, options)?;
+    let batch = client.check_fns_batched(import.partners, check);
+
+    if let Some(ref rsp) = batch.response {
+        write_response(output, rsp, options)?;
+    }
+
+    Ok(CsvCheckReport {
+        errors: import.errors,
+        failures: batch.failures,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PartnerState;
+
+    fn windows_1251(text: &str) -> Vec<u8> {
+        WINDOWS_1251.encode(text).0.into_owned()
+    }
+
+    #[test]
+    fn windows_1251_is_decoded() {
+        let input = windows_1251("ИНН;КПП;Дата\n7707083893;773601001;01.02.2018\n");
+        let options = CsvOptions {
+            columns: ColumnMapping {
+                inn: Column::Name("ИНН".into()),
+                kpp: Some(Column::Name("КПП".into())),
+                date: Some(Column::Name("Дата".into())),
+            },
+            ..CsvOptions::default()
+        };
+
+        let import = read_partners(&input[..], &options).unwrap();
+
+        assert!(import.errors.is_empty());
+        assert_eq!(import.partners.len(), 1);
+        assert_eq!(import.partners[0].inn, "7707083893");
+        assert_eq!(import.partners[0].kpp, "773601001");
+        assert_eq!(import.partners[0].dt, Utc.ymd(2018, 2, 1).and_hms(0, 0, 0));
+    }
+
+    #[test]
+    fn columns_are_mapped_by_header() {
+        let input = "Name,Date,INN,KPP\nSber,01.02.2018,7707083893,773601001\nIP,,500100732259,\n";
+        let options = CsvOptions {
+            delimiter: b',',
+            encoding: Encoding::Utf8,
+            columns: ColumnMapping {
+                inn: Column::Name("INN".into()),
+                kpp: Some(Column::Name("KPP".into())),
+                date: Some(Column::Name("Date".into())),
+            },
+            ..CsvOptions::default()
+        };
+
+        let import = read_partners(input.as_bytes(), &options).unwrap();
+
+        let partners: Vec<(&str, &str)> =
+            import.partners.iter().map(|p| (&*p.inn, &*p.kpp)).collect();
+        assert_eq!(partners, vec![("7707083893", "773601001"), ("500100732259", "")]);
+        assert_eq!(import.partners[0].dt, Utc.ymd(2018, 2, 1).and_hms(0, 0, 0));
+        assert_eq!(import.partners[1].dt, Utc::today().and_hms(0, 0, 0));
+    }
+
+    #[test]
+    fn utf8_bom_is_skipped() {
+        let input = "\u{feff}inn;kpp\n7707083893;773601001\n";
+        let options = CsvOptions {
+            encoding: Encoding::Utf8,
+            columns: ColumnMapping {
+                inn: Column::Name("inn".into()),
+                kpp: Some(Column::Name("kpp".into())),
+                date: None,
+            },
+            ..CsvOptions::default()
+        };
+
+        let import = read_partners(input.as_bytes(), &options).unwrap();
+
+        assert_eq!(import.partners.len(), 1);
+        assert_eq!(import.partners[0].inn, "7707083893");
+    }
+
+    #[test]
+    fn unknown_column_is_an_error() {
+        let options = CsvOptions {
+            encoding: Encoding::Utf8,
+            columns: ColumnMapping {
+                inn: Column::Name("INN".into()),
+                ..ColumnMapping::default()
+            },
+            ..CsvOptions::default()
+        };
+
+        match read_partners("ИНН;КПП\n".as_bytes(), &options) {
+            Err(error::Error::ColumnNotFound(ref name)) => assert_eq!(name, "INN"),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rows_with_errors_are_reported() {
+        let input = "7707083893;773601001;01.02.2018\n\
+                     ;773601001;01.02.2018\n\
+                     6648185610;662301001;2018-02-01\n\
+                     500100732259\n";
+        let options = CsvOptions {
+            has_headers: false,
+            encoding: Encoding::Utf8,
+            ..CsvOptions::default()
+        };
+
+        let import = read_partners(input.as_bytes(), &options).unwrap();
+
+        let inns: Vec<&str> = import.partners.iter().map(|p| &*p.inn).collect();
+        assert_eq!(inns, vec!["7707083893", "500100732259"]);
+        let lines: Vec<u64> = import.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![2, 3]);
+        assert!(import.errors[1].message.contains("2018-02-01"));
+    }
+
+    #[test]
+    fn response_is_written_in_windows_1251() {
+        let dt = Utc.ymd(2018, 2, 1).and_hms(0, 0, 0);
+        let mut partner = Partner::new("7707083893", "773601001", dt);
+        partner.state = Some(PartnerState::Active);
+        let rsp = NdsResponse {
+            dtact_fl: Utc.ymd(2018, 1, 30).and_hms(0, 0, 0),
+            dtact_ul: Utc.ymd(2018, 1, 31).and_hms(0, 0, 0),
+            partners: vec![partner],
+        };
+
+        let mut output = vec![];
+        write_response(&mut output, &rsp, &CsvOptions::default()).unwrap();
+
+        let expected = format!(
+            "INN;KPP;DT;State;Description;DTActFL;DTActUL\n\
+             7707083893;773601001;01.02.2018;0;{};30.01.2018;31.01.2018\n",
+            PartnerState::Active.description()
+        );
+        assert_eq!(output, windows_1251(&expected));
+    }
+
+    #[test]
+    fn characters_split_between_writes_are_encoded() {
+        let text = "ИНН;Дата";
+        let mut output = vec![];
+        {
+            let mut writer = Windows1251Writer::new(&mut output);
+            for chunk in text.as_bytes().chunks(3) {
+                writer.write_all(chunk).unwrap();
+            }
+            writer.flush().unwrap();
+        }
+
+        assert_eq!(output, windows_1251(text));
+    }
+}
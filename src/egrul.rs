@@ -0,0 +1,502 @@
+//! Search of the Unified State Register of Legal Entities (EGRUL) and
+//! of Individual Entrepreneurs (EGRIP) through the service
+//! [https://egrul.nalog.ru/](https://egrul.nalog.ru/)
+//!
+//! Available with the `egrul` feature. The search is asynchronous on the
+//! service side: the query is posted, then the result is polled until it
+//! is ready. The signed PDF extract of a found record is requested and
+//! downloaded the same way.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+use hyper::header::ContentType;
+
+use super::{error, http, validate, Result};
+use service::{self, parse_date, Call, Service, ServiceConfig};
+
+/// The default connection point of the service
+pub const EGRUL_URL: &'static str = "https://egrul.nalog.ru";
+
+const SEARCH: &'static str = "egrul_search";
+const EXTRACT: &'static str = "egrul_extract";
+
+/// Register the record belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegistryKind {
+    /// EGRUL, the register of legal entities
+    LegalEntity,
+    /// EGRIP, the register of individual entrepreneurs
+    Entrepreneur,
+}
+
+/// Head of the legal entity entitled to act without a power of attorney
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Director {
+    /// Position, e.g. `ГЕНЕРАЛЬНЫЙ ДИРЕКТОР`
+    pub position: Option<String>,
+    /// Full name
+    pub name: String,
+}
+
+/// Record of the register found by the search
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryRecord {
+    pub kind: RegistryKind,
+    pub inn: String,
+    /// OGRN of the legal entity or OGRNIP of the entrepreneur
+    pub ogrn: String,
+    /// The current KPP, only for legal entities
+    pub kpp: Option<String>,
+    /// Full name of the legal entity or name of the entrepreneur
+    pub name: String,
+    pub short_name: Option<String>,
+    pub address: Option<String>,
+    pub director: Option<Director>,
+    pub registration_date: Option<NaiveDate>,
+    /// Date the activity was terminated, if it was
+    pub liquidation_date: Option<NaiveDate>,
+    /// Token of the extract, see `EgrulClient::download_extract`;
+    /// valid for a limited time after the search
+    pub token: String,
+}
+
+/// Any answer of the service, only the fields of the step are set
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Reply {
+    t: Option<String>,
+    #[serde(rename = "captchaRequired")]
+    captcha_required: bool,
+    status: Option<String>,
+    rows: Option<Vec<Row>>,
+}
+
+/// Row of the search result, as sent by the service
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Row {
+    /// Address
+    a: Option<String>,
+    /// Short name
+    c: Option<String>,
+    /// Position and name of the director
+    g: Option<String>,
+    i: String,
+    /// `ul` for legal entities, `fl` for entrepreneurs
+    k: String,
+    n: String,
+    o: String,
+    p: Option<String>,
+    /// Registration date
+    r: Option<String>,
+    /// Liquidation date
+    e: Option<String>,
+    t: String,
+}
+
+/// Client of the EGRUL/EGRIP search
+#[derive(Debug)]
+pub struct EgrulClient {
+    service: Service,
+    poll_interval: Duration,
+    poll_attempts: u32,
+}
+
+/// Builder of the `EgrulClient`
+#[derive(Debug, Clone)]
+pub struct EgrulClientBuilder {
+    config: ServiceConfig,
+    poll_interval: Duration,
+    poll_attempts: u32,
+}
+
+impl RegistryRecord {
+    /// Returns `true` if the activity was terminated.
+    pub fn is_liquidated(&self) -> bool {
+        self.liquidation_date.is_some()
+    }
+
+    fn from_row(row: Row) -> RegistryRecord {
+        let kind = if row.k == "fl" {
+            RegistryKind::Entrepreneur
+        } else {
+            RegistryKind::LegalEntity
+        };
+
+        RegistryRecord {
+            kind: kind,
+            inn: row.i,
+            ogrn: row.o,
+            kpp: non_empty(row.p),
+            name: row.n,
+            short_name: non_empty(row.c),
+            address: non_empty(row.a),
+            director: non_empty(row.g).map(|g| Director::parse(&g)),
+            registration_date: non_empty(row.r).and_then(|r| parse_date(&r)),
+            liquidation_date: non_empty(row.e).and_then(|e| parse_date(&e)),
+            token: row.t,
+        }
+    }
+}
+
+impl Director {
+    /// Parses `POSITION: NAME` as shown by the service.
+    fn parse(value: &str) -> Director {
+        match value.find(':') {
+            Some(pos) => Director {
+                position: Some(value[..pos].trim().to_string()),
+                name: value[pos + 1..].trim().to_string(),
+            },
+            None => Director {
+                position: None,
+                name: value.trim().to_string(),
+            },
+        }
+    }
+}
+
+impl Default for EgrulClientBuilder {
+    fn default() -> EgrulClientBuilder {
+        EgrulClientBuilder {
+            config: ServiceConfig::new(EGRUL_URL),
+            poll_interval: Duration::from_secs(1),
+            poll_attempts: 30,
+        }
+    }
+}
+
+service_client!(EgrulClient, EgrulClientBuilder);
+
+impl EgrulClientBuilder {
+    /// Poll the result that is not ready at most `attempts` times,
+    /// every `interval`; 30 times every second by default.
+    pub fn poll(mut self, interval: Duration, attempts: u32) -> Self {
+        self.poll_interval = interval;
+        self.poll_attempts = attempts;
+        self
+    }
+
+    /// Create the client.
+    pub fn build(self) -> Result<EgrulClient> {
+        Ok(EgrulClient {
+            service: self.config.connect()?,
+            poll_interval: self.poll_interval,
+            poll_attempts: self.poll_attempts,
+        })
+    }
+}
+
+impl EgrulClient {
+    /// Searches the registers by INN, OGRN or OGRNIP.
+    pub fn search(&self, query: &str) -> Result<Vec<RegistryRecord>> {
+        let rsp = self.search_rows(query);
+        if let Err(ref e) = rsp {
+            self.service.metrics.error(SEARCH, e);
+        }
+
+        rsp
+    }
+
+    /// The record with the INN, OGRN or OGRNIP, if there is one.
+    pub fn find(&self, query: &str) -> Result<Option<RegistryRecord>> {
+        Ok(self.search(query)?.into_iter().next())
+    }
+
+    /// Writes the signed PDF extract of the record to the file.
+    ///
+    /// The extract is downloaded into `<path>.part` first and renamed
+    /// when complete, so a failed download does not leave a file behind.
+    pub fn download_extract<P>(&self, record: &RegistryRecord, path: P) -> Result<u64>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let mut part = path.as_os_str().to_owned();
+        part.push(".part");
+        let part = PathBuf::from(part);
+
+        let rsp = File::create(&part)
+            .map_err(error::Error::from)
+            .and_then(|mut file| self.write_extract(record, &mut file))
+            .and_then(|size| {
+                fs::rename(&part, path)?;
+                Ok(size)
+            });
+        if rsp.is_err() {
+            let _ = fs::remove_file(&part);
+        }
+
+        rsp
+    }
+
+    /// Writes the signed PDF extract of the record.
+    ///
+    /// Returns the size of the extract.
+    pub fn write_extract<W: Write>(&self, record: &RegistryRecord, writer: &mut W) -> Result<u64> {
+        let rsp = self.extract(record, writer);
+        if let Err(ref e) = rsp {
+            self.service.metrics.error(EXTRACT, e);
+        }
+
+        rsp
+    }
+
+    fn search_rows(&self, query: &str) -> Result<Vec<RegistryRecord>> {
+        let query = query.trim();
+        check_query(query)?;
+
+        let reply = self.call(SEARCH, "/", Some(&[("query", query)][..]))?;
+        let token = token(reply)?;
+
+        let path = format!("/search-result/{}", token);
+        let rows = self.poll(SEARCH, &path, |reply| reply.rows.take())?;
+
+        Ok(rows.into_iter().map(RegistryRecord::from_row).collect())
+    }
+
+    fn extract<W: Write>(&self, record: &RegistryRecord, writer: &mut W) -> Result<u64> {
+        let reply = self.call(EXTRACT, &format!("/vyp-request/{}", record.token), None)?;
+        let token = token(reply)?;
+
+        let path = format!("/vyp-status/{}", token);
+        self.poll(EXTRACT, &path, |reply| match reply.status {
+            Some(ref status) if status == "ready" => Some(()),
+            _ => None,
+        })?;
+
+        // Not repeated, a failed download may have written a part of the file
+        let url = format!("{}/vyp-download/{}", self.service.url, token);
+        let start = Instant::now();
+        let size = self.download(&url, writer);
+        let downloaded = size.as_ref().map(|size| *size as usize).unwrap_or(0);
+        self.service
+            .metrics
+            .http_request(EXTRACT, start.elapsed(), downloaded);
+
+        size
+    }
+
+    /// Copies the PDF served at the URL to the writer.
+    fn download<W: Write>(&self, url: &str, writer: &mut W) -> Result<u64> {
+        let mut response = http::get_stream(&self.service.http, url, &self.service.user_agent)?;
+
+        let is_pdf = response
+            .headers()
+            .get::<ContentType>()
+            .map_or(false, |content_type| {
+                content_type.0.type_() == "application" && content_type.0.subtype() == "pdf"
+            });
+        if !is_pdf {
+            return Err(error::Error::EgrulError(
+                "The service returned no PDF extract".into(),
+            ));
+        }
+
+        Ok(io::copy(&mut response, writer)?)
+    }
+
+    /// Requests the path until `ready` returns the result.
+    fn poll<T, F>(&self, method: &str, path: &str, mut ready: F) -> Result<T>
+    where
+        F: FnMut(&mut Reply) -> Option<T>,
+    {
+        for attempt in 0..self.poll_attempts.max(1) {
+            if attempt > 0 {
+                thread::sleep(self.poll_interval);
+            }
+
+            let mut reply = self.call(method, path, None)?;
+            if let Some(result) = ready(&mut reply) {
+                return Ok(result);
+            }
+            debug!("{} is not ready, status {:?}", path, reply.status);
+        }
+
+        Err(error::Error::EgrulError(format!(
+            "The result is not ready after {} attempts",
+            self.poll_attempts
+        )))
+    }
+
+    fn call(&self, method: &str, path: &str, form: Option<&[(&str, &str)]>) -> Result<Reply> {
+        let (path, call) = match form {
+            Some(form) => (path.to_string(), Call::Form(form)),
+            None => {
+                let now = Utc::now().timestamp() * 1000;
+                (format!("{}?r={}&_={}", path, now, now), Call::Get)
+            }
+        };
+
+        self.service.call_json(method, &path, call, |body| {
+            service::reported_error(body).map(error::Error::EgrulError)
+        })
+    }
+}
+
+/// The token of the reply, or the error if the service wants a captcha.
+fn token(reply: Reply) -> Result<String> {
+    if reply.captcha_required {
+        return Err(error::Error::EgrulError(
+            "The service requires a captcha, try again later".into(),
+        ));
+    }
+
+    reply
+        .t
+        .ok_or_else(|| error::Error::EgrulError("The service returned no token".into()))
+}
+
+/// Checks the length and control digits of INN, OGRN (13 digits)
+/// or OGRNIP (15 digits).
+fn check_query(query: &str) -> Result<()> {
+    let valid = match query.len() {
+        10 | 12 => validate::validate_inn(query).is_none(),
+        13 | 15 if query.chars().all(|c| c.is_ascii_digit()) => {
+            let (number, control) = query.split_at(query.len() - 1);
+            let divisor = if query.len() == 13 { 11 } else { 13 };
+            let remainder = number.parse::<u64>().map(|n| n % divisor % 10);
+            remainder.ok().map(|r| r.to_string()) == Some(control.to_string())
+        }
+        _ => false,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(error::Error::EgrulError(format!(
+            "Invalid INN, OGRN or OGRNIP {}",
+            query
+        )))
+    }
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.and_then(|value| {
+        if value.trim().is_empty() {
+            None
+        } else {
+            Some(value)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::{env, process};
+
+    use super::*;
+    use error::Error;
+    use mock::MockEgrulServer;
+
+    fn sberbank() -> RegistryRecord {
+        RegistryRecord {
+            kind: RegistryKind::LegalEntity,
+            inn: "7707083893".into(),
+            ogrn: "1027700132195".into(),
+            kpp: Some("773601001".into()),
+            name: "ПУБЛИЧНОЕ АКЦИОНЕРНОЕ ОБЩЕСТВО \"СБЕРБАНК РОССИИ\"".into(),
+            short_name: Some("ПАО СБЕРБАНК".into()),
+            address: Some("117312, Г.МОСКВА, УЛ. ВАВИЛОВА, Д.19".into()),
+            director: Some(Director {
+                position: Some("ПРЕЗИДЕНТ, ПРЕДСЕДАТЕЛЬ ПРАВЛЕНИЯ".into()),
+                name: "Греф Герман Оскарович".into(),
+            }),
+            registration_date: Some(NaiveDate::from_ymd(2002, 8, 16)),
+            liquidation_date: None,
+            token: String::new(),
+        }
+    }
+
+    #[test]
+    fn finds_by_inn_and_ogrn() {
+        let server = MockEgrulServer::start().unwrap();
+        server.add_record(sberbank());
+        server.set_pending(2);
+        let client = server.client().unwrap();
+
+        let record = client.find("7707083893").unwrap().unwrap();
+        assert_eq!(
+            RegistryRecord {
+                token: String::new(),
+                ..record.clone()
+            },
+            sberbank()
+        );
+        assert!(!record.is_liquidated());
+
+        let found = client.search("1027700132195").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].inn, "7707083893");
+
+        assert!(client.find("6648185610").unwrap().is_none());
+    }
+
+    #[test]
+    fn reports_captcha() {
+        let server = MockEgrulServer::start().unwrap();
+        server.set_captcha_required(true);
+
+        match server.client().unwrap().search("7707083893") {
+            Err(Error::EgrulError(ref msg)) => assert!(msg.contains("captcha")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn gives_up_polling() {
+        let server = MockEgrulServer::start().unwrap();
+        server.set_pending(10);
+        let client = server
+            .client_builder()
+            .poll(Duration::from_millis(1), 3)
+            .build()
+            .unwrap();
+
+        assert!(client.search("7707083893").is_err());
+    }
+
+    #[test]
+    fn downloads_the_extract() {
+        let server = MockEgrulServer::start().unwrap();
+        server.add_record(sberbank());
+        server.set_pending(1);
+        let client = server.client().unwrap();
+
+        let record = client.find("7707083893").unwrap().unwrap();
+        let mut pdf = Cursor::new(vec![]);
+        let size = client.write_extract(&record, &mut pdf).unwrap();
+
+        assert_eq!(size, pdf.get_ref().len() as u64);
+        assert!(pdf.get_ref().starts_with(b"%PDF-"));
+    }
+
+    #[test]
+    fn failed_download_leaves_no_file() {
+        let server = MockEgrulServer::start().unwrap();
+        server.add_record(sberbank());
+        let client = server.client().unwrap();
+        let dir = env::temp_dir().join(format!("npchk-egrul-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let record = client.find("7707083893").unwrap().unwrap();
+        let downloaded = client.download_extract(&record, dir.join("extract.pdf"));
+        server.set_captcha_required(true);
+        let failed = client.download_extract(&record, dir.join("captcha.pdf"));
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(downloaded.unwrap() > 0);
+        assert!(failed.is_err());
+        assert_eq!(files, vec!["extract.pdf"]);
+    }
+}
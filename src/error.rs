@@ -1,18 +1,62 @@
+#[cfg(feature = "async")]
+use hyper;
 use reqwest;
+use reqwest::StatusCode;
+use retry::Attempt;
 use rpser;
 use chrono;
+#[cfg(feature = "csv-io")]
+use csv;
 use std::{error as stderror, fmt, io, num};
+#[cfg(feature = "xlsx")]
+use zip;
+#[cfg(feature = "sqlite")]
+use rusqlite;
+#[cfg(feature = "serde_json")]
+use serde_json;
 
 #[derive(Debug)]
 pub enum Error {
     TooManyRecords,
     FnsError(String),
     ReqError(reqwest::Error),
+    #[cfg(feature = "async")]
+    HyperError(hyper::Error),
+    #[cfg(feature = "async")]
+    UriError(hyper::error::UriError),
+    HttpStatus(StatusCode),
+    /// The call was repeated and failed, `last` is the error of the final attempt
+    RetriesExhausted {
+        attempts: Vec<Attempt>,
+        last: Box<Error>,
+    },
     RpcError(rpser::RpcError),
     XmlError(rpser::xml::Error),
     ParseIntError(num::ParseIntError),
     ParseDateTimeError(chrono::ParseError),
     IoError(io::Error),
+    #[cfg(feature = "csv-io")]
+    CsvError(csv::Error),
+    #[cfg(feature = "csv-io")]
+    ColumnNotFound(String),
+    #[cfg(feature = "xlsx")]
+    ZipError(zip::result::ZipError),
+    #[cfg(feature = "sqlite")]
+    SqliteError(rusqlite::Error),
+    #[cfg(feature = "serde_json")]
+    JsonError(serde_json::Error),
+    #[cfg(feature = "watch")]
+    ScheduleError(String),
+    #[cfg(feature = "npd")]
+    NpdError { code: String, message: String },
+    #[cfg(feature = "egrul")]
+    EgrulError(String),
+    #[cfg(feature = "pb")]
+    PbError(String),
+    #[cfg(feature = "bi")]
+    BiError(String),
+    #[cfg(feature = "msp")]
+    MspError(String),
 }
 
 impl fmt::Display for Error {
@@ -24,11 +68,54 @@ impl fmt::Display for Error {
             ),
             Error::FnsError(ref err_msg) => write!(f, "{}", err_msg),
             Error::ReqError(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "async")]
+            Error::HyperError(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "async")]
+            Error::UriError(ref e) => fmt::Display::fmt(e, f),
+            Error::HttpStatus(ref status) => write!(f, "Unexpected HTTP status {}", status),
+            Error::RetriesExhausted {
+                ref attempts,
+                ref last,
+            } => {
+                write!(f, "{} (", last)?;
+                for (i, attempt) in attempts.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", attempt)?;
+                }
+                write!(f, ")")
+            }
             Error::RpcError(ref e) => fmt::Display::fmt(e, f),
             Error::XmlError(ref e) => fmt::Display::fmt(e, f),
             Error::ParseIntError(ref e) => fmt::Display::fmt(e, f),
             Error::ParseDateTimeError(ref e) => fmt::Display::fmt(e, f),
             Error::IoError(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "csv-io")]
+            Error::CsvError(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "csv-io")]
+            Error::ColumnNotFound(ref name) => write!(f, "Column not found: {}", name),
+            #[cfg(feature = "xlsx")]
+            Error::ZipError(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "sqlite")]
+            Error::SqliteError(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "serde_json")]
+            Error::JsonError(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "watch")]
+            Error::ScheduleError(ref msg) => write!(f, "Invalid schedule {}", msg),
+            #[cfg(feature = "npd")]
+            Error::NpdError {
+                ref code,
+                ref message,
+            } => write!(f, "{} ({})", message, code),
+            #[cfg(feature = "egrul")]
+            Error::EgrulError(ref msg) => write!(f, "{}", msg),
+            #[cfg(feature = "pb")]
+            Error::PbError(ref msg) => write!(f, "{}", msg),
+            #[cfg(feature = "bi")]
+            Error::BiError(ref msg) => write!(f, "{}", msg),
+            #[cfg(feature = "msp")]
+            Error::MspError(ref msg) => write!(f, "{}", msg),
         }
     }
 }
@@ -43,11 +130,43 @@ impl stderror::Error for Error {
                 "The service reported an error processing the request"
             }
             Error::ReqError(ref e) => e.description(),
+            #[cfg(feature = "async")]
+            Error::HyperError(ref e) => e.description(),
+            #[cfg(feature = "async")]
+            Error::UriError(ref e) => e.description(),
+            Error::HttpStatus(_) => "Unexpected HTTP status",
+            Error::RetriesExhausted { ref last, .. } => last.description(),
             Error::RpcError(ref e) => e.description(),
             Error::XmlError(ref e) => e.description(),
             Error::ParseIntError(ref e) => e.description(),
             Error::ParseDateTimeError(ref e) => e.description(),
             Error::IoError(ref e) => e.description(),
+            #[cfg(feature = "csv-io")]
+            Error::CsvError(ref e) => e.description(),
+            #[cfg(feature = "csv-io")]
+            Error::ColumnNotFound(_) => "Column not found",
+            #[cfg(feature = "xlsx")]
+            Error::ZipError(ref e) => e.description(),
+            #[cfg(feature = "sqlite")]
+            Error::SqliteError(ref e) => e.description(),
+            #[cfg(feature = "serde_json")]
+            Error::JsonError(ref e) => e.description(),
+            #[cfg(feature = "watch")]
+            Error::ScheduleError(_) => "Invalid schedule",
+            #[cfg(feature = "npd")]
+            Error::NpdError { .. } => "The NPD service reported an error processing the request",
+            #[cfg(feature = "egrul")]
+            Error::EgrulError(_) => "The EGRUL service reported an error processing the request",
+            #[cfg(feature = "pb")]
+            Error::PbError(_) => {
+                "The \"Transparent business\" service reported an error processing the request"
+            }
+            #[cfg(feature = "bi")]
+            Error::BiError(_) => {
+                "The account blocking service reported an error processing the request"
+            }
+            #[cfg(feature = "msp")]
+            Error::MspError(_) => "The MSP register lookup failed",
         }
     }
 
@@ -56,11 +175,114 @@ impl stderror::Error for Error {
             Error::TooManyRecords => None,
             Error::FnsError(_) => None,
             Error::ReqError(ref e) => e.cause(),
+            #[cfg(feature = "async")]
+            Error::HyperError(ref e) => e.cause(),
+            #[cfg(feature = "async")]
+            Error::UriError(ref e) => e.cause(),
+            Error::HttpStatus(_) => None,
+            Error::RetriesExhausted { ref last, .. } => Some(&**last),
             Error::RpcError(ref e) => e.cause(),
             Error::XmlError(ref e) => e.cause(),
             Error::ParseIntError(ref e) => e.cause(),
             Error::ParseDateTimeError(ref e) => e.cause(),
             Error::IoError(ref e) => e.cause(),
+            #[cfg(feature = "csv-io")]
+            Error::CsvError(ref e) => e.cause(),
+            #[cfg(feature = "csv-io")]
+            Error::ColumnNotFound(_) => None,
+            #[cfg(feature = "xlsx")]
+            Error::ZipError(ref e) => e.cause(),
+            #[cfg(feature = "sqlite")]
+            Error::SqliteError(ref e) => e.cause(),
+            #[cfg(feature = "serde_json")]
+            Error::JsonError(ref e) => e.cause(),
+            #[cfg(feature = "watch")]
+            Error::ScheduleError(_) => None,
+            #[cfg(feature = "npd")]
+            Error::NpdError { .. } => None,
+            #[cfg(feature = "egrul")]
+            Error::EgrulError(_) => None,
+            #[cfg(feature = "pb")]
+            Error::PbError(_) => None,
+            #[cfg(feature = "bi")]
+            Error::BiError(_) => None,
+            #[cfg(feature = "msp")]
+            Error::MspError(_) => None,
+        }
+    }
+}
+
+impl Error {
+    /// Name of the variant, e.g. `"HttpStatus"`, for counting the errors.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Error::TooManyRecords => "TooManyRecords",
+            Error::FnsError(_) => "FnsError",
+            Error::ReqError(_) => "ReqError",
+            #[cfg(feature = "async")]
+            Error::HyperError(_) => "HyperError",
+            #[cfg(feature = "async")]
+            Error::UriError(_) => "UriError",
+            Error::HttpStatus(_) => "HttpStatus",
+            Error::RetriesExhausted { .. } => "RetriesExhausted",
+            Error::RpcError(_) => "RpcError",
+            Error::XmlError(_) => "XmlError",
+            Error::ParseIntError(_) => "ParseIntError",
+            Error::ParseDateTimeError(_) => "ParseDateTimeError",
+            Error::IoError(_) => "IoError",
+            #[cfg(feature = "csv-io")]
+            Error::CsvError(_) => "CsvError",
+            #[cfg(feature = "csv-io")]
+            Error::ColumnNotFound(_) => "ColumnNotFound",
+            #[cfg(feature = "xlsx")]
+            Error::ZipError(_) => "ZipError",
+            #[cfg(feature = "sqlite")]
+            Error::SqliteError(_) => "SqliteError",
+            #[cfg(feature = "serde_json")]
+            Error::JsonError(_) => "JsonError",
+            #[cfg(feature = "watch")]
+            Error::ScheduleError(_) => "ScheduleError",
+            #[cfg(feature = "npd")]
+            Error::NpdError { .. } => "NpdError",
+            #[cfg(feature = "egrul")]
+            Error::EgrulError(_) => "EgrulError",
+            #[cfg(feature = "pb")]
+            Error::PbError(_) => "PbError",
+            #[cfg(feature = "bi")]
+            Error::BiError(_) => "BiError",
+            #[cfg(feature = "msp")]
+            Error::MspError(_) => "MspError",
+        }
+    }
+
+    /// Returns `true` if repeating the call may succeed: connection failures,
+    /// timeouts, HTTP 5xx, SOAP faults caused by the server and the rate limit
+    /// or unavailability reported by the NPD service.
+    ///
+    /// Errors reported by the service about the request data are never transient.
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            Error::ReqError(ref e) => e.is_http() || e.is_server_error(),
+            #[cfg(feature = "async")]
+            Error::HyperError(_) => true,
+            Error::IoError(ref e) => match e.kind() {
+                io::ErrorKind::TimedOut
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::Interrupted => true,
+                _ => false,
+            },
+            Error::HttpStatus(ref status) => status.is_server_error(),
+            Error::RpcError(rpser::RpcError::Fault { ref fault_code, .. }) => {
+                fault_code.ends_with("Server")
+            }
+            #[cfg(feature = "npd")]
+            Error::NpdError { ref code, .. } => {
+                code == ::npd::LIMITED_ERROR || code == ::npd::UNAVAILABLE_ERROR
+            }
+            _ => false,
         }
     }
 }
@@ -71,6 +293,20 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+#[cfg(feature = "async")]
+impl From<hyper::Error> for Error {
+    fn from(other: hyper::Error) -> Error {
+        Error::HyperError(other)
+    }
+}
+
+#[cfg(feature = "async")]
+impl From<hyper::error::UriError> for Error {
+    fn from(other: hyper::error::UriError) -> Error {
+        Error::UriError(other)
+    }
+}
+
 impl From<rpser::RpcError> for Error {
     fn from(other: rpser::RpcError) -> Error {
         Error::RpcError(other)
@@ -100,3 +336,31 @@ impl From<io::Error> for Error {
         Error::IoError(other)
     }
 }
+
+#[cfg(feature = "csv-io")]
+impl From<csv::Error> for Error {
+    fn from(other: csv::Error) -> Error {
+        Error::CsvError(other)
+    }
+}
+
+#[cfg(feature = "xlsx")]
+impl From<zip::result::ZipError> for Error {
+    fn from(other: zip::result::ZipError) -> Error {
+        Error::ZipError(other)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(other: rusqlite::Error) -> Error {
+        Error::SqliteError(other)
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl From<serde_json::Error> for Error {
+    fn from(other: serde_json::Error) -> Error {
+        Error::JsonError(other)
+    }
+}
@@ -0,0 +1,548 @@
+//! JSON gateway in front of the service.
+//!
+//! Available with the `server` feature. The gateway is independent of the
+//! HTTP server: it takes the method, path, query and body of the request
+//! and returns the status and JSON body of the response. The `npchk-server`
+//! binary serves it over HTTP.
+//!
+//! Endpoints:
+//!
+//! * `POST /check` with `{"partners": [{"inn": "...", "kpp": "...", "date": "..."}]}`
+//!   checks the list of partners, any number of them;
+//! * `GET /partner/{inn}/{kpp}?date=...` checks one partner, the KPP
+//!   may be omitted for individual entrepreneurs;
+//! * `GET /metrics` returns the metrics of the client in the Prometheus
+//!   text format, if the gateway was created `with_metrics`.
+//!
+//! Dates are `dd.mm.yyyy` or `yyyy-mm-dd`, today by default. Errors are
+//! reported as `application/problem+json` (RFC 7807).
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use serde_json;
+
+use super::{error, validate, CheckOptions, NpchkClient, Partner, PartnerState};
+use cache::{Cache, CachedPartner, CachedResponse, MemoryCache};
+use metrics::PrometheusMetrics;
+
+const JSON: &'static str = "application/json";
+const PROBLEM_JSON: &'static str = "application/problem+json";
+const PROMETHEUS_TEXT: &'static str = "text/plain; version=0.0.4";
+
+/// The number of the results the gateway keeps by default
+pub const DEFAULT_CACHE_CAPACITY: usize = 100_000;
+
+/// Response of the gateway
+#[derive(Debug, Clone, PartialEq)]
+pub struct GatewayResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+/// Error reported to the client, RFC 7807
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+}
+
+/// Partner of the `POST /check` request
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckItem {
+    pub inn: String,
+    #[serde(default)]
+    pub kpp: String,
+    pub date: Option<String>,
+}
+
+/// Body of the `POST /check` request
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckRequest {
+    pub partners: Vec<CheckItem>,
+}
+
+/// The checked partner
+#[derive(Debug, Clone, Serialize)]
+pub struct PartnerResult {
+    pub inn: String,
+    pub kpp: String,
+    pub date: String,
+    pub state: Option<PartnerState>,
+    pub active: bool,
+    /// The result was taken from the cache of the gateway
+    pub cached: bool,
+}
+
+/// Body of the successful response
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub dtact_fl: String,
+    pub dtact_ul: String,
+    pub partners: Vec<PartnerResult>,
+}
+
+/// Checks the partners for the JSON requests, keeping the results
+/// in memory until the service refreshes its data or the time to live expires
+///
+/// At most `DEFAULT_CACHE_CAPACITY` results are kept, see `with_cache_capacity`.
+#[derive(Debug)]
+pub struct Gateway {
+    client: NpchkClient,
+    cache: MemoryCache,
+    ttl: Duration,
+    metrics: Option<Arc<PrometheusMetrics>>,
+}
+
+impl GatewayResponse {
+    fn json<T: ::serde::Serialize>(status: u16, value: &T) -> GatewayResponse {
+        match serde_json::to_string(value) {
+            Ok(body) => GatewayResponse {
+                status: status,
+                content_type: JSON,
+                body: body,
+            },
+            Err(e) => Problem::new(500, "Internal Server Error", e.to_string()).into(),
+        }
+    }
+
+    /// The response to the request with the body larger than `limit` bytes.
+    pub fn body_too_large(limit: u64) -> GatewayResponse {
+        Problem::new(
+            413,
+            "Payload Too Large",
+            format!("The body of the request exceeds {} bytes", limit),
+        ).with_type("payload-too-large")
+            .into()
+    }
+}
+
+impl From<Problem> for GatewayResponse {
+    fn from(problem: Problem) -> GatewayResponse {
+        GatewayResponse {
+            status: problem.status,
+            content_type: PROBLEM_JSON,
+            body: serde_json::to_string(&problem).unwrap_or_default(),
+        }
+    }
+}
+
+impl Problem {
+    fn new<S: Into<String>>(status: u16, title: &str, detail: S) -> Problem {
+        Problem {
+            problem_type: "about:blank".into(),
+            title: title.into(),
+            status: status,
+            detail: detail.into(),
+        }
+    }
+
+    fn with_type(mut self, problem_type: &str) -> Problem {
+        self.problem_type = format!("urn:npchk:problem:{}", problem_type);
+        self
+    }
+
+    /// The problem reported for the error of the check.
+    ///
+    /// Errors of the request are client errors. The service being
+    /// unavailable is 503, any other failure of the service is 502.
+    pub fn from_error(e: &error::Error) -> Problem {
+        use error::Error;
+
+        let detail = e.to_string();
+        match *e {
+            Error::RetriesExhausted { ref last, .. } => Problem {
+                detail: detail,
+                ..Problem::from_error(last)
+            },
+            Error::TooManyRecords => {
+                Problem::new(413, "Too Many Records", detail).with_type("too-many-records")
+            }
+            Error::JsonError(_) => {
+                Problem::new(400, "Invalid Request", detail).with_type("invalid-request")
+            }
+            Error::FnsError(_) => {
+                Problem::new(502, "Service Error", detail).with_type("service-error")
+            }
+            _ if e.is_transient() => {
+                Problem::new(503, "Service Unavailable", detail).with_type("service-unavailable")
+            }
+            Error::HttpStatus(_)
+            | Error::RpcError(_)
+            | Error::XmlError(_)
+            | Error::ParseIntError(_)
+            | Error::ParseDateTimeError(_)
+            | Error::ReqError(_) => {
+                Problem::new(502, "Bad Gateway", detail).with_type("invalid-response")
+            }
+            _ => Problem::new(500, "Internal Server Error", detail),
+        }
+    }
+}
+
+impl Gateway {
+    /// Creates the gateway, keeping the results for the time to live.
+    pub fn new(client: NpchkClient, ttl: Duration) -> Gateway {
+        Gateway {
+            client: client,
+            cache: MemoryCache::with_capacity(DEFAULT_CACHE_CAPACITY),
+            ttl: ttl,
+            metrics: None,
+        }
+    }
+
+    /// Keep at most `capacity` results, dropping the oldest ones
+    /// when the cache is full.
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache = MemoryCache::with_capacity(capacity);
+        self
+    }
+
+    /// Serve the metrics at `GET /metrics`.
+    ///
+    /// The metrics should be the ones the client was built with.
+    pub fn with_metrics(mut self, metrics: Arc<PrometheusMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Handles the request.
+    pub fn handle(
+        &self,
+        method: &str,
+        path: &str,
+        query: Option<&str>,
+        body: &[u8],
+    ) -> GatewayResponse {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        if let (&["metrics"], Some(metrics)) = (segments.as_slice(), self.metrics.as_ref()) {
+            return if method == "GET" {
+                GatewayResponse {
+                    status: 200,
+                    content_type: PROMETHEUS_TEXT,
+                    body: metrics.render(),
+                }
+            } else {
+                Problem::new(405, "Method Not Allowed", format!("{} {}", method, path)).into()
+            };
+        }
+
+        let result = match (method, segments.as_slice()) {
+            ("POST", &["check"]) => self.check(body),
+            ("GET", &["partner", inn]) => self.partner(inn, "", query),
+            ("GET", &["partner", inn, kpp]) => self.partner(inn, kpp, query),
+            (_, &["check"]) | (_, &["partner", _]) | (_, &["partner", _, _]) => Err(
+                Problem::new(405, "Method Not Allowed", format!("{} {}", method, path)),
+            ),
+            _ => Err(Problem::new(404, "Not Found", path)),
+        };
+
+        match result {
+            Ok(result) => GatewayResponse::json(200, &result),
+            Err(problem) => problem.into(),
+        }
+    }
+
+    fn check(&self, body: &[u8]) -> ::std::result::Result<CheckResult, Problem> {
+        let request: CheckRequest = serde_json::from_slice(body)
+            .map_err(|e| Problem::from_error(&error::Error::from(e)))?;
+        if request.partners.is_empty() {
+            return Err(Problem::new(400, "Invalid Request", "No partners to check")
+                .with_type("invalid-request"));
+        }
+
+        let mut partners = vec![];
+        for (index, item) in request.partners.into_iter().enumerate() {
+            let dt = parse_date(item.date.as_ref().map(|s| s.as_str())).map_err(|detail| {
+                Problem::new(400, "Invalid Request", format!("partners[{}]: {}", index, detail))
+                    .with_type("invalid-request")
+            })?;
+            partners.push(Partner::new(item.inn, item.kpp, dt));
+        }
+
+        self.check_partners(partners)
+    }
+
+    fn partner(
+        &self,
+        inn: &str,
+        kpp: &str,
+        query: Option<&str>,
+    ) -> ::std::result::Result<CheckResult, Problem> {
+        let invalid = |detail: String| {
+            Problem::new(400, "Invalid Request", detail).with_type("invalid-request")
+        };
+        let date = match query {
+            Some(query) => query_value(query, "date").map_err(&invalid)?,
+            None => None,
+        };
+        let dt = parse_date(date.as_ref().map(|date| date.as_str())).map_err(&invalid)?;
+
+        self.check_partners(vec![Partner::new(inn.to_string(), kpp.to_string(), dt)])
+    }
+
+    /// Checks the partners passing the local validation through the cache,
+    /// the others get the state of the failed validation.
+    fn check_partners(
+        &self,
+        partners: Vec<Partner<'static>>,
+    ) -> ::std::result::Result<CheckResult, Problem> {
+        let mut rejected: Vec<Option<CachedPartner<'static>>> = vec![];
+        let mut valid: Vec<Partner<'static>> = vec![];
+        for mut p in partners {
+            match validate::validate(&p) {
+                Some(state) => {
+                    p.state = Some(state);
+                    rejected.push(Some(CachedPartner {
+                        partner: p,
+                        from_cache: false,
+                    }));
+                }
+                None => {
+                    rejected.push(None);
+                    valid.push(p);
+                }
+            }
+        }
+
+        let rsp = if valid.is_empty() {
+            let (dtact_fl, dtact_ul) = self.cache
+                .actuality()
+                .ok()
+                .and_then(|actuality| actuality)
+                .unwrap_or_else(|| (Utc::now(), Utc::now()));
+            CachedResponse {
+                dtact_fl: dtact_fl,
+                dtact_ul: dtact_ul,
+                partners: vec![],
+            }
+        } else {
+            self.client
+                .check_fns_cached(&self.cache, valid, self.ttl, &CheckOptions::default())
+                .map_err(|e| {
+                    error!("Check of the partners failed: {}", e);
+                    Problem::from_error(&e)
+                })?
+        };
+
+        let mut checked = rsp.partners.into_iter();
+        let partners = rejected
+            .into_iter()
+            .filter_map(|local| local.or_else(|| checked.next()))
+            .collect();
+
+        Ok(CheckResult::from(CachedResponse { partners: partners, ..rsp }))
+    }
+}
+
+impl<'a> From<CachedResponse<'a>> for CheckResult {
+    fn from(rsp: CachedResponse<'a>) -> CheckResult {
+        CheckResult {
+            dtact_fl: rsp.dtact_fl.format("%d.%m.%Y").to_string(),
+            dtact_ul: rsp.dtact_ul.format("%d.%m.%Y").to_string(),
+            partners: rsp.partners.into_iter().map(PartnerResult::from).collect(),
+        }
+    }
+}
+
+impl<'a> From<CachedPartner<'a>> for PartnerResult {
+    fn from(cached: CachedPartner<'a>) -> PartnerResult {
+        let p = cached.partner;
+        PartnerResult {
+            inn: p.inn.into_owned(),
+            kpp: p.kpp.into_owned(),
+            date: p.dt.format("%d.%m.%Y").to_string(),
+            state: p.state,
+            active: p.state.map_or(false, |state| state.is_active()),
+            cached: cached.from_cache,
+        }
+    }
+}
+
+/// The percent-decoded value of the query parameter.
+fn query_value(query: &str, name: &str) -> ::std::result::Result<Option<String>, String> {
+    for pair in query.split('&') {
+        let mut pair = pair.splitn(2, '=');
+        if let (Some(key), Some(value)) = (pair.next(), pair.next()) {
+            if percent_decode(key)? == name {
+                return percent_decode(value).map(Some);
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Decodes `%XX` escapes and `+` as a space, as in `application/x-www-form-urlencoded`.
+fn percent_decode(value: &str) -> ::std::result::Result<String, String> {
+    let invalid = || format!("Invalid escape in the query {:?}", value);
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'%' => {
+                let high = input.next().and_then(|b| (b as char).to_digit(16));
+                let low = input.next().and_then(|b| (b as char).to_digit(16));
+                match (high, low) {
+                    (Some(high), Some(low)) => bytes.push((high * 16 + low) as u8),
+                    _ => return Err(invalid()),
+                }
+            }
+            b'+' => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+fn parse_date(value: Option<&str>) -> ::std::result::Result<DateTime<Utc>, String> {
+    let value = match value {
+        Some(value) if !value.trim().is_empty() => value.trim(),
+        _ => return Ok(Utc::today().and_hms(0, 0, 0)),
+    };
+
+    NaiveDate::parse_from_str(value, "%d.%m.%Y")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d"))
+        .map(|date| Utc.from_utc_date(&date).and_hms(0, 0, 0))
+        .map_err(|_| format!("Invalid date {:?}, expected dd.mm.yyyy or yyyy-mm-dd", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::{Behavior, MockNpchkServer};
+
+    fn gateway(server: &MockNpchkServer) -> Gateway {
+        Gateway::new(server.client().unwrap(), Duration::from_secs(3600))
+    }
+
+    #[test]
+    fn checks_the_list() {
+        let server = MockNpchkServer::start().unwrap();
+        server.set_inn_state("6648185610", 3);
+        let gateway = gateway(&server);
+
+        let body = br#"{"partners": [
+            {"inn": "7707083893", "kpp": "773601001", "date": "01.02.2018"},
+            {"inn": "6648185610", "kpp": "662301001", "date": "2018-02-01"},
+            {"inn": "7707083890", "kpp": "773601001"}
+        ]}"#;
+        let rsp = gateway.handle("POST", "/check", None, body);
+        assert_eq!(rsp.status, 200);
+
+        let result: serde_json::Value = serde_json::from_str(&rsp.body).unwrap();
+        let partners = result["partners"].as_array().unwrap();
+        assert_eq!(partners.len(), 3);
+        assert_eq!(partners[0]["state"]["code"], 0);
+        assert_eq!(partners[0]["active"], true);
+        assert_eq!(partners[1]["state"]["code"], 3);
+        assert_eq!(partners[2]["state"]["code"], 5);
+
+        let rsp = gateway.handle("POST", "/check", None, body);
+        let result: serde_json::Value = serde_json::from_str(&rsp.body).unwrap();
+        assert_eq!(result["partners"][0]["cached"], true);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn checks_one_partner() {
+        let server = MockNpchkServer::start().unwrap();
+        let gateway = gateway(&server);
+
+        let path = "/partner/7707083893/773601001";
+        let rsp = gateway.handle("GET", path, Some("date=01.02.2018"), b"");
+        assert_eq!(rsp.status, 200);
+
+        let result: serde_json::Value = serde_json::from_str(&rsp.body).unwrap();
+        assert_eq!(result["partners"][0]["date"], "01.02.2018");
+
+        let rsp = gateway.handle("GET", path, Some("date=2018%2D02%2D01"), b"");
+        let result: serde_json::Value = serde_json::from_str(&rsp.body).unwrap();
+        assert_eq!(result["partners"][0]["date"], "01.02.2018");
+    }
+
+    #[test]
+    fn invalid_partner_gets_the_state_of_the_validation() {
+        let server = MockNpchkServer::start().unwrap();
+        let gateway = gateway(&server);
+
+        let rsp = gateway.handle("GET", "/partner/7707083890/773601001", None, b"");
+        assert_eq!(rsp.status, 200);
+
+        let result: serde_json::Value = serde_json::from_str(&rsp.body).unwrap();
+        assert_eq!(result["partners"][0]["state"]["code"], 5);
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn query_values_are_decoded() {
+        assert_eq!(query_value("a=1&date=01%2E02%2E2018", "date"), Ok(Some("01.02.2018".into())));
+        assert_eq!(query_value("da%74e=a+b", "date"), Ok(Some("a b".into())));
+        assert_eq!(query_value("dates=1", "date"), Ok(None));
+        assert!(query_value("date=%zz", "date").is_err());
+        assert!(query_value("date=%ff", "date").is_err());
+    }
+
+    #[test]
+    fn reports_problems() {
+        let server = MockNpchkServer::start().unwrap();
+        let gateway = gateway(&server);
+
+        let path = "/partner/7707083893/773601001";
+        let rsp = gateway.handle("GET", path, Some("date=32.01.2018"), b"");
+        assert_eq!(rsp.status, 400);
+        assert_eq!(rsp.content_type, PROBLEM_JSON);
+
+        let rsp = gateway.handle("GET", path, Some("date=%3"), b"");
+        assert_eq!(rsp.status, 400);
+
+        let rsp = gateway.handle("POST", "/check", None, b"{");
+        assert_eq!(rsp.status, 400);
+
+        let rsp = gateway.handle("GET", "/check", None, b"");
+        assert_eq!(rsp.status, 405);
+
+        let rsp = gateway.handle("GET", "/unknown", None, b"");
+        assert_eq!(rsp.status, 404);
+
+        let rsp = gateway.handle("GET", "/metrics", None, b"");
+        assert_eq!(rsp.status, 404);
+
+        server.respond_with(Behavior::ErrMsg("Internal error".into()));
+        let rsp = gateway.handle("GET", "/partner/7707083893/773601001", None, b"");
+        assert_eq!(rsp.status, 502);
+
+        server.respond_with(Behavior::Status(503));
+        let rsp = gateway.handle("GET", "/partner/4205036750/420501001", None, b"");
+        assert_eq!(rsp.status, 503);
+
+        let problem: Problem = serde_json::from_str(&rsp.body).unwrap();
+        assert_eq!(problem.problem_type, "urn:npchk:problem:service-unavailable");
+    }
+
+    #[test]
+    fn serves_metrics() {
+        let server = MockNpchkServer::start().unwrap();
+        let metrics = Arc::new(PrometheusMetrics::new());
+        let client = server
+            .client_builder()
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+        let gateway = Gateway::new(client, Duration::from_secs(3600)).with_metrics(metrics);
+
+        gateway.handle("GET", "/partner/7707083893/773601001", None, b"");
+
+        let rsp = gateway.handle("GET", "/metrics", None, b"");
+        assert_eq!(rsp.status, 200);
+        assert_eq!(rsp.content_type, PROMETHEUS_TEXT);
+        assert!(rsp.body.contains("npchk_results_total{state=\"0\"} 1\n"));
+    }
+}
@@ -0,0 +1,420 @@
+//! History of the partner states and changes between check runs.
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteHistory;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use chrono::prelude::*;
+
+use super::{NdsResponse, Partner, PartnerKind, PartnerState, Result};
+
+/// The state of the partner recorded after a check
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryRecord {
+    pub inn: String,
+    pub kpp: String,
+    /// Date on which the state was requested
+    pub date: NaiveDate,
+    /// The state code returned by the service
+    pub state: i32,
+    /// Data actuality date for individual entrepreneurs
+    pub dtact_fl: DateTime<Utc>,
+    /// Data actuality date for legal entities
+    pub dtact_ul: DateTime<Utc>,
+    /// Time of the check
+    pub checked_at: DateTime<Utc>,
+}
+
+/// Storage of the history of the partner states
+pub trait HistoryStore {
+    /// Appends the records to the history.
+    fn record(&self, records: &[HistoryRecord]) -> Result<()>;
+
+    /// The latest record of the partner.
+    fn latest(&self, inn: &str, kpp: &str) -> Result<Option<HistoryRecord>>;
+
+    /// All records of the partner, oldest first.
+    fn history(&self, inn: &str, kpp: &str) -> Result<Vec<HistoryRecord>>;
+}
+
+/// Kind of the change of the partner state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ChangeKind {
+    /// The partner had a valid status and lost it
+    BecameInactive,
+    /// The partner got a valid status
+    BecameActive,
+    /// The reason code of registration no longer matches
+    KppMismatch,
+    /// The INN is no longer registered
+    NotRegistered,
+    /// Any other change of the state
+    StateChanged,
+    /// The partner was not checked before
+    Appeared,
+    /// The partner is missing from the latest check
+    Disappeared,
+}
+
+/// Change of the partner state between two checks
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChangeEvent {
+    pub inn: String,
+    pub kpp: String,
+    /// Date of the later check, of the earlier one if the partner disappeared
+    #[cfg_attr(feature = "serde", serde(with = "::models::serde_date"))]
+    pub dt: DateTime<Utc>,
+    pub kind: ChangeKind,
+    /// The state in the earlier check
+    pub previous: Option<PartnerState>,
+    /// The state in the later check
+    pub current: Option<PartnerState>,
+}
+
+/// History kept in memory for the lifetime of the process
+#[derive(Debug, Default)]
+pub struct MemoryHistory {
+    records: Mutex<HashMap<(String, String), Vec<HistoryRecord>>>,
+}
+
+impl HistoryRecord {
+    /// Creates the record of the checked partner.
+    pub fn from_partner(
+        p: &Partner,
+        dtact_fl: DateTime<Utc>,
+        dtact_ul: DateTime<Utc>,
+        checked_at: DateTime<Utc>,
+    ) -> HistoryRecord {
+        HistoryRecord {
+            inn: p.inn.to_string(),
+            kpp: p.kpp.to_string(),
+            date: p.dt.date().naive_utc(),
+            state: p.state.map_or(-1, |state| state.code()),
+            dtact_fl: dtact_fl,
+            dtact_ul: dtact_ul,
+            checked_at: checked_at,
+        }
+    }
+
+    /// The recorded state of the partner.
+    pub fn partner_state(&self) -> PartnerState {
+        PartnerState::from_code(PartnerKind::from_inn(&self.inn), self.state)
+    }
+}
+
+impl ChangeKind {
+    /// Classifies the change of the state, `None` if the state did not change.
+    pub fn classify(previous: PartnerState, current: PartnerState) -> Option<ChangeKind> {
+        if previous == current {
+            return None;
+        }
+
+        Some(match current {
+            PartnerState::NotRegistered => ChangeKind::NotRegistered,
+            PartnerState::KppMismatch => ChangeKind::KppMismatch,
+            PartnerState::Active => ChangeKind::BecameActive,
+            _ if previous == PartnerState::Active => ChangeKind::BecameInactive,
+            _ => ChangeKind::StateChanged,
+        })
+    }
+}
+
+impl fmt::Display for ChangeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}: {:?}",
+            self.inn,
+            self.kpp,
+            self.dt.format("%d.%m.%Y"),
+            self.kind
+        )?;
+        if let Some(ref previous) = self.previous {
+            write!(f, ", was {}", previous)?;
+        }
+        if let Some(ref current) = self.current {
+            write!(f, ", now {}", current)?;
+        }
+        Ok(())
+    }
+}
+
+impl MemoryHistory {
+    pub fn new() -> MemoryHistory {
+        MemoryHistory::default()
+    }
+}
+
+impl HistoryStore for MemoryHistory {
+    fn record(&self, records: &[HistoryRecord]) -> Result<()> {
+        let mut history = self.records.lock().unwrap();
+        for record in records {
+            history
+                .entry((record.inn.clone(), record.kpp.clone()))
+                .or_insert_with(Vec::new)
+                .push(record.clone());
+        }
+        Ok(())
+    }
+
+    fn latest(&self, inn: &str, kpp: &str) -> Result<Option<HistoryRecord>> {
+        Ok(self.records
+            .lock()
+            .unwrap()
+            .get(&(inn.to_string(), kpp.to_string()))
+            .and_then(|records| records.last().cloned()))
+    }
+
+    fn history(&self, inn: &str, kpp: &str) -> Result<Vec<HistoryRecord>> {
+        Ok(self.records
+            .lock()
+            .unwrap()
+            .get(&(inn.to_string(), kpp.to_string()))
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// Records the states of the checked partners.
+pub fn record_response<S: HistoryStore>(
+    store: &S,
+    rsp: &NdsResponse,
+    checked_at: DateTime<Utc>,
+) -> Result<()> {
+    let records: Vec<HistoryRecord> = rsp.partners
+        .iter()
+        .filter(|p| p.state.is_some())
+        .map(|p| HistoryRecord::from_partner(p, rsp.dtact_fl, rsp.dtact_ul, checked_at))
+        .collect();
+
+    store.record(&records)
+}
+
+/// Compares two check results of the same partners.
+///
+/// Partners are matched by INN, KPP and the date of the check, so the
+/// checks of one partner on several dates are compared date by date.
+pub fn diff(old: &NdsResponse, new: &NdsResponse) -> Vec<ChangeEvent> {
+    diff_by(old, new, |p| (p.inn.to_string(), p.kpp.to_string(), Some(p.dt.date())))
+}
+
+/// Compares the results of two runs checking the same list of partners
+/// on different dates.
+///
+/// Partners are matched by INN and KPP only, each of them is expected
+/// once in every run.
+pub fn diff_runs(old: &NdsResponse, new: &NdsResponse) -> Vec<ChangeEvent> {
+    diff_by(old, new, |p| (p.inn.to_string(), p.kpp.to_string(), None))
+}
+
+fn diff_by<F>(old: &NdsResponse, new: &NdsResponse, key: F) -> Vec<ChangeEvent>
+where
+    F: Fn(&Partner) -> (String, String, Option<Date<Utc>>),
+{
+    let mut previous: HashMap<(String, String, Option<Date<Utc>>), Option<PartnerState>> =
+        HashMap::new();
+    for p in &old.partners {
+        previous.insert(key(p), p.state);
+    }
+
+    let mut events = vec![];
+    for p in &new.partners {
+        match previous.remove(&key(p)) {
+            Some(state) => events.extend(change(p, state, p.state)),
+            None => events.push(event(p, ChangeKind::Appeared, None, p.state)),
+        }
+    }
+
+    for p in &old.partners {
+        if let Some(state) = previous.remove(&key(p)) {
+            events.push(event(p, ChangeKind::Disappeared, state, None));
+        }
+    }
+
+    events
+}
+
+/// Compares the check result with the latest recorded states.
+///
+/// Partners are matched by INN and KPP with the latest record,
+/// whatever date it was checked on.
+pub fn diff_with_store<S: HistoryStore>(store: &S, new: &NdsResponse) -> Result<Vec<ChangeEvent>> {
+    let mut events = vec![];
+    for p in &new.partners {
+        match store.latest(&p.inn, &p.kpp)? {
+            Some(record) => events.extend(change(p, Some(record.partner_state()), p.state)),
+            None => events.push(event(p, ChangeKind::Appeared, None, p.state)),
+        }
+    }
+
+    Ok(events)
+}
+
+fn change(
+    p: &Partner,
+    previous: Option<PartnerState>,
+    current: Option<PartnerState>,
+) -> Option<ChangeEvent> {
+    let kind = match (previous, current) {
+        (Some(previous), Some(current)) => ChangeKind::classify(previous, current),
+        _ => None,
+    };
+
+    kind.map(|kind| event(p, kind, previous, current))
+}
+
+fn event(
+    p: &Partner,
+    kind: ChangeKind,
+    previous: Option<PartnerState>,
+    current: Option<PartnerState>,
+) -> ChangeEvent {
+    ChangeEvent {
+        inn: p.inn.to_string(),
+        kpp: p.kpp.to_string(),
+        dt: p.dt,
+        kind: kind,
+        previous: previous,
+        current: current,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partner(inn: &str, kpp: &str, day: u32, state: Option<PartnerState>) -> Partner<'static> {
+        let mut partner =
+            Partner::new(inn.to_string(), kpp.to_string(), Utc.ymd(2018, 2, day).and_hms(0, 0, 0));
+        partner.state = state;
+        partner
+    }
+
+    fn response(partners: Vec<Partner<'static>>) -> NdsResponse<'static> {
+        NdsResponse {
+            dtact_fl: Utc.ymd(2018, 2, 1).and_hms(0, 0, 0),
+            dtact_ul: Utc.ymd(2018, 2, 1).and_hms(0, 0, 0),
+            partners: partners,
+        }
+    }
+
+    fn kinds(events: &[ChangeEvent]) -> Vec<(&str, ChangeKind)> {
+        events.iter().map(|e| (e.inn.as_str(), e.kind)).collect()
+    }
+
+    #[test]
+    fn changes_are_classified() {
+        use PartnerState::*;
+
+        assert_eq!(ChangeKind::classify(Active, Active), None);
+        assert_eq!(ChangeKind::classify(Active, Inactive), Some(ChangeKind::BecameInactive));
+        assert_eq!(ChangeKind::classify(Registered, Active), Some(ChangeKind::BecameActive));
+        assert_eq!(ChangeKind::classify(Active, KppMismatch), Some(ChangeKind::KppMismatch));
+        assert_eq!(ChangeKind::classify(Inactive, NotRegistered), Some(ChangeKind::NotRegistered));
+        assert_eq!(ChangeKind::classify(Inactive, Registered), Some(ChangeKind::StateChanged));
+    }
+
+    #[test]
+    fn diff_reports_changes_of_the_same_date() {
+        let old = response(vec![
+            partner("7707083893", "773601001", 1, Some(PartnerState::Active)),
+            partner("6648185610", "662301001", 1, Some(PartnerState::Active)),
+            partner("4205036750", "420501001", 1, Some(PartnerState::Active)),
+        ]);
+        let new = response(vec![
+            partner("7707083893", "773601001", 1, Some(PartnerState::Active)),
+            partner("6648185610", "662301001", 1, Some(PartnerState::Inactive)),
+            partner("500100732259", "", 1, Some(PartnerState::Active)),
+        ]);
+
+        let events = diff(&old, &new);
+
+        assert_eq!(
+            kinds(&events),
+            vec![
+                ("6648185610", ChangeKind::BecameInactive),
+                ("500100732259", ChangeKind::Appeared),
+                ("4205036750", ChangeKind::Disappeared),
+            ]
+        );
+        assert_eq!(events[0].previous, Some(PartnerState::Active));
+        assert_eq!(events[0].current, Some(PartnerState::Inactive));
+        assert_eq!(events[2].current, None);
+    }
+
+    #[test]
+    fn diff_compares_date_by_date() {
+        let old = response(vec![
+            partner("7707083893", "773601001", 1, Some(PartnerState::Active)),
+            partner("7707083893", "773601001", 2, Some(PartnerState::Inactive)),
+        ]);
+        let new = response(vec![
+            partner("7707083893", "773601001", 2, Some(PartnerState::Inactive)),
+            partner("7707083893", "773601001", 1, Some(PartnerState::Active)),
+        ]);
+
+        assert!(diff(&old, &new).is_empty());
+
+        let moved = response(vec![
+            partner("7707083893", "773601001", 3, Some(PartnerState::Inactive)),
+        ]);
+        let events = diff(&old, &moved);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].kind, ChangeKind::Appeared);
+        assert_eq!(events[0].dt, Utc.ymd(2018, 2, 3).and_hms(0, 0, 0));
+    }
+
+    #[test]
+    fn runs_are_matched_across_dates() {
+        let old = response(vec![
+            partner("7707083893", "773601001", 1, Some(PartnerState::Active)),
+        ]);
+        let new = response(vec![
+            partner("7707083893", "773601001", 2, Some(PartnerState::NotRegistered)),
+        ]);
+
+        let events = diff_runs(&old, &new);
+
+        assert_eq!(kinds(&events), vec![("7707083893", ChangeKind::NotRegistered)]);
+        assert_eq!(events[0].dt, Utc.ymd(2018, 2, 2).and_hms(0, 0, 0));
+    }
+
+    #[test]
+    fn diff_with_store_uses_the_latest_record() {
+        let store = MemoryHistory::new();
+        let checked_at = Utc::now();
+        let first = response(vec![
+            partner("7707083893", "773601001", 1, Some(PartnerState::Active)),
+            partner("6648185610", "662301001", 1, None),
+        ]);
+        record_response(&store, &first, checked_at).unwrap();
+        let second = response(vec![
+            partner("7707083893", "773601001", 2, Some(PartnerState::Inactive)),
+        ]);
+        record_response(&store, &second, checked_at).unwrap();
+
+        let new = response(vec![
+            partner("7707083893", "773601001", 3, Some(PartnerState::Active)),
+            partner("6648185610", "662301001", 3, Some(PartnerState::Active)),
+        ]);
+        let events = diff_with_store(&store, &new).unwrap();
+
+        assert_eq!(
+            kinds(&events),
+            vec![
+                ("7707083893", ChangeKind::BecameActive),
+                ("6648185610", ChangeKind::Appeared),
+            ]
+        );
+        assert_eq!(events[0].previous, Some(PartnerState::Inactive));
+        assert_eq!(store.history("7707083893", "773601001").unwrap().len(), 2);
+    }
+}
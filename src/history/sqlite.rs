@@ -0,0 +1,154 @@
+//! History stored in an SQLite database.
+
+use chrono::prelude::*;
+use rusqlite::{Connection, Row};
+
+use super::{HistoryRecord, HistoryStore};
+use super::super::Result;
+use sqlite::{parse_datetime, transaction};
+
+/// History stored in an SQLite database file
+///
+/// Available with the `sqlite` feature.
+pub struct SqliteHistory {
+    conn: Connection,
+}
+
+sqlite_store!(SqliteHistory);
+
+impl SqliteHistory {
+    fn from_connection(conn: Connection) -> Result<SqliteHistory> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS npchk_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                inn TEXT NOT NULL,
+                kpp TEXT NOT NULL,
+                date TEXT NOT NULL,
+                state INTEGER NOT NULL,
+                dtact_fl TEXT NOT NULL,
+                dtact_ul TEXT NOT NULL,
+                checked_at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS npchk_history_partner
+                ON npchk_history (inn, kpp, id);",
+        )?;
+
+        Ok(SqliteHistory { conn: conn })
+    }
+}
+
+impl HistoryStore for SqliteHistory {
+    fn record(&self, records: &[HistoryRecord]) -> Result<()> {
+        transaction(&self.conn, || self.insert(records))
+    }
+
+    fn latest(&self, inn: &str, kpp: &str) -> Result<Option<HistoryRecord>> {
+        let mut records = self.query(
+            "SELECT inn, kpp, date, state, dtact_fl, dtact_ul, checked_at
+             FROM npchk_history WHERE inn = ?1 AND kpp = ?2
+             ORDER BY id DESC LIMIT 1",
+            inn,
+            kpp,
+        )?;
+
+        Ok(records.pop())
+    }
+
+    fn history(&self, inn: &str, kpp: &str) -> Result<Vec<HistoryRecord>> {
+        self.query(
+            "SELECT inn, kpp, date, state, dtact_fl, dtact_ul, checked_at
+             FROM npchk_history WHERE inn = ?1 AND kpp = ?2
+             ORDER BY id",
+            inn,
+            kpp,
+        )
+    }
+}
+
+impl SqliteHistory {
+    fn insert(&self, records: &[HistoryRecord]) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "INSERT INTO npchk_history
+             (inn, kpp, date, state, dtact_fl, dtact_ul, checked_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for record in records {
+            stmt.execute(&[
+                &record.inn,
+                &record.kpp,
+                &record.date.to_string(),
+                &record.state,
+                &record.dtact_fl.to_rfc3339(),
+                &record.dtact_ul.to_rfc3339(),
+                &record.checked_at.to_rfc3339(),
+            ])?;
+        }
+
+        Ok(())
+    }
+
+    fn query(&self, sql: &str, inn: &str, kpp: &str) -> Result<Vec<HistoryRecord>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let rows = stmt.query_map(&[&inn, &kpp], read_row)?;
+
+        let mut records = vec![];
+        for row in rows {
+            let (inn, kpp, date, state, dtact_fl, dtact_ul, checked_at) = row?;
+            records.push(HistoryRecord {
+                inn: inn,
+                kpp: kpp,
+                date: NaiveDate::parse_from_str(&date, "%Y-%m-%d")?,
+                state: state,
+                dtact_fl: parse_datetime(&dtact_fl)?,
+                dtact_ul: parse_datetime(&dtact_ul)?,
+                checked_at: parse_datetime(&checked_at)?,
+            });
+        }
+
+        Ok(records)
+    }
+}
+
+fn read_row(row: &Row) -> (String, String, String, i32, String, String, String) {
+    (
+        row.get(0),
+        row.get(1),
+        row.get(2),
+        row.get(3),
+        row.get(4),
+        row.get(5),
+        row.get(6),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(state: i32, day: u32) -> HistoryRecord {
+        HistoryRecord {
+            inn: "7707083893".into(),
+            kpp: "773601001".into(),
+            date: NaiveDate::from_ymd(2018, 2, day),
+            state: state,
+            dtact_fl: Utc.ymd(2018, 1, 30).and_hms(0, 0, 0),
+            dtact_ul: Utc.ymd(2018, 1, 31).and_hms(0, 0, 0),
+            checked_at: Utc.ymd(2018, 2, day).and_hms(9, 30, 0),
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let history = SqliteHistory::open_in_memory().unwrap();
+        assert_eq!(history.latest("7707083893", "773601001").unwrap(), None);
+
+        history.record(&[record(0, 1), record(1, 2)]).unwrap();
+
+        assert_eq!(
+            history.history("7707083893", "773601001").unwrap(),
+            vec![record(0, 1), record(1, 2)]
+        );
+        assert_eq!(history.latest("7707083893", "773601001").unwrap(), Some(record(1, 2)));
+        assert!(history.history("7707083893", "773601002").unwrap().is_empty());
+    }
+}
@@ -2,13 +2,25 @@
 //! HTTP helpers.
 
 use std::io::Read;
+use std::time::Duration;
 
-use reqwest::{Client, StatusCode};
+use reqwest::{self, Client, StatusCode};
 
-use hyper::header::ContentType;
+use hyper::header::{ContentType, UserAgent};
 use hyper::mime;
 
+#[cfg(feature = "async")]
+use futures::{future, Future, Stream};
+#[cfg(feature = "async")]
+use hyper::{self, Request, Uri};
+#[cfg(feature = "async")]
+use hyper::client::HttpConnector;
+#[cfg(feature = "async")]
+use super::error::Error;
+
 header! { (SoapAction, "SOAPAction") => [String] }
+header! { (XNpchkSignature, "X-Npchk-Signature") => [String] }
+header! { (XNpchkTimestamp, "X-Npchk-Timestamp") => [i64] }
 
 /// Simplified HTTP response representation.
 #[derive(Debug)]
@@ -17,6 +29,19 @@ pub struct Response {
     pub body: String,
 }
 
+/// Create the HTTP client with the timeout and the proxy.
+pub fn client(timeout: Option<Duration>, proxy: Option<&str>) -> super::Result<Client> {
+    let mut builder = Client::builder()?;
+    if let Some(timeout) = timeout {
+        builder.timeout(timeout);
+    }
+    if let Some(proxy) = proxy {
+        builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+
+    Ok(builder.build()?)
+}
+
 /// Perform a GET request to specified URL.
 pub fn get(url: &str) -> super::Result<Response> {
     let client = Client::new()?;
@@ -31,11 +56,96 @@ pub fn get(url: &str) -> super::Result<Response> {
     })
 }
 
+/// Perform a GET request to specified URL with the `User-Agent` header.
+pub fn get_with(client: &Client, url: &str, user_agent: &str) -> super::Result<Response> {
+    let mut response = client
+        .get(url)?
+        .header(UserAgent::new(user_agent.to_string()))
+        .send()?;
+
+    let mut body = String::new();
+    response.read_to_string(&mut body)?;
+
+    Ok(Response {
+        status: response.status(),
+        body: body,
+    })
+}
+
+/// Perform a GET request to specified URL, returning the successful
+/// response to read the body from.
+pub fn get_stream(
+    client: &Client,
+    url: &str,
+    user_agent: &str,
+) -> super::Result<reqwest::Response> {
+    let response = client
+        .get(url)?
+        .header(UserAgent::new(user_agent.to_string()))
+        .send()?;
+
+    if !response.status().is_success() {
+        return Err(super::error::Error::HttpStatus(response.status()));
+    }
+
+    Ok(response)
+}
+
+/// Perform a POST of the form to specified URL.
+pub fn post_form(
+    client: &Client,
+    url: &str,
+    user_agent: &str,
+    form: &[(&str, &str)],
+) -> super::Result<Response> {
+    let mut response = client
+        .post(url)?
+        .header(UserAgent::new(user_agent.to_string()))
+        .header(ContentType(mime::APPLICATION_WWW_FORM_URLENCODED))
+        .body(form_urlencode(form))
+        .send()?;
+
+    let mut body = String::new();
+    response.read_to_string(&mut body)?;
+
+    Ok(Response {
+        status: response.status(),
+        body: body,
+    })
+}
+
+/// Encodes the pairs as `application/x-www-form-urlencoded`.
+pub fn form_urlencode(form: &[(&str, &str)]) -> String {
+    let encode = |value: &str| -> String {
+        value
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => {
+                    (b as char).to_string()
+                }
+                b' ' => "+".to_string(),
+                _ => format!("%{:02X}", b),
+            })
+            .collect()
+    };
+
+    form.iter()
+        .map(|&(name, value)| format!("{}={}", encode(name), encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
 /// Perform a SOAP action to specified URL.
-pub fn soap_action(url: &str, action: &str, xml: &str) -> super::Result<Response> {
-    let client = Client::new()?;
+pub fn soap_action(
+    client: &Client,
+    url: &str,
+    user_agent: &str,
+    action: &str,
+    xml: &str,
+) -> super::Result<Response> {
     let mut response = client
         .post(url)?
+        .header(UserAgent::new(user_agent.to_string()))
         .header(ContentType(mime::TEXT_XML))
         .header(SoapAction(action.into()))
         .body(xml.to_string())
@@ -49,3 +159,64 @@ pub fn soap_action(url: &str, action: &str, xml: &str) -> super::Result<Response
         body: body,
     })
 }
+
+/// Perform a POST of the JSON body to specified URL, with the timestamp
+/// and signature headers if the signature is set.
+pub fn post_json(
+    client: &Client,
+    url: &str,
+    user_agent: &str,
+    signature: Option<(i64, &str)>,
+    json: &str,
+) -> super::Result<Response> {
+    let mut request = client.post(url)?;
+    request
+        .header(UserAgent::new(user_agent.to_string()))
+        .header(ContentType(mime::APPLICATION_JSON))
+        .body(json.to_string());
+    if let Some((timestamp, signature)) = signature {
+        request
+            .header(XNpchkTimestamp(timestamp))
+            .header(XNpchkSignature(signature.into()));
+    }
+    let mut response = request.send()?;
+
+    let mut body = String::new();
+    response.read_to_string(&mut body)?;
+
+    Ok(Response {
+        status: response.status(),
+        body: body,
+    })
+}
+
+/// Perform a SOAP action to specified URL without blocking the thread.
+#[cfg(feature = "async")]
+pub fn soap_action_async(
+    client: &hyper::Client<HttpConnector>,
+    url: &str,
+    user_agent: &str,
+    action: &str,
+    xml: &str,
+) -> Box<Future<Item = Response, Error = Error>> {
+    let uri: Uri = match url.parse() {
+        Ok(uri) => uri,
+        Err(e) => return Box::new(future::err(Error::from(e))),
+    };
+
+    let mut request = Request::new(hyper::Method::Post, uri);
+    request
+        .headers_mut()
+        .set(UserAgent::new(user_agent.to_string()));
+    request.headers_mut().set(ContentType(mime::TEXT_XML));
+    request.headers_mut().set(SoapAction(action.into()));
+    request.set_body(xml.to_string());
+
+    Box::new(client.request(request).from_err().and_then(|response| {
+        let status = response.status();
+        response.body().concat2().from_err().map(move |body| Response {
+            status: status,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }))
+}
@@ -1,74 +1,435 @@
 extern crate chrono;
+#[cfg(feature = "watch")]
+extern crate cron;
+#[cfg(feature = "csv-io")]
+extern crate csv;
+#[cfg(any(feature = "csv-io", feature = "msp"))]
+extern crate encoding_rs;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "webhook")]
+extern crate hmac;
 #[macro_use]
 extern crate hyper;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+extern crate rand;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 extern crate reqwest;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "serde_json")]
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "webhook")]
+extern crate sha2;
+#[cfg(feature = "async")]
+extern crate tokio_core;
 extern crate xml;
 extern crate xmltree;
+#[cfg(feature = "xlsx")]
+extern crate zip;
 
+#[cfg(feature = "sqlite")]
+#[macro_use]
+mod sqlite;
 mod rpser;
 mod http;
+#[cfg(any(feature = "npd", feature = "egrul", feature = "pb", feature = "bi", feature = "msp"))]
+#[macro_use]
+mod service;
 mod transforms;
+mod client;
 pub mod models;
 pub mod error;
+pub mod validate;
+pub mod retry;
+pub mod correlate;
+pub mod cache;
+pub mod history;
+pub mod schema;
+pub mod metrics;
+pub mod throttle;
+#[cfg(feature = "csv-io")]
+pub mod csv_io;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+#[cfg(feature = "server")]
+pub mod gateway;
+#[cfg(feature = "npd")]
+pub mod npd;
+#[cfg(feature = "egrul")]
+pub mod egrul;
+#[cfg(feature = "pb")]
+pub mod pb;
+#[cfg(feature = "bi")]
+pub mod bi;
+#[cfg(feature = "msp")]
+pub mod msp;
 
 use std::result;
-
-use rpser::Method;
+use std::sync::{Arc, Mutex};
 
 pub use models::partner::Partner;
+pub use models::partner_state::{PartnerKind, PartnerState};
 pub use models::nds_response::NdsResponse;
+pub use models::batch_response::{BatchResponse, ChunkFailure};
 
 pub use transforms::FromElement;
+pub use client::{NpchkClient, NpchkClientBuilder};
+pub use retry::RetryPolicy;
+pub use correlate::{CheckedPartner, Correlation, CorrelatedResponse};
 
-/// The connection point of the service
+/// The default connection point of the service
 const V2_API_RPC_PATH: &'static str = "http://npchk.nalog.ru:80/FNSNDSCAWS_2";
 const V2_API_REQUEST: &'static str = "http://ws.unisoft/FNSNDSCAWS2/Request";
 const V2_API_NAMESPACE: &'static str = "req";
 
+/// The maximum number of partners in one request to the service
+pub const MAX_RECORDS: usize = schema::NDS_REQUEST2_NP_MAX_OCCURS;
+
+/// Options of the contractors check
+#[derive(Debug, Clone, Default)]
+pub struct CheckOptions {
+    /// Do not send partners that fail the local validation
+    /// (see the `validate` module). The locally produced states
+    /// are merged into the response in place of the service answers.
+    pub skip_invalid: bool,
+}
+
 /// Checks of contractors through the service
 /// [http://npchk.nalog.ru/](http://npchk.nalog.ru/)
 pub fn check_fns(partners: Vec<Partner>) -> Result<NdsResponse> {
-    use self::rpser::xml::BuildElement;
-    use xmltree::Element;
-
-    if partners.len() > 10_000 {
-        return Err(error::Error::TooManyRecords);
-    }
+    default_client()?.check_fns(partners)
+}
 
-    let mut nds_request2 = Method::new("NdsRequest2");
-    for elem in partners {
-        nds_request2 = nds_request2.with(
-            Element::node(format!("{}:{}", V2_API_NAMESPACE, "NP"))
-                .with_attr("INN", elem.inn)
-                .with_attr("KPP", elem.kpp)
-                .with_attr("DT", elem.dt.format("%d.%m.%Y").to_string()),
-        );
-    }
+/// Checks of contractors through the service
+/// [http://npchk.nalog.ru/](http://npchk.nalog.ru/) with the specified options
+pub fn check_fns_with_options<'a>(
+    partners: Vec<Partner<'a>>,
+    options: &CheckOptions,
+) -> Result<NdsResponse<'a>> {
+    default_client()?.check_fns_with_options(partners, options)
+}
 
-    let response = call(nds_request2)?;
+/// Checks any number of contractors, splitting them into requests
+/// of at most `MAX_RECORDS` partners.
+///
+/// See `NpchkClient::check_fns_batched`.
+pub fn check_fns_batched<'a>(
+    partners: Vec<Partner<'a>>,
+    options: &CheckOptions,
+) -> Result<BatchResponse<'a>> {
+    Ok(default_client()?.check_fns_batched(partners, options))
+}
 
-    Ok(NdsResponse::from_element(response.body)?)
+/// Checks of contractors and pairs every submitted partner with its answer
+///
+/// See `NpchkClient::check_fns_keyed`.
+pub fn check_fns_keyed<'a, K>(
+    partners: Vec<(K, Partner<'a>)>,
+    options: &CheckOptions,
+) -> Result<CorrelatedResponse<'a, K>> {
+    default_client()?.check_fns_keyed(partners, options)
 }
 
 /// Checks the 1st of the contractor using the service
 /// [http://npchk.nalog.ru/](http://npchk.nalog.ru/)
 pub fn check_fns_partner(p: Partner) -> Result<NdsResponse> {
-    let mut partners: Vec<Partner> = vec![];
-    partners.push(p);
-
-    check_fns(partners)
+    default_client()?.check_fns_partner(p)
 }
 
-/// Calls a remote procedure through a Protocol `SOAP`
-fn call(method: rpser::Method) -> Result<rpser::Response> {
-    let envelope = method.as_xml(V2_API_REQUEST, V2_API_NAMESPACE);
-
-    let http_response = http::soap_action(V2_API_RPC_PATH, &method.name, &envelope)?;
+pub type Result<T> = result::Result<T, error::Error>;
 
-    Ok(rpser::Response::from_xml(&http_response.body)?)
+lazy_static! {
+    static ref DEFAULT_CLIENT: Mutex<Option<Arc<NpchkClient>>> = Mutex::new(None);
 }
 
-pub type Result<T> = result::Result<T, error::Error>;
+/// The client with the settings of the public service shared by the free
+/// functions, created on the first successful call.
+fn default_client() -> Result<Arc<NpchkClient>> {
+    let mut shared = DEFAULT_CLIENT.lock().unwrap();
+    if let Some(ref client) = *shared {
+        return Ok(client.clone());
+    }
+
+    let client = Arc::new(NpchkClient::new()?);
+    *shared = Some(client.clone());
+    Ok(client)
+}
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::time::Duration;
+
+    use chrono::prelude::*;
+
+    use super::*;
+    use error::Error;
+    use mock::{Behavior, MockNpchkServer};
+    use rpser::RpcError;
+
+    fn partners() -> Vec<Partner<'static>> {
+        vec![
+            Partner::new("4205036750", "420501001", Utc::now()),
+            Partner::new("6648185610", "662301001", Utc::now()),
+        ]
+    }
+
+    #[test]
+    fn check_fns_returns_states_from_service() {
+        let server = MockNpchkServer::start().unwrap();
+        server.set_state("6648185610", "662301001", 3);
+        server.set_actuality("01.02.2018", "02.02.2018");
+
+        let rsp = server.client().unwrap().check_fns(partners()).unwrap();
+
+        assert_eq!(rsp.dtact_fl, Utc.ymd(2018, 2, 1).and_hms(0, 0, 0));
+        assert_eq!(rsp.dtact_ul, Utc.ymd(2018, 2, 2).and_hms(0, 0, 0));
+        assert_eq!(rsp.partners.len(), 2);
+        assert_eq!(rsp.partners[0].state, Some(PartnerState::Active));
+        assert_eq!(rsp.partners[1].state, Some(PartnerState::KppMismatch));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].header("SOAPAction"), Some("NdsRequest2"));
+    }
+
+    #[test]
+    fn empty_kpp_is_not_sent() {
+        let server = MockNpchkServer::start().unwrap();
+        let entrepreneur = Partner::new("500100732259", "", Utc::now());
+
+        let rsp = server.client().unwrap().check_fns(vec![entrepreneur]).unwrap();
+
+        assert_eq!(rsp.partners[0].kpp, "");
+        let requests = server.requests();
+        assert!(requests[0].body.contains("500100732259"));
+        assert!(!requests[0].body.contains("KPP"));
+    }
+
+    #[test]
+    fn err_msg_is_reported_as_fns_error() {
+        let server = MockNpchkServer::start().unwrap();
+        server.respond_with(Behavior::ErrMsg("Too many requests".into()));
+
+        match server.client().unwrap().check_fns(partners()) {
+            Err(Error::FnsError(ref msg)) => assert_eq!(msg, "Too many requests"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn soap_fault_is_reported_as_rpc_error() {
+        let server = MockNpchkServer::start().unwrap();
+        server.respond_with(Behavior::Fault {
+            code: "soap:Client".into(),
+            string: "Bad request".into(),
+            detail: Some("NP".into()),
+        });
+
+        match server.client().unwrap().check_fns(partners()) {
+            Err(Error::RpcError(RpcError::Fault {
+                ref fault_string, ..
+            })) => assert_eq!(fault_string, "Bad request"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed_response_is_an_error() {
+        let server = MockNpchkServer::start().unwrap();
+        server.respond_with(Behavior::Malformed);
+
+        assert!(server.client().unwrap().check_fns(partners()).is_err());
+    }
+
+    #[test]
+    fn server_errors_are_retried() {
+        let server = MockNpchkServer::start().unwrap();
+        server.respond_once_with(Behavior::Status(503));
+        let retry = RetryPolicy {
+            initial_backoff: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        };
+        let client = server.client_builder().retry(retry).build().unwrap();
+
+        let rsp = client.check_fns(partners()).unwrap();
+
+        assert_eq!(rsp.partners.len(), 2);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn invalid_input_is_not_retried() {
+        let server = MockNpchkServer::start().unwrap();
+        server.respond_with(Behavior::Fault {
+            code: "soap:Client".into(),
+            string: "Bad request".into(),
+            detail: None,
+        });
+        let client = server
+            .client_builder()
+            .retry(RetryPolicy::default())
+            .build()
+            .unwrap();
+
+        assert!(client.check_fns(partners()).is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn slow_response_times_out() {
+        let server = MockNpchkServer::start().unwrap();
+        server.respond_with(Behavior::Slow(Duration::from_secs(2)));
+        let client = server
+            .client_builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+
+        assert!(client.check_fns(partners()).is_err());
+    }
+
+    #[test]
+    fn invalid_partners_are_not_sent() {
+        let server = MockNpchkServer::start().unwrap();
+        let mut partners = partners();
+        partners.insert(1, Partner::new("7707083890", "770701001", Utc::now()));
+        let options = CheckOptions { skip_invalid: true };
+
+        let rsp = server
+            .client()
+            .unwrap()
+            .check_fns_with_options(partners, &options)
+            .unwrap();
+
+        let states: Vec<_> = rsp.partners.iter().map(|p| p.state).collect();
+        assert_eq!(
+            states,
+            vec![
+                Some(PartnerState::Active),
+                Some(PartnerState::IncorrectInn),
+                Some(PartnerState::Active),
+            ]
+        );
+        assert!(!server.requests()[0].body.contains("7707083890"));
+    }
+
+    #[test]
+    fn answers_are_matched_by_inn_and_kpp() {
+        let server = MockNpchkServer::start().unwrap();
+        server.respond_with(Behavior::Reversed);
+        server.set_state("6648185610", "662301001", 3);
+        let mut partners = partners();
+        partners.insert(1, Partner::new("7707083890", "770701001", Utc::now()));
+        let options = CheckOptions { skip_invalid: true };
+
+        let rsp = server
+            .client()
+            .unwrap()
+            .check_fns_with_options(partners, &options)
+            .unwrap();
+
+        let states: Vec<_> = rsp.partners
+            .iter()
+            .map(|p| (&*p.inn, p.state))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("4205036750", Some(PartnerState::Active)),
+                ("7707083890", Some(PartnerState::IncorrectInn)),
+                ("6648185610", Some(PartnerState::KppMismatch)),
+            ]
+        );
+    }
+
+    #[test]
+    fn keyed_check_is_split_into_requests() {
+        let server = MockNpchkServer::start().unwrap();
+        server.set_inn_state("6648185610", 1);
+        let mut partners: Vec<(usize, Partner)> = (0..MAX_RECORDS)
+            .map(|key| (key, Partner::new("7707083893", "773601001", Utc::now())))
+            .collect();
+        partners.push((MAX_RECORDS, Partner::new("6648185610", "662301001", Utc::now())));
+
+        let rsp = server
+            .client()
+            .unwrap()
+            .check_fns_keyed(partners, &CheckOptions::default())
+            .unwrap();
+
+        assert!(rsp.is_consistent());
+        assert_eq!(rsp.partners.len(), MAX_RECORDS + 1);
+        let last = &rsp.partners[MAX_RECORDS];
+        assert_eq!(last.key, MAX_RECORDS);
+        assert_eq!(last.result.as_ref().unwrap().state, Some(PartnerState::Inactive));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn failed_chunks_are_reported_with_their_partners() {
+        let server = MockNpchkServer::start().unwrap();
+        server.respond_once_with(Behavior::Status(400));
+        let mut partners = vec![Partner::new("7707083893", "773601001", Utc::now()); MAX_RECORDS];
+        partners.push(Partner::new("6648185610", "662301001", Utc::now()));
+
+        let batch = server
+            .client()
+            .unwrap()
+            .check_fns_batched(partners, &CheckOptions::default());
+
+        assert!(!batch.is_complete());
+        assert_eq!(batch.failures.len(), 1);
+        assert_eq!(batch.failures[0].index, 0);
+        assert_eq!(batch.failures[0].partners.len(), MAX_RECORDS);
+        match batch.failures[0].error {
+            Error::HttpStatus(ref status) => assert_eq!(status.as_u16(), 400),
+            ref other => panic!("unexpected error {:?}", other),
+        }
+
+        let rsp = batch.response.unwrap();
+        assert_eq!(rsp.partners.len(), 1);
+        assert_eq!(rsp.partners[0].inn, "6648185610");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn nothing_is_sent_when_every_partner_is_invalid() {
+        let server = MockNpchkServer::start().unwrap();
+        let partners = vec![
+            Partner::new("7707083890", "770701001", Utc::now()),
+            Partner::new("АБВГДЕЖЗИК", "770701001", Utc::now()),
+        ];
+        let options = CheckOptions { skip_invalid: true };
+
+        let rsp = server
+            .client()
+            .unwrap()
+            .check_fns_with_options(partners, &options)
+            .unwrap();
+
+        let states: Vec<_> = rsp.partners.iter().map(|p| p.state).collect();
+        assert_eq!(
+            states,
+            vec![
+                Some(PartnerState::IncorrectInn),
+                Some(PartnerState::InvalidInnCharacters),
+            ]
+        );
+        assert!(server.requests().is_empty());
+    }
+}
@@ -0,0 +1,50 @@
+//! Instrumentation of the calls to the service.
+//!
+//! The client reports every HTTP exchange, every request sent and every
+//! failed call to its `Metrics`. Nothing is recorded by default; pass an
+//! implementation to `NpchkClientBuilder::metrics` to collect them, e.g.
+//! `PrometheusMetrics` with the `prometheus` feature.
+
+#[cfg(feature = "prometheus")]
+mod prometheus;
+
+#[cfg(feature = "prometheus")]
+pub use self::prometheus::PrometheusMetrics;
+
+use std::fmt;
+use std::time::Duration;
+
+use super::PartnerState;
+use error::Error;
+
+/// Receiver of the measurements of the client
+///
+/// Every method does nothing by default.
+pub trait Metrics: fmt::Debug + Send + Sync {
+    /// One HTTP exchange with the service, including the failed ones
+    /// and each retried attempt. The size is 0 if no response was received.
+    fn http_request(&self, method: &str, latency: Duration, response_size: usize) {
+        let _ = (method, latency, response_size);
+    }
+
+    /// The request of `partners` partners is sent to the service.
+    fn batch(&self, method: &str, partners: usize) {
+        let _ = (method, partners);
+    }
+
+    /// The service reported the state of a partner.
+    fn result(&self, state: PartnerState) {
+        let _ = state;
+    }
+
+    /// The call failed after all the attempts.
+    fn error(&self, method: &str, error: &Error) {
+        let _ = (method, error);
+    }
+}
+
+/// Metrics that record nothing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
@@ -0,0 +1,260 @@
+//! Export of the metrics in the Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::Metrics;
+use error::Error;
+use PartnerState;
+
+const LATENCY_BUCKETS: &'static [f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+const SIZE_BUCKETS: &'static [f64] = &[1e3, 1e4, 1e5, 1e6, 1e7];
+const BATCH_BUCKETS: &'static [f64] = &[1.0, 10.0, 100.0, 1000.0, 5000.0, 10000.0];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Cumulative counts of the observations per upper bound
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    requests: BTreeMap<String, u64>,
+    latency: BTreeMap<String, Histogram>,
+    response_size: BTreeMap<String, Histogram>,
+    batches: BTreeMap<String, Histogram>,
+    results: BTreeMap<i32, u64>,
+    errors: BTreeMap<(String, &'static str), u64>,
+}
+
+/// Metrics kept in memory and rendered in the Prometheus text format
+///
+/// Available with the `prometheus` feature.
+#[derive(Debug, Default)]
+pub struct PrometheusMetrics {
+    registry: Mutex<Registry>,
+}
+
+impl Histogram {
+    fn observe(&mut self, bounds: &[f64], value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; bounds.len()];
+        }
+        for (bucket, bound) in self.buckets.iter_mut().zip(bounds) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str, bounds: &[f64]) {
+        for (bucket, bound) in self.buckets.iter().zip(bounds) {
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, bucket);
+        }
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, self.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+impl PrometheusMetrics {
+    pub fn new() -> PrometheusMetrics {
+        PrometheusMetrics::default()
+    }
+
+    /// The metrics in the Prometheus text exposition format, version 0.0.4.
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "npchk_http_requests_total",
+            "counter",
+            "HTTP requests sent to the service",
+        );
+        for (method, count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "npchk_http_requests_total{{method=\"{}\"}} {}",
+                escape(method),
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "npchk_http_request_duration_seconds",
+            "histogram",
+            "Latency of the HTTP requests to the service",
+        );
+        for (method, histogram) in &registry.latency {
+            let labels = format!("method=\"{}\"", escape(method));
+            histogram.render(
+                &mut out,
+                "npchk_http_request_duration_seconds",
+                &labels,
+                LATENCY_BUCKETS,
+            );
+        }
+
+        header(
+            &mut out,
+            "npchk_response_size_bytes",
+            "histogram",
+            "Size of the responses of the service",
+        );
+        for (method, histogram) in &registry.response_size {
+            let labels = format!("method=\"{}\"", escape(method));
+            histogram.render(&mut out, "npchk_response_size_bytes", &labels, SIZE_BUCKETS);
+        }
+
+        header(
+            &mut out,
+            "npchk_batch_partners",
+            "histogram",
+            "Number of partners per request",
+        );
+        for (method, histogram) in &registry.batches {
+            let labels = format!("method=\"{}\"", escape(method));
+            histogram.render(&mut out, "npchk_batch_partners", &labels, BATCH_BUCKETS);
+        }
+
+        header(
+            &mut out,
+            "npchk_results_total",
+            "counter",
+            "Partner states reported by the service",
+        );
+        for (state, count) in &registry.results {
+            let _ = writeln!(out, "npchk_results_total{{state=\"{}\"}} {}", state, count);
+        }
+
+        header(
+            &mut out,
+            "npchk_errors_total",
+            "counter",
+            "Failed calls per error variant",
+        );
+        for (&(ref method, kind), count) in &registry.errors {
+            let _ = writeln!(
+                out,
+                "npchk_errors_total{{method=\"{}\",kind=\"{}\"}} {}",
+                escape(method),
+                kind,
+                count
+            );
+        }
+
+        out
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn http_request(&self, method: &str, latency: Duration, response_size: usize) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.requests.entry(method.to_string()).or_insert(0) += 1;
+
+        let secs = latency.as_secs() as f64 + latency.subsec_nanos() as f64 / 1_000_000_000.0;
+        registry
+            .latency
+            .entry(method.to_string())
+            .or_insert_with(Histogram::default)
+            .observe(LATENCY_BUCKETS, secs);
+        registry
+            .response_size
+            .entry(method.to_string())
+            .or_insert_with(Histogram::default)
+            .observe(SIZE_BUCKETS, response_size as f64);
+    }
+
+    fn batch(&self, method: &str, partners: usize) {
+        self.registry
+            .lock()
+            .unwrap()
+            .batches
+            .entry(method.to_string())
+            .or_insert_with(Histogram::default)
+            .observe(BATCH_BUCKETS, partners as f64);
+    }
+
+    fn result(&self, state: PartnerState) {
+        *self.registry
+            .lock()
+            .unwrap()
+            .results
+            .entry(state.code())
+            .or_insert(0) += 1;
+    }
+
+    fn error(&self, method: &str, error: &Error) {
+        *self.registry
+            .lock()
+            .unwrap()
+            .errors
+            .entry((method.to_string(), error.kind()))
+            .or_insert(0) += 1;
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::prelude::*;
+
+    use super::*;
+    use mock::{Behavior, MockNpchkServer};
+    use Partner;
+
+    #[test]
+    fn records_the_calls() {
+        let server = MockNpchkServer::start().unwrap();
+        server.set_inn_state("6648185610", 3);
+        let metrics = Arc::new(PrometheusMetrics::new());
+        let client = server
+            .client_builder()
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+
+        let partners = vec![
+            Partner::new("7707083893", "773601001", Utc::now()),
+            Partner::new("6648185610", "662301001", Utc::now()),
+        ];
+        client.check_fns(partners.clone()).unwrap();
+
+        server.respond_once_with(Behavior::ErrMsg("Internal error".into()));
+        assert!(client.check_fns(partners).is_err());
+
+        let text = metrics.render();
+        assert!(text.contains("npchk_http_requests_total{method=\"NdsRequest2\"} 2\n"));
+        assert!(text.contains(
+            "npchk_http_request_duration_seconds_count{method=\"NdsRequest2\"} 2\n"
+        ));
+        assert!(text.contains("npchk_batch_partners_sum{method=\"NdsRequest2\"} 4\n"));
+        assert!(text.contains("npchk_results_total{state=\"0\"} 1\n"));
+        assert!(text.contains("npchk_results_total{state=\"3\"} 1\n"));
+        assert!(text.contains(
+            "npchk_errors_total{method=\"NdsRequest2\",kind=\"FnsError\"} 1\n"
+        ));
+    }
+}
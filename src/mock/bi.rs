@@ -0,0 +1,116 @@
+//! Mock of the bank account blocking service.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use serde_json::{self, Value};
+
+use super::{HttpRequest, HttpResponse, MockServer};
+use bi::{BiClient, BiClientBuilder, BlockingDecision};
+
+#[derive(Default)]
+struct State {
+    decisions: HashMap<String, Vec<BlockingDecision>>,
+    captcha_required: bool,
+}
+
+/// In-process server speaking the protocol of `bi2-proc.json`
+///
+/// Answers with the decisions added by `add_decision` for the INN
+/// and the BIK of the decision.
+pub struct MockBiServer {
+    server: MockServer,
+    state: Arc<Mutex<State>>,
+}
+
+mock_service!(MockBiServer, State, handle, "/bi2-proc.json", BiClient);
+
+impl MockBiServer {
+    /// Builder of the client pointed at the mock.
+    pub fn client_builder(&self) -> BiClientBuilder {
+        BiClient::builder().url(self.url())
+    }
+
+    /// Add the active decision on the accounts of the taxpayer.
+    pub fn add_decision(&self, inn: &str, decision: BlockingDecision) {
+        self.state
+            .lock()
+            .unwrap()
+            .decisions
+            .entry(inn.into())
+            .or_insert_with(Vec::new)
+            .push(decision);
+    }
+
+    /// Answer every request with the captcha error.
+    pub fn set_captcha_required(&self, captcha_required: bool) {
+        self.state.lock().unwrap().captcha_required = captcha_required;
+    }
+}
+
+fn handle(state: &Mutex<State>, request: &HttpRequest) -> HttpResponse {
+    let state = state.lock().unwrap();
+    if state.captcha_required {
+        return reply(
+            400,
+            &json!({"ERRORS": {"captcha": ["Цифры с картинки введены неверно"]}}),
+        );
+    }
+
+    let form: HashMap<&str, &str> = request
+        .body
+        .split('&')
+        .filter_map(|pair| {
+            let mut pair = pair.splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some(key), Some(value)) => Some((key, value)),
+                _ => None,
+            }
+        })
+        .collect();
+    let inn = form.get("innPRS").cloned().unwrap_or("");
+    let bik = form.get("bikPRS").cloned().unwrap_or("");
+
+    let rows: Vec<Value> = state
+        .decisions
+        .get(inn)
+        .map(|decisions| {
+            decisions
+                .iter()
+                .filter(|decision| decision.bik == bik)
+                .map(row)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let date = Local::now().format("%d.%m.%Y %H:%M:%S").to_string();
+    reply(
+        200,
+        &json!({
+            "innPRS": inn,
+            "bikPRS": bik,
+            "datePRS": date,
+            "rows": rows,
+        }),
+    )
+}
+
+fn row(decision: &BlockingDecision) -> Value {
+    let date = decision.date.map(|date| date.format("%d.%m.%Y").to_string());
+    let received_at = decision
+        .received_at
+        .map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string());
+
+    json!({
+        "NOMER": decision.number,
+        "DATA": date,
+        "IFNS": decision.tax_office,
+        "BIK": decision.bik,
+        "DATABI": received_at,
+    })
+}
+
+fn reply(status: u16, value: &Value) -> HttpResponse {
+    HttpResponse::new(status, "application/json", serde_json::to_string(value).unwrap())
+}
@@ -0,0 +1,197 @@
+//! Mock of the EGRUL/EGRIP search service.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use serde_json;
+
+use super::{HttpRequest, HttpResponse, MockServer};
+use egrul::{EgrulClient, EgrulClientBuilder, RegistryKind, RegistryRecord};
+
+/// Body of the extract served by the mock
+pub const EXTRACT_PDF: &'static str = "%PDF-1.4\n% npchk mock extract\n%%EOF\n";
+
+#[derive(Serialize)]
+struct Row {
+    a: String,
+    c: String,
+    g: String,
+    i: String,
+    k: &'static str,
+    n: String,
+    o: String,
+    p: String,
+    r: String,
+    e: String,
+    t: String,
+}
+
+#[derive(Default)]
+struct State {
+    records: Vec<RegistryRecord>,
+    /// Query of the search per token
+    searches: HashMap<String, String>,
+    /// Polls left before the result of the token is ready
+    pending: HashMap<String, u32>,
+    pending_polls: u32,
+    captcha_required: bool,
+    next_token: u32,
+}
+
+/// In-process server speaking the protocol of `egrul.nalog.ru`
+///
+/// Finds the records added by `add_record` by INN or OGRN.
+pub struct MockEgrulServer {
+    server: MockServer,
+    state: Arc<Mutex<State>>,
+}
+
+mock_service!(MockEgrulServer, State, handle, "", EgrulClient);
+
+impl MockEgrulServer {
+    /// Builder of the client pointed at the mock, polling without a delay.
+    pub fn client_builder(&self) -> EgrulClientBuilder {
+        EgrulClient::builder()
+            .url(self.url())
+            .poll(Duration::from_secs(0), 30)
+    }
+
+    /// Add the record to the register, its token is ignored.
+    pub fn add_record(&self, record: RegistryRecord) {
+        self.state.lock().unwrap().records.push(record);
+    }
+
+    /// Answer `wait` to the first `polls` polls of every search and extract.
+    pub fn set_pending(&self, polls: u32) {
+        self.state.lock().unwrap().pending_polls = polls;
+    }
+
+    /// Require a captcha for every search and extract.
+    pub fn set_captcha_required(&self, captcha_required: bool) {
+        self.state.lock().unwrap().captcha_required = captcha_required;
+    }
+}
+
+fn handle(state: &Mutex<State>, request: &HttpRequest) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    let path = request.path.split('?').next().unwrap_or("");
+    let (step, token) = match path.rfind('/') {
+        Some(pos) => (&path[..pos], &path[pos + 1..]),
+        None => (path, ""),
+    };
+
+    match (request.method.as_str(), step) {
+        ("POST", "") => {
+            let query = form_value(&request.body, "query").unwrap_or_default();
+            new_token(&mut state, query)
+        }
+        ("GET", "/search-result") => {
+            if !poll(&mut state, token) {
+                return json(200, r#"{"status":"wait"}"#);
+            }
+            let query = state.searches.get(token).cloned().unwrap_or_default();
+            let rows: Vec<Row> = state
+                .records
+                .iter()
+                .filter(|record| record.inn == query || record.ogrn == query)
+                .map(row)
+                .collect();
+            json(
+                200,
+                &format!("{{\"rows\":{}}}", serde_json::to_string(&rows).unwrap()),
+            )
+        }
+        ("GET", "/vyp-request") => {
+            let inn = token.trim_left_matches("row-").to_string();
+            new_token(&mut state, inn)
+        }
+        ("GET", "/vyp-status") => if poll(&mut state, token) {
+            json(200, r#"{"status":"ready"}"#)
+        } else {
+            json(200, r#"{"status":"wait"}"#)
+        },
+        ("GET", "/vyp-download") if state.searches.contains_key(token) => {
+            HttpResponse::new(200, "application/pdf", EXTRACT_PDF)
+        }
+        _ => HttpResponse::new(404, "text/plain", ""),
+    }
+}
+
+fn new_token(state: &mut State, query: String) -> HttpResponse {
+    if state.captcha_required {
+        return json(200, r#"{"captchaRequired":true}"#);
+    }
+
+    state.next_token += 1;
+    let token = format!("token-{}", state.next_token);
+    state.searches.insert(token.clone(), query);
+    let polls = state.pending_polls;
+    state.pending.insert(token.clone(), polls);
+
+    json(
+        200,
+        &format!("{{\"t\":\"{}\",\"captchaRequired\":false}}", token),
+    )
+}
+
+/// Counts the poll, `true` if the result of the token is ready.
+fn poll(state: &mut State, token: &str) -> bool {
+    match state.pending.get_mut(token) {
+        Some(left) => if *left > 0 {
+            *left -= 1;
+            false
+        } else {
+            true
+        },
+        None => true,
+    }
+}
+
+fn row(record: &RegistryRecord) -> Row {
+    Row {
+        a: record.address.clone().unwrap_or_default(),
+        c: record.short_name.clone().unwrap_or_default(),
+        g: record
+            .director
+            .as_ref()
+            .map(|director| match director.position {
+                Some(ref position) => format!("{}: {}", position, director.name),
+                None => director.name.clone(),
+            })
+            .unwrap_or_default(),
+        i: record.inn.clone(),
+        k: match record.kind {
+            RegistryKind::LegalEntity => "ul",
+            RegistryKind::Entrepreneur => "fl",
+        },
+        n: record.name.clone(),
+        o: record.ogrn.clone(),
+        p: record.kpp.clone().unwrap_or_default(),
+        r: format_date(record.registration_date),
+        e: format_date(record.liquidation_date),
+        t: format!("row-{}", record.inn),
+    }
+}
+
+fn format_date(date: Option<NaiveDate>) -> String {
+    date.map(|date| date.format("%d.%m.%Y").to_string())
+        .unwrap_or_default()
+}
+
+fn form_value(body: &str, name: &str) -> Option<String> {
+    body.split('&')
+        .filter_map(|pair| {
+            let mut pair = pair.splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some(key), Some(value)) if key == name => Some(value.to_string()),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+fn json(status: u16, body: &str) -> HttpResponse {
+    HttpResponse::new(status, "application/json", body)
+}
@@ -0,0 +1,284 @@
+//! Local HTTP servers emulating the services, for offline testing.
+//!
+//! Available with the `test-support` feature.
+
+/// Implements `start`, `url`, `client` and `requests` of the mock of a JSON
+/// service, kept in the `server: MockServer` and `state: Arc<Mutex<State>>`
+/// fields of the mock.
+///
+/// The state starts as `Default` and every request is answered by
+/// `handle(&Mutex<State>, &HttpRequest)`; `path` is the connection point
+/// under the URL of the server. The mock implements `client_builder` itself.
+#[cfg(any(feature = "npd", feature = "egrul", feature = "pb", feature = "bi", feature = "msp"))]
+macro_rules! mock_service {
+    ($mock:ident, $state:ident, $handle:ident, $path:expr, $client:ident) => {
+        impl $mock {
+            /// Starts the server on a free local port.
+            pub fn start() -> ::std::io::Result<$mock> {
+                let state = ::std::sync::Arc::new(::std::sync::Mutex::new($state::default()));
+
+                let server = {
+                    let state = state.clone();
+                    $crate::mock::MockServer::start(move |request| $handle(&state, request))?
+                };
+
+                Ok($mock {
+                    server: server,
+                    state: state,
+                })
+            }
+
+            /// Connection point of the service to pass to the `url` of the builder.
+            pub fn url(&self) -> String {
+                format!("{}{}", self.server.url(), $path)
+            }
+
+            /// Client pointed at the mock.
+            pub fn client(&self) -> $crate::Result<$client> {
+                self.client_builder().build()
+            }
+
+            /// Requests received so far.
+            pub fn requests(&self) -> Vec<$crate::mock::HttpRequest> {
+                self.server.requests()
+            }
+        }
+    };
+}
+
+mod npchk;
+#[cfg(feature = "bi")]
+mod bi;
+#[cfg(feature = "egrul")]
+mod egrul;
+#[cfg(feature = "msp")]
+mod msp;
+#[cfg(feature = "npd")]
+mod npd;
+#[cfg(feature = "pb")]
+mod pb;
+
+pub use self::npchk::{Behavior, MockNpchkServer};
+#[cfg(feature = "bi")]
+pub use self::bi::MockBiServer;
+#[cfg(feature = "egrul")]
+pub use self::egrul::{MockEgrulServer, EXTRACT_PDF};
+#[cfg(feature = "msp")]
+pub use self::msp::MockMspServer;
+#[cfg(feature = "npd")]
+pub use self::npd::{MockNpdServer, NpdBehavior};
+#[cfg(feature = "pb")]
+pub use self::pb::MockPbServer;
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// HTTP request received by the mock server
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// HTTP response sent by the mock server
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: String,
+    pub body: String,
+    /// Delay before the response is sent
+    pub delay: Option<Duration>,
+}
+
+/// Minimal HTTP/1.1 server answering every request with the handler,
+/// one connection per request.
+///
+/// The server is stopped when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<HttpRequest>>>,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HttpRequest {
+    /// Value of the header, the name is case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref key, _)| key.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_str())
+    }
+}
+
+impl HttpResponse {
+    pub fn new<S>(status: u16, content_type: &str, body: S) -> HttpResponse
+    where
+        S: Into<String>,
+    {
+        HttpResponse {
+            status: status,
+            content_type: content_type.into(),
+            body: body.into(),
+            delay: None,
+        }
+    }
+
+    /// Send the response after the delay.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+impl MockServer {
+    /// Starts the server on a free local port.
+    pub fn start<F>(handler: F) -> io::Result<MockServer>
+    where
+        F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(vec![]));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let handler = Arc::new(handler);
+
+        let thread = {
+            let requests = requests.clone();
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+
+                    let handler = handler.clone();
+                    let requests = requests.clone();
+                    thread::spawn(move || {
+                        let _ = serve(stream, &*handler, &requests);
+                    });
+                }
+            })
+        };
+
+        Ok(MockServer {
+            addr: addr,
+            requests: requests,
+            stopped: stopped,
+            thread: Some(thread),
+        })
+    }
+
+    /// Address the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Base URL of the server, without the trailing slash.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Requests received so far.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake up the accepting thread
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve<F>(stream: TcpStream, handler: &F, requests: &Mutex<Vec<HttpRequest>>) -> io::Result<()>
+where
+    F: Fn(&HttpRequest) -> HttpResponse,
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+
+    let mut headers = vec![];
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_right();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(pos) = line.find(':') {
+            let name = line[..pos].trim().to_string();
+            let value = line[pos + 1..].trim().to_string();
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.parse().unwrap_or(0);
+            }
+            headers.push((name, value));
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let request = HttpRequest {
+        method: method,
+        path: path,
+        headers: headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    };
+    let response = handler(&request);
+    requests.lock().unwrap().push(request);
+
+    if let Some(delay) = response.delay {
+        thread::sleep(delay);
+    }
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(response.body.as_bytes())?;
+    stream.flush()?;
+    stream.shutdown(Shutdown::Both)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
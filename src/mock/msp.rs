@@ -0,0 +1,88 @@
+//! Mock of the MSP register service.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{self, Value};
+
+use super::{HttpRequest, HttpResponse, MockServer};
+use msp::{MspClient, MspClientBuilder, MspRecord};
+
+/// Records of the register by INN
+type State = HashMap<String, MspRecord>;
+
+/// In-process server speaking the protocol of `rmsp.nalog.ru`
+///
+/// Finds the records added by `add_record`.
+pub struct MockMspServer {
+    server: MockServer,
+    state: Arc<Mutex<State>>,
+}
+
+mock_service!(MockMspServer, State, handle, "", MspClient);
+
+impl MockMspServer {
+    /// Builder of the client pointed at the mock.
+    pub fn client_builder(&self) -> MspClientBuilder {
+        MspClient::builder().url(self.url())
+    }
+
+    /// Add the record to the register.
+    pub fn add_record(&self, record: MspRecord) {
+        self.state
+            .lock()
+            .unwrap()
+            .insert(record.inn.clone(), record);
+    }
+}
+
+fn handle(records: &Mutex<State>, request: &HttpRequest) -> HttpResponse {
+    if request.path != "/search-proc.json" {
+        return HttpResponse::new(404, "text/plain", "");
+    }
+
+    let query = request
+        .body
+        .split('&')
+        .filter_map(|pair| {
+            let mut pair = pair.splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some("query"), Some(value)) => Some(value),
+                _ => None,
+            }
+        })
+        .next()
+        .unwrap_or("");
+    if query.is_empty() {
+        return reply(
+            400,
+            &json!({"ERRORS": {"query": ["Необходимо указать параметры поиска"]}}),
+        );
+    }
+
+    let data: Vec<Value> = records
+        .lock()
+        .unwrap()
+        .get(query)
+        .map(row)
+        .into_iter()
+        .collect();
+
+    reply(200, &json!({"rowCount": data.len(), "data": data}))
+}
+
+/// Record in the form the service uses, the category as a string.
+fn row(record: &MspRecord) -> Value {
+    json!({
+        "inn": record.inn,
+        "ogrn": record.ogrn,
+        "name_ex": record.name,
+        "category": record.category.code().to_string(),
+        "dtregistry": format!("{} 00:00:00", record.included.format("%d.%m.%Y")),
+        "dtdelete": record.excluded.map(|date| date.format("%d.%m.%Y").to_string()),
+    })
+}
+
+fn reply(status: u16, value: &Value) -> HttpResponse {
+    HttpResponse::new(status, "application/json", serde_json::to_string(value).unwrap())
+}
@@ -0,0 +1,235 @@
+//! Mock of the `FNSNDSCAWS_2` SOAP service.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use xmltree::Element;
+
+use super::{HttpRequest, HttpResponse, MockServer};
+use super::super::{NpchkClient, NpchkClientBuilder, Result, V2_API_REQUEST};
+use rpser;
+use rpser::xml::BuildElement;
+
+const PATH: &'static str = "/FNSNDSCAWS_2";
+
+/// How the mock answers a request
+#[derive(Debug, Clone)]
+pub enum Behavior {
+    /// Answer with the states from the table
+    Normal,
+    /// Answer with the states from the table in the reverse order
+    /// of the request
+    Reversed,
+    /// Answer with the `errMsg` attribute set
+    ErrMsg(String),
+    /// Answer with the SOAP Fault
+    Fault {
+        code: String,
+        string: String,
+        detail: Option<String>,
+    },
+    /// Answer normally after the delay
+    Slow(Duration),
+    /// Answer with a body that is not valid XML
+    Malformed,
+    /// Answer with the HTTP status and an empty body
+    Status(u16),
+}
+
+struct State {
+    states: HashMap<(String, String), i32>,
+    inn_states: HashMap<String, i32>,
+    default_state: i32,
+    dtact_fl: String,
+    dtact_ul: String,
+    behavior: Behavior,
+    queue: VecDeque<Behavior>,
+}
+
+/// In-process server speaking the `NdsRequest2` SOAP protocol
+///
+/// Answers with the states from the configurable table of INN/KPP,
+/// `0` (active) for unknown partners by default.
+pub struct MockNpchkServer {
+    server: MockServer,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockNpchkServer {
+    /// Starts the server on a free local port.
+    pub fn start() -> io::Result<MockNpchkServer> {
+        let state = Arc::new(Mutex::new(State {
+            states: HashMap::new(),
+            inn_states: HashMap::new(),
+            default_state: 0,
+            dtact_fl: "01.01.2018".into(),
+            dtact_ul: "01.01.2018".into(),
+            behavior: Behavior::Normal,
+            queue: VecDeque::new(),
+        }));
+
+        let server = {
+            let state = state.clone();
+            MockServer::start(move |request| handle(&state, request))?
+        };
+
+        Ok(MockNpchkServer {
+            server: server,
+            state: state,
+        })
+    }
+
+    /// Connection point of the service to pass to `NpchkClientBuilder::url`.
+    pub fn url(&self) -> String {
+        format!("{}{}", self.server.url(), PATH)
+    }
+
+    /// Builder of the client pointed at the mock.
+    pub fn client_builder(&self) -> NpchkClientBuilder {
+        NpchkClient::builder().url(self.url())
+    }
+
+    /// Client pointed at the mock.
+    pub fn client(&self) -> Result<NpchkClient> {
+        self.client_builder().build()
+    }
+
+    /// Set the state returned for the INN and KPP.
+    pub fn set_state(&self, inn: &str, kpp: &str, code: i32) {
+        self.state
+            .lock()
+            .unwrap()
+            .states
+            .insert((inn.into(), kpp.into()), code);
+    }
+
+    /// Set the state returned for the INN with any KPP
+    /// not set by `set_state`.
+    pub fn set_inn_state(&self, inn: &str, code: i32) {
+        self.state
+            .lock()
+            .unwrap()
+            .inn_states
+            .insert(inn.into(), code);
+    }
+
+    /// Set the state returned for partners missing from the table.
+    pub fn set_default_state(&self, code: i32) {
+        self.state.lock().unwrap().default_state = code;
+    }
+
+    /// Set the data actuality dates, `dd.mm.yyyy`.
+    pub fn set_actuality(&self, dtact_fl: &str, dtact_ul: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.dtact_fl = dtact_fl.into();
+        state.dtact_ul = dtact_ul.into();
+    }
+
+    /// Answer every following request with the behavior.
+    pub fn respond_with(&self, behavior: Behavior) {
+        self.state.lock().unwrap().behavior = behavior;
+    }
+
+    /// Answer the next request with the behavior, then return
+    /// to the behavior set by `respond_with`.
+    pub fn respond_once_with(&self, behavior: Behavior) {
+        self.state.lock().unwrap().queue.push_back(behavior);
+    }
+
+    /// Requests received so far.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.server.requests()
+    }
+}
+
+fn handle(state: &Mutex<State>, request: &HttpRequest) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    let behavior = match state.queue.pop_front() {
+        Some(behavior) => behavior,
+        None => state.behavior.clone(),
+    };
+
+    match behavior {
+        Behavior::Normal => answer(&state, request, false),
+        Behavior::Reversed => answer(&state, request, true),
+        Behavior::Slow(delay) => answer(&state, request, false).with_delay(delay),
+        Behavior::ErrMsg(err_msg) => soap(
+            200,
+            Element::node("NdsResponse2")
+                .with_attr("xmlns", V2_API_REQUEST)
+                .with_attr("errMsg", err_msg),
+        ),
+        Behavior::Fault {
+            code,
+            string,
+            detail,
+        } => {
+            let mut fault = Element::node("soap:Fault").with_children(vec![
+                Element::node("faultcode").with_text(code),
+                Element::node("faultstring").with_text(string),
+            ]);
+            if let Some(detail) = detail {
+                fault = fault.with_child(Element::node("detail").with_text(detail));
+            }
+            soap(500, fault)
+        }
+        Behavior::Malformed => HttpResponse::new(200, "text/xml", "<soap:Envelope><soap:Body>"),
+        Behavior::Status(status) => HttpResponse::new(status, "text/plain", ""),
+    }
+}
+
+fn answer(state: &State, request: &HttpRequest, reversed: bool) -> HttpResponse {
+    let method = match rpser::Response::from_xml(&request.body) {
+        Ok(method) => method.body,
+        Err(e) => {
+            let fault = Element::node("soap:Fault").with_children(vec![
+                Element::node("faultcode").with_text("soap:Client"),
+                Element::node("faultstring").with_text(e.to_string()),
+            ]);
+            return soap(500, fault);
+        }
+    };
+
+    let mut partners: Vec<Element> = method
+        .children
+        .iter()
+        .map(|np| {
+            let inn = np.get_attr("INN");
+            let kpp = np.get_attr("KPP");
+            let code = state
+                .states
+                .get(&(inn.clone(), kpp.clone()))
+                .or_else(|| state.inn_states.get(&inn))
+                .cloned()
+                .unwrap_or(state.default_state);
+
+            Element::node("NP")
+                .with_attr("INN", inn)
+                .with_attr("KPP", kpp)
+                .with_attr("DT", np.get_attr("DT"))
+                .with_attr("State", code.to_string())
+        })
+        .collect();
+    if reversed {
+        partners.reverse();
+    }
+
+    soap(
+        200,
+        Element::node("NdsResponse2")
+            .with_attr("xmlns", V2_API_REQUEST)
+            .with_attr("DTActFL", state.dtact_fl.clone())
+            .with_attr("DTActUL", state.dtact_ul.clone())
+            .with_children(partners),
+    )
+}
+
+fn soap(status: u16, content: Element) -> HttpResponse {
+    let envelope = Element::node("soap:Envelope")
+        .with_attr("xmlns:soap", "http://schemas.xmlsoap.org/soap/envelope/")
+        .with_child(Element::node("soap:Body").with_child(content));
+
+    HttpResponse::new(status, "text/xml; charset=utf-8", envelope.to_string())
+}
@@ -0,0 +1,189 @@
+//! Mock of the NPD status JSON service.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json;
+
+use super::{HttpRequest, HttpResponse, MockServer};
+use npd::{NpdClient, NpdClientBuilder, NpdStatus, LIMITED_ERROR, VALIDATION_ERROR};
+
+const PATH: &'static str = "/api/v1/tracker/taxpayer_status";
+
+/// How the NPD mock answers a request
+#[derive(Debug, Clone)]
+pub enum NpdBehavior {
+    /// Answer with the statuses from the table
+    Normal,
+    /// Answer with the error of the exceeded rate limit
+    Limited,
+    /// Answer with the error body
+    Error {
+        status: u16,
+        code: String,
+        message: String,
+    },
+    /// Answer normally after the delay
+    Slow(Duration),
+    /// Answer with the HTTP status and an empty body
+    Status(u16),
+}
+
+#[derive(Deserialize)]
+struct Request {
+    inn: String,
+    #[serde(rename = "requestDate")]
+    request_date: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: &'a str,
+}
+
+struct State {
+    statuses: HashMap<String, bool>,
+    rate_limit: Option<(usize, Duration)>,
+    calls: VecDeque<Instant>,
+    behavior: NpdBehavior,
+    queue: VecDeque<NpdBehavior>,
+}
+
+impl Default for State {
+    fn default() -> State {
+        State {
+            statuses: HashMap::new(),
+            rate_limit: None,
+            calls: VecDeque::new(),
+            behavior: NpdBehavior::Normal,
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+/// In-process server speaking the `taxpayer_status` JSON protocol
+///
+/// Answers with the statuses from the configurable table of INN,
+/// `false` (not self-employed) for unknown taxpayers.
+pub struct MockNpdServer {
+    server: MockServer,
+    state: Arc<Mutex<State>>,
+}
+
+mock_service!(MockNpdServer, State, handle, PATH, NpdClient);
+
+impl MockNpdServer {
+    /// Builder of the client pointed at the mock, without the rate limit.
+    pub fn client_builder(&self) -> NpdClientBuilder {
+        NpdClient::builder().url(self.url()).no_rate_limit()
+    }
+
+    /// Set the status returned for the INN.
+    pub fn set_status(&self, inn: &str, status: bool) {
+        self.state
+            .lock()
+            .unwrap()
+            .statuses
+            .insert(inn.into(), status);
+    }
+
+    /// Reject the requests exceeding `requests` in any period of `per`
+    /// the way the service does.
+    pub fn set_rate_limit(&self, requests: usize, per: Duration) {
+        self.state.lock().unwrap().rate_limit = Some((requests, per));
+    }
+
+    /// Answer every following request with the behavior.
+    pub fn respond_with(&self, behavior: NpdBehavior) {
+        self.state.lock().unwrap().behavior = behavior;
+    }
+
+    /// Answer the next request with the behavior, then return
+    /// to the behavior set by `respond_with`.
+    pub fn respond_once_with(&self, behavior: NpdBehavior) {
+        self.state.lock().unwrap().queue.push_back(behavior);
+    }
+}
+
+fn handle(state: &Mutex<State>, request: &HttpRequest) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    let behavior = match state.queue.pop_front() {
+        Some(behavior) => behavior,
+        None => state.behavior.clone(),
+    };
+
+    if let Some((requests, per)) = state.rate_limit {
+        let now = Instant::now();
+        while state
+            .calls
+            .front()
+            .map_or(false, |start| now.duration_since(*start) >= per)
+        {
+            state.calls.pop_front();
+        }
+        if state.calls.len() >= requests {
+            return limited();
+        }
+        state.calls.push_back(now);
+    }
+
+    match behavior {
+        NpdBehavior::Normal => answer(&state, request),
+        NpdBehavior::Slow(delay) => answer(&state, request).with_delay(delay),
+        NpdBehavior::Limited => limited(),
+        NpdBehavior::Error {
+            status,
+            code,
+            message,
+        } => error(status, &code, &message),
+        NpdBehavior::Status(status) => HttpResponse::new(status, "text/plain", ""),
+    }
+}
+
+fn answer(state: &State, request: &HttpRequest) -> HttpResponse {
+    let request: Request = match serde_json::from_str(&request.body) {
+        Ok(request) => request,
+        Err(e) => return error(422, VALIDATION_ERROR, &e.to_string()),
+    };
+
+    let status = state
+        .statuses
+        .get(&request.inn)
+        .cloned()
+        .unwrap_or(false);
+    let message = if status {
+        format!(
+            "{} является плательщиком налога на профессиональный доход",
+            request.inn
+        )
+    } else {
+        format!(
+            "{} не является плательщиком налога на профессиональный доход на {}",
+            request.inn, request.request_date
+        )
+    };
+
+    let body = NpdStatus {
+        status: status,
+        message: message,
+    };
+    HttpResponse::new(200, "application/json", serde_json::to_string(&body).unwrap())
+}
+
+fn limited() -> HttpResponse {
+    error(
+        422,
+        LIMITED_ERROR,
+        "Превышено количество запросов к сервису",
+    )
+}
+
+fn error(status: u16, code: &str, message: &str) -> HttpResponse {
+    let body = ErrorBody {
+        code: code,
+        message: message,
+    };
+    HttpResponse::new(status, "application/json", serde_json::to_string(&body).unwrap())
+}
@@ -0,0 +1,144 @@
+//! Mock of the service "Transparent business".
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{self, Map, Value};
+
+use super::{HttpRequest, HttpResponse, MockServer};
+use pb::{PbClient, PbClientBuilder, RiskFlags};
+
+#[derive(Default)]
+struct State {
+    flags: HashMap<String, RiskFlags>,
+    /// Polls left before the card of the request id is ready
+    pending: HashMap<String, u32>,
+    pending_polls: u32,
+    next_id: u32,
+}
+
+/// In-process server speaking the protocol of `pb.nalog.ru`
+///
+/// Finds the taxpayers set by `set_flags`.
+pub struct MockPbServer {
+    server: MockServer,
+    state: Arc<Mutex<State>>,
+}
+
+mock_service!(MockPbServer, State, handle, "", PbClient);
+
+impl MockPbServer {
+    /// Builder of the client pointed at the mock, polling without a delay.
+    pub fn client_builder(&self) -> PbClientBuilder {
+        PbClient::builder()
+            .url(self.url())
+            .poll(Duration::from_secs(0), 30)
+    }
+
+    /// Add the taxpayer with the signals.
+    pub fn set_flags(&self, flags: RiskFlags) {
+        self.state
+            .lock()
+            .unwrap()
+            .flags
+            .insert(flags.inn.clone(), flags);
+    }
+
+    /// Answer with an empty card to the first `polls` polls of every card.
+    pub fn set_pending(&self, polls: u32) {
+        self.state.lock().unwrap().pending_polls = polls;
+    }
+}
+
+fn handle(state: &Mutex<State>, request: &HttpRequest) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    let form = form(&request.body);
+    let value = |name: &str| form.get(name).cloned().unwrap_or_default();
+
+    match request.path.as_str() {
+        "/search-proc.json" => {
+            let inn = value("queryAll");
+            let data: Vec<Value> = state
+                .flags
+                .get(&inn)
+                .map(|flags| json!({"token": format!("token-{}", flags.inn), "inn": flags.inn}))
+                .into_iter()
+                .collect();
+            let key = if inn.len() == 12 { "ip" } else { "ul" };
+            let mut found = Map::new();
+            found.insert(key.to_string(), json!({ "data": data }));
+            reply(&Value::Object(found))
+        }
+        "/company-proc.json" if value("method") == "get-request" => {
+            state.next_id += 1;
+            let id = state.next_id.to_string();
+            let polls = state.pending_polls;
+            state.pending.insert(id.clone(), polls);
+            reply(&json!({"id": id, "token": value("token")}))
+        }
+        "/company-proc.json" if value("method") == "get-response" => {
+            let ready = match state.pending.get_mut(&value("id")) {
+                Some(left) => if *left > 0 {
+                    *left -= 1;
+                    false
+                } else {
+                    true
+                },
+                None => {
+                    return reply(&json!({"ERROR": "Request not found"}));
+                }
+            };
+            if !ready {
+                return reply(&json!({}));
+            }
+
+            let inn = value("token").trim_left_matches("token-").to_string();
+            match state.flags.get(&inn) {
+                Some(flags) => reply(&card(flags)),
+                None => reply(&json!({"ERROR": "Company not found"})),
+            }
+        }
+        _ => HttpResponse::new(404, "text/plain", ""),
+    }
+}
+
+/// Card with the signals in the forms the service uses.
+fn card(flags: &RiskFlags) -> Value {
+    let list = |raised: bool| if raised {
+        json!([{"cnt": 50}])
+    } else {
+        json!([])
+    };
+    let arrears = match flags.arrears_amount {
+        Some(amount) => json!(amount),
+        None => json!(flags.tax_arrears),
+    };
+
+    json!({
+        "inn": flags.inn,
+        "namec": flags.name,
+        "masaddress": list(flags.mass_address),
+        "masruleader": list(flags.mass_director),
+        "masfounder": list(flags.mass_founder),
+        "rdl": flags.disqualified,
+        "invalid": if flags.unreliable { 1 } else { 0 },
+        "arrears": arrears,
+    })
+}
+
+fn form(body: &str) -> HashMap<String, String> {
+    body.split('&')
+        .filter_map(|pair| {
+            let mut pair = pair.splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some(key), Some(value)) => Some((key.to_string(), value.to_string())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn reply(value: &Value) -> HttpResponse {
+    HttpResponse::new(200, "application/json", serde_json::to_string(value).unwrap())
+}
@@ -0,0 +1,31 @@
+use super::nds_response::NdsResponse;
+use super::partner::Partner;
+use super::super::error::Error;
+
+/// Result of the check split into several requests to the service
+#[derive(Debug)]
+pub struct BatchResponse<'a> {
+    /// Merged responses of the successful chunks,
+    /// `None` if every chunk failed.
+    pub response: Option<NdsResponse<'a>>,
+    /// Chunks the service failed to check.
+    pub failures: Vec<ChunkFailure<'a>>,
+}
+
+/// The chunk of partners that could not be checked
+#[derive(Debug)]
+pub struct ChunkFailure<'a> {
+    /// Zero-based number of the chunk in the order of sending
+    pub index: usize,
+    /// Partners sent in the chunk
+    pub partners: Vec<Partner<'a>>,
+    /// Error returned for the chunk
+    pub error: Error,
+}
+
+impl<'a> BatchResponse<'a> {
+    /// Returns `true` if every chunk was checked successfully.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
@@ -1,2 +1,6 @@
 pub mod partner;
+pub mod partner_state;
 pub mod nds_response;
+pub mod batch_response;
+#[cfg(feature = "serde")]
+pub mod serde_date;
@@ -2,12 +2,55 @@ use chrono::prelude::*;
 use super::partner::Partner;
 
 /// Structure describes the type of data that the server sends to the client
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NdsResponse<'a> {
     /// Date on which relevant data for the individual entrepreneur,
     /// used to check.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_date"))]
     pub dtact_fl: DateTime<Utc>,
     /// Date on which relevant data for legal, used to check.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_date"))]
     pub dtact_ul: DateTime<Utc>,
     pub partners: Vec<Partner<'a>>,
 }
+
+impl<'a> NdsResponse<'a> {
+    /// Appends the partners of another response.
+    ///
+    /// The earliest of the data actuality dates is kept, so the merged
+    /// response never claims fresher data than any of its parts.
+    pub fn merge(&mut self, other: NdsResponse<'a>) {
+        if other.dtact_fl < self.dtact_fl {
+            self.dtact_fl = other.dtact_fl;
+        }
+        if other.dtact_ul < self.dtact_ul {
+            self.dtact_ul = other.dtact_ul;
+        }
+        self.partners.extend(other.partners);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(dtact_fl: (i32, u32, u32), dtact_ul: (i32, u32, u32), inn: &str) -> NdsResponse {
+        NdsResponse {
+            dtact_fl: Utc.ymd(dtact_fl.0, dtact_fl.1, dtact_fl.2).and_hms(0, 0, 0),
+            dtact_ul: Utc.ymd(dtact_ul.0, dtact_ul.1, dtact_ul.2).and_hms(0, 0, 0),
+            partners: vec![Partner::new(inn, "", Utc::now())],
+        }
+    }
+
+    #[test]
+    fn merge_keeps_the_earliest_actuality() {
+        let mut merged = response((2018, 2, 1), (2018, 2, 5), "7707083893");
+        merged.merge(response((2018, 2, 3), (2018, 2, 2), "6648185610"));
+
+        assert_eq!(merged.dtact_fl, Utc.ymd(2018, 2, 1).and_hms(0, 0, 0));
+        assert_eq!(merged.dtact_ul, Utc.ymd(2018, 2, 2).and_hms(0, 0, 0));
+        let inns: Vec<&str> = merged.partners.iter().map(|p| &*p.inn).collect();
+        assert_eq!(inns, vec!["7707083893", "6648185610"]);
+    }
+}
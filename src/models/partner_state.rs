@@ -0,0 +1,345 @@
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
+
+/// Kind of the taxpayer, determines how the state code is interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PartnerKind {
+    /// Legal entity, has a 10-digit Taxpayer identification number
+    LegalEntity,
+    /// Individual entrepreneur, has a 12-digit Taxpayer identification number
+    IndividualEntrepreneur,
+}
+
+impl PartnerKind {
+    /// Determines the kind of the taxpayer by the length of the
+    /// Taxpayer identification number.
+    pub fn from_inn(inn: &str) -> PartnerKind {
+        if inn.chars().count() == 12 {
+            PartnerKind::IndividualEntrepreneur
+        } else {
+            PartnerKind::LegalEntity
+        }
+    }
+}
+
+/// Validation status returned by the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PartnerState {
+    /// 0 - the Taxpayer was in the unified state register of taxpayers
+    /// and had a valid status on the specified date.
+    Active,
+    /// 1 - the Taxpayer was in the unified state register of taxpayers,
+    /// but did not have a valid status on the specified date.
+    Inactive,
+    /// 2 - the Taxpayer was in the unified state register of taxpayers.
+    Registered,
+    /// 3 - the taxpayer was registered with the specified Taxpayer
+    /// identification number, the reason code of registration does not match
+    /// or was not specified. Legal entities only.
+    KppMismatch,
+    /// 4 - the taxpayer with the specified Taxpayer identification number
+    /// was not registered in the unified state register of taxpayers.
+    NotRegistered,
+    /// 5 - incorrect Taxpayer identification number.
+    IncorrectInn,
+    /// 6 - invalid number of characters of the Taxpayer identification number.
+    InvalidInnLength,
+    /// 7 - invalid number of characters in the reason code of registration.
+    /// Legal entities only.
+    InvalidKppLength,
+    /// 8 - invalid characters in the Taxpayer identification number.
+    InvalidInnCharacters,
+    /// 9 - invalid characters in the reason code of registration.
+    /// Legal entities only.
+    InvalidKppCharacters,
+    /// 10 - the reason code of registration should not be used when checking.
+    /// Individual entrepreneurs only.
+    KppNotApplicable,
+    /// 11 - incorrect date format.
+    IncorrectDateFormat,
+    /// 12 - incorrect date (earlier than 01.01.1991 or later than the current date).
+    IncorrectDate,
+    /// A code the service is not documented to return for this kind of taxpayer.
+    Unknown(i32),
+}
+
+impl PartnerState {
+    /// Interprets the code returned by the service for the given kind of taxpayer.
+    pub fn from_code(kind: PartnerKind, code: i32) -> PartnerState {
+        match (code, kind) {
+            (0, _) => PartnerState::Active,
+            (1, _) => PartnerState::Inactive,
+            (2, _) => PartnerState::Registered,
+            (3, PartnerKind::LegalEntity) => PartnerState::KppMismatch,
+            (4, _) => PartnerState::NotRegistered,
+            (5, _) => PartnerState::IncorrectInn,
+            (6, _) => PartnerState::InvalidInnLength,
+            (7, PartnerKind::LegalEntity) => PartnerState::InvalidKppLength,
+            (8, _) => PartnerState::InvalidInnCharacters,
+            (9, PartnerKind::LegalEntity) => PartnerState::InvalidKppCharacters,
+            (10, PartnerKind::IndividualEntrepreneur) => PartnerState::KppNotApplicable,
+            (11, _) => PartnerState::IncorrectDateFormat,
+            (12, _) => PartnerState::IncorrectDate,
+            (other, _) => PartnerState::Unknown(other),
+        }
+    }
+
+    /// The numeric code as it is sent by the service.
+    pub fn code(&self) -> i32 {
+        match *self {
+            PartnerState::Active => 0,
+            PartnerState::Inactive => 1,
+            PartnerState::Registered => 2,
+            PartnerState::KppMismatch => 3,
+            PartnerState::NotRegistered => 4,
+            PartnerState::IncorrectInn => 5,
+            PartnerState::InvalidInnLength => 6,
+            PartnerState::InvalidKppLength => 7,
+            PartnerState::InvalidInnCharacters => 8,
+            PartnerState::InvalidKppCharacters => 9,
+            PartnerState::KppNotApplicable => 10,
+            PartnerState::IncorrectDateFormat => 11,
+            PartnerState::IncorrectDate => 12,
+            PartnerState::Unknown(code) => code,
+        }
+    }
+
+    /// The taxpayer had a valid status on the specified date.
+    pub fn is_active(&self) -> bool {
+        *self == PartnerState::Active
+    }
+
+    /// The taxpayer is present in the unified state register of taxpayers.
+    pub fn is_registered(&self) -> bool {
+        match *self {
+            PartnerState::Active
+            | PartnerState::Inactive
+            | PartnerState::Registered
+            | PartnerState::KppMismatch => true,
+            _ => false,
+        }
+    }
+
+    /// The service rejected the request data for this partner.
+    pub fn is_input_error(&self) -> bool {
+        match *self {
+            PartnerState::IncorrectInn
+            | PartnerState::InvalidInnLength
+            | PartnerState::InvalidKppLength
+            | PartnerState::InvalidInnCharacters
+            | PartnerState::InvalidKppCharacters
+            | PartnerState::KppNotApplicable
+            | PartnerState::IncorrectDateFormat
+            | PartnerState::IncorrectDate => true,
+            _ => false,
+        }
+    }
+
+    /// Name of the variant, e.g. `"KppMismatch"`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PartnerState::Active => "Active",
+            PartnerState::Inactive => "Inactive",
+            PartnerState::Registered => "Registered",
+            PartnerState::KppMismatch => "KppMismatch",
+            PartnerState::NotRegistered => "NotRegistered",
+            PartnerState::IncorrectInn => "IncorrectInn",
+            PartnerState::InvalidInnLength => "InvalidInnLength",
+            PartnerState::InvalidKppLength => "InvalidKppLength",
+            PartnerState::InvalidInnCharacters => "InvalidInnCharacters",
+            PartnerState::InvalidKppCharacters => "InvalidKppCharacters",
+            PartnerState::KppNotApplicable => "KppNotApplicable",
+            PartnerState::IncorrectDateFormat => "IncorrectDateFormat",
+            PartnerState::IncorrectDate => "IncorrectDate",
+            PartnerState::Unknown(_) => "Unknown",
+        }
+    }
+
+    /// Human-readable meaning of the state.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            PartnerState::Active => "The taxpayer is registered and has a valid status",
+            PartnerState::Inactive => "The taxpayer is registered, but does not have a valid status",
+            PartnerState::Registered => "The taxpayer is registered",
+            PartnerState::KppMismatch => {
+                "The taxpayer is registered, the reason code of registration does not match"
+            }
+            PartnerState::NotRegistered => "The taxpayer is not registered",
+            PartnerState::IncorrectInn => "Incorrect taxpayer identification number",
+            PartnerState::InvalidInnLength => {
+                "Invalid number of characters of the taxpayer identification number"
+            }
+            PartnerState::InvalidKppLength => {
+                "Invalid number of characters in the reason code of registration"
+            }
+            PartnerState::InvalidInnCharacters => {
+                "Invalid characters in the taxpayer identification number"
+            }
+            PartnerState::InvalidKppCharacters => {
+                "Invalid characters in the reason code of registration"
+            }
+            PartnerState::KppNotApplicable => {
+                "The reason code of registration should not be used when checking"
+            }
+            PartnerState::IncorrectDateFormat => "Incorrect date format",
+            PartnerState::IncorrectDate => "Incorrect date",
+            PartnerState::Unknown(_) => "Unknown state",
+        }
+    }
+}
+
+impl fmt::Display for PartnerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} - {}", self.code(), self.description())
+    }
+}
+
+/// The state is written as the code of the service along with the name
+/// of the variant and its meaning:
+/// `{"code": 3, "name": "KppMismatch", "description": "..."}`
+#[cfg(feature = "serde")]
+impl Serialize for PartnerState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("PartnerState", 3)?;
+        state.serialize_field("code", &self.code())?;
+        state.serialize_field("name", self.name())?;
+        state.serialize_field("description", self.description())?;
+        state.end()
+    }
+}
+
+/// The state is restored from the code and the name, the description is ignored.
+///
+/// Without the name, as written by the earlier versions, codes 3, 7 and 9
+/// are read as states of a legal entity and code 10 as a state of an
+/// individual entrepreneur, as the service returns them.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for PartnerState {
+    fn deserialize<D>(deserializer: D) -> Result<PartnerState, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Repr {
+            code: i32,
+            #[serde(default)]
+            name: Option<String>,
+        }
+
+        let repr = Repr::deserialize(deserializer)?;
+        let candidates = [
+            PartnerState::from_code(PartnerKind::LegalEntity, repr.code),
+            PartnerState::from_code(PartnerKind::IndividualEntrepreneur, repr.code),
+        ];
+
+        match repr.name {
+            Some(ref name) => candidates
+                .iter()
+                .find(|state| state.name() == name.as_str())
+                .cloned()
+                .ok_or_else(|| {
+                    let msg = format!("state {} does not have the code {}", name, repr.code);
+                    de::Error::custom(msg)
+                }),
+            None if repr.code == 10 => Ok(candidates[1]),
+            None => Ok(candidates[0]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LE: PartnerKind = PartnerKind::LegalEntity;
+    const IE: PartnerKind = PartnerKind::IndividualEntrepreneur;
+
+    #[test]
+    fn codes_round_trip() {
+        for code in 0..13 {
+            let kind = if code == 10 { IE } else { LE };
+            let state = PartnerState::from_code(kind, code);
+            assert_eq!(state.code(), code);
+            assert!(state != PartnerState::Unknown(code), "code {}", code);
+        }
+        assert_eq!(PartnerState::from_code(LE, 13), PartnerState::Unknown(13));
+        assert_eq!(PartnerState::from_code(IE, -1), PartnerState::Unknown(-1));
+    }
+
+    #[test]
+    fn kpp_codes_depend_on_the_kind() {
+        assert_eq!(PartnerState::from_code(LE, 3), PartnerState::KppMismatch);
+        assert_eq!(PartnerState::from_code(LE, 7), PartnerState::InvalidKppLength);
+        assert_eq!(PartnerState::from_code(LE, 9), PartnerState::InvalidKppCharacters);
+        assert_eq!(PartnerState::from_code(LE, 10), PartnerState::Unknown(10));
+
+        assert_eq!(PartnerState::from_code(IE, 3), PartnerState::Unknown(3));
+        assert_eq!(PartnerState::from_code(IE, 7), PartnerState::Unknown(7));
+        assert_eq!(PartnerState::from_code(IE, 9), PartnerState::Unknown(9));
+        assert_eq!(PartnerState::from_code(IE, 10), PartnerState::KppNotApplicable);
+
+        assert_eq!(PartnerState::from_code(IE, 0), PartnerState::Active);
+        assert_eq!(PartnerState::from_code(IE, 12), PartnerState::IncorrectDate);
+    }
+
+    #[test]
+    fn kind_is_determined_by_the_inn() {
+        assert_eq!(PartnerKind::from_inn("7707083893"), LE);
+        assert_eq!(PartnerKind::from_inn("500100732259"), IE);
+    }
+
+    #[test]
+    fn predicates() {
+        let registered = [0, 1, 2, 3];
+        let input_errors = [5, 6, 7, 8, 9, 11, 12];
+
+        for code in 0..13 {
+            let state = PartnerState::from_code(LE, code);
+            assert_eq!(state.is_active(), code == 0, "code {}", code);
+            assert_eq!(state.is_registered(), registered.contains(&code), "code {}", code);
+            assert_eq!(state.is_input_error(), input_errors.contains(&code), "code {}", code);
+        }
+
+        assert!(PartnerState::KppNotApplicable.is_input_error());
+        assert!(!PartnerState::NotRegistered.is_registered());
+        assert!(!PartnerState::NotRegistered.is_input_error());
+        assert!(!PartnerState::Unknown(42).is_registered());
+        assert!(!PartnerState::Unknown(42).is_input_error());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn serde_round_trip_keeps_the_kind() {
+        use serde_json;
+
+        for &kind in &[LE, IE] {
+            for code in -1..14 {
+                let state = PartnerState::from_code(kind, code);
+                let json = serde_json::to_string(&state).unwrap();
+                let read: PartnerState = serde_json::from_str(&json).unwrap();
+                assert_eq!(read, state, "{}", json);
+            }
+        }
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn serde_reads_the_code_alone() {
+        use serde_json;
+
+        let read = |json: &str| serde_json::from_str::<PartnerState>(json).unwrap();
+
+        assert_eq!(read(r#"{"code": 3}"#), PartnerState::KppMismatch);
+        assert_eq!(read(r#"{"code": 10}"#), PartnerState::KppNotApplicable);
+        assert_eq!(read(r#"{"code": 3, "name": "Unknown"}"#), PartnerState::Unknown(3));
+        assert!(serde_json::from_str::<PartnerState>(r#"{"code": 3, "name": "Active"}"#).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+//! Serialization of the dates of the models.
+//!
+//! The models write the dates in the `dd.mm.yyyy` format used by the service.
+//! Both `dd.mm.yyyy` and ISO 8601 are accepted when reading.
+//!
+//! `Iso8601` writes a `Partner` or an `NdsResponse` with ISO 8601 dates:
+//!
+//! ```rust,ignore
+//! let json = serde_json::to_string(&Iso8601(&response))?;
+//! ```
+//!
+//! The functions are usable with `#[serde(with = "...")]` on the fields
+//! of the caller's own types; `iso8601` writes the dates as ISO 8601 instead:
+//!
+//! ```rust,ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Row {
+//!     #[serde(with = "npchk::models::serde_date::iso8601")]
+//!     checked_at: DateTime<Utc>,
+//! }
+//! ```
+
+use chrono::prelude::*;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeStruct;
+
+use super::nds_response::NdsResponse;
+use super::partner::Partner;
+
+/// Model written with the dates as ISO 8601 instead of `dd.mm.yyyy`
+#[derive(Debug, Clone, Copy)]
+pub struct Iso8601<'a, T: 'a>(pub &'a T);
+
+/// Date written as ISO 8601
+struct IsoDate<'a>(&'a DateTime<Utc>);
+
+impl<'a> Serialize for IsoDate<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        iso8601::serialize(self.0, serializer)
+    }
+}
+
+impl<'a, 'p> Serialize for Iso8601<'a, Partner<'p>> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let partner = self.0;
+        let mut state = serializer.serialize_struct("Partner", 4)?;
+        state.serialize_field("inn", &partner.inn)?;
+        state.serialize_field("kpp", &partner.kpp)?;
+        state.serialize_field("dt", &IsoDate(&partner.dt))?;
+        state.serialize_field("state", &partner.state)?;
+        state.end()
+    }
+}
+
+impl<'a, 'p> Serialize for Iso8601<'a, NdsResponse<'p>> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let rsp = self.0;
+        let partners: Vec<Iso8601<Partner>> = rsp.partners.iter().map(Iso8601).collect();
+        let mut state = serializer.serialize_struct("NdsResponse", 3)?;
+        state.serialize_field("dtact_fl", &IsoDate(&rsp.dtact_fl))?;
+        state.serialize_field("dtact_ul", &IsoDate(&rsp.dtact_ul))?;
+        state.serialize_field("partners", &partners)?;
+        state.end()
+    }
+}
+
+/// Writes the date as `dd.mm.yyyy`.
+pub fn serialize<S>(dt: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&dt.format("%d.%m.%Y").to_string())
+}
+
+/// Reads the date as ISO 8601 or `dd.mm.yyyy`.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+
+    match DateTime::parse_from_rfc3339(&value) {
+        Ok(dt) => Ok(dt.with_timezone(&Utc)),
+        Err(_) => Utc.datetime_from_str(&format!("{} 00:00:00", value), "%d.%m.%Y %H:%M:%S")
+            .map_err(de::Error::custom),
+    }
+}
+
+/// Dates written as ISO 8601.
+pub mod iso8601 {
+    use chrono::prelude::*;
+    use serde::{Deserializer, Serializer};
+
+    /// Writes the date as ISO 8601.
+    pub fn serialize<S>(dt: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&dt.to_rfc3339())
+    }
+
+    /// Reads the date as ISO 8601 or `dd.mm.yyyy`.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        super::deserialize(deserializer)
+    }
+}
+
+#[cfg(all(test, feature = "serde_json"))]
+mod tests {
+    use chrono::prelude::*;
+    use serde_json;
+
+    use models::nds_response::NdsResponse;
+    use models::partner::Partner;
+    use models::partner_state::PartnerState;
+    use super::Iso8601;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Row {
+        #[serde(with = "super::iso8601")]
+        checked_at: DateTime<Utc>,
+    }
+
+    #[test]
+    fn models_round_trip() {
+        let dt = Utc.ymd(2018, 2, 1).and_hms(0, 0, 0);
+        let mut partner = Partner::new("7707083893", "773601001", dt);
+        partner.state = Some(PartnerState::KppMismatch);
+        let rsp = NdsResponse {
+            dtact_fl: Utc.ymd(2018, 1, 30).and_hms(0, 0, 0),
+            dtact_ul: Utc.ymd(2018, 1, 31).and_hms(0, 0, 0),
+            partners: vec![partner],
+        };
+
+        let json = serde_json::to_string(&rsp).unwrap();
+        assert!(json.contains(r#""dt":"01.02.2018""#), "{}", json);
+        let read: NdsResponse = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(read.dtact_fl, rsp.dtact_fl);
+        assert_eq!(read.dtact_ul, rsp.dtact_ul);
+        assert_eq!(read.partners[0].inn, "7707083893");
+        assert_eq!(read.partners[0].dt, rsp.partners[0].dt);
+        assert_eq!(read.partners[0].state, Some(PartnerState::KppMismatch));
+    }
+
+    #[test]
+    fn models_are_written_in_both_formats() {
+        let dt = Utc.ymd(2018, 2, 1).and_hms(0, 0, 0);
+        let rsp = NdsResponse {
+            dtact_fl: Utc.ymd(2018, 1, 30).and_hms(0, 0, 0),
+            dtact_ul: Utc.ymd(2018, 1, 31).and_hms(0, 0, 0),
+            partners: vec![Partner::new("7707083893", "773601001", dt)],
+        };
+
+        let json = serde_json::to_string(&rsp).unwrap();
+        assert!(json.contains(r#""dtact_fl":"30.01.2018""#), "{}", json);
+        assert!(json.contains(r#""dt":"01.02.2018""#), "{}", json);
+
+        let json = serde_json::to_string(&Iso8601(&rsp)).unwrap();
+        assert!(json.contains(r#""dtact_fl":"2018-01-30T00:00:00+00:00""#), "{}", json);
+        assert!(json.contains(r#""dtact_ul":"2018-01-31T00:00:00+00:00""#), "{}", json);
+        assert!(json.contains(r#""dt":"2018-02-01T00:00:00+00:00""#), "{}", json);
+        let read: NdsResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.dtact_fl, rsp.dtact_fl);
+        assert_eq!(read.partners[0].dt, dt);
+
+        let json = serde_json::to_string(&Iso8601(&rsp.partners[0])).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"inn":"7707083893","kpp":"773601001","#,
+                r#""dt":"2018-02-01T00:00:00+00:00","state":null}"#
+            )
+        );
+    }
+
+    #[test]
+    fn iso8601_round_trip() {
+        let row = Row {
+            checked_at: Utc.ymd(2018, 2, 1).and_hms(10, 20, 30),
+        };
+
+        let json = serde_json::to_string(&row).unwrap();
+        assert_eq!(json, r#"{"checked_at":"2018-02-01T10:20:30+00:00"}"#);
+        assert_eq!(serde_json::from_str::<Row>(&json).unwrap(), row);
+        let read: Row = serde_json::from_str(r#"{"checked_at":"01.02.2018"}"#).unwrap();
+        assert_eq!(read.checked_at, Utc.ymd(2018, 2, 1).and_hms(0, 0, 0));
+    }
+}
@@ -0,0 +1,333 @@
+//! Reader of the open-data dump of the register.
+//!
+//! The dump is published at
+//! [https://www.nalog.ru/opendata/7707329152-rsmp/](https://www.nalog.ru/opendata/7707329152-rsmp/)
+//! as a ZIP archive of XML files, each with up to a thousand `Документ`
+//! elements in UTF-8 or windows-1251. Every document carries the date
+//! of the state of the register, `ДатаСост`, the same in the whole dump.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+use encoding_rs::WINDOWS_1251;
+use xml::reader::{EventReader, XmlEvent};
+
+use super::{MspCategory, MspIndex, MspRecord};
+use super::super::{error, Result};
+use service::parse_date;
+
+/// Records of a file of the dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dump {
+    /// Date of the state of the register, `None` if the file has no documents
+    pub date: Option<NaiveDate>,
+    pub records: Vec<MspRecord>,
+}
+
+/// Record under construction while reading a `Документ` element
+#[derive(Default)]
+struct Document {
+    id: String,
+    date: Option<NaiveDate>,
+    inn: String,
+    ogrn: Option<String>,
+    name: String,
+    category: Option<MspCategory>,
+    included: Option<NaiveDate>,
+}
+
+/// Reads the records of a file of the dump.
+///
+/// The dump lists the taxpayers in the register on the date of the dump,
+/// so `excluded` of every record is `None`.
+pub fn read_dump<R: Read>(mut reader: R) -> Result<Dump> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let text = decode(&bytes);
+
+    let mut dump = Dump {
+        date: None,
+        records: Vec::new(),
+    };
+    let mut document: Option<Document> = None;
+
+    for event in EventReader::new(text.as_bytes()) {
+        let event = event.map_err(|e| error::Error::MspError(format!("Invalid dump: {}", e)))?;
+        match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let attr = |key: &str| {
+                    attributes
+                        .iter()
+                        .find(|a| a.name.local_name == key)
+                        .map(|a| a.value.trim().to_string())
+                };
+
+                match name.local_name.as_str() {
+                    "Документ" => {
+                        document = Some(Document {
+                            id: attr("ИдДок").unwrap_or_default(),
+                            date: attr("ДатаСост").and_then(|date| parse_date(&date)),
+                            category: attr("КатСубМСП")
+                                .and_then(|code| code.parse().ok())
+                                .and_then(MspCategory::from_code),
+                            included: attr("ДатаВклМСП").and_then(|date| parse_date(&date)),
+                            ..Document::default()
+                        });
+                    }
+                    "ОргВклМСП" => if let Some(ref mut document) = document {
+                        document.inn = attr("ИННЮЛ").unwrap_or_default();
+                        document.ogrn = attr("ОГРН");
+                        document.name = attr("НаимОрг").unwrap_or_default();
+                    },
+                    "ИПВклМСП" => if let Some(ref mut document) = document {
+                        document.inn = attr("ИННФЛ").unwrap_or_default();
+                        document.ogrn = attr("ОГРНИП");
+                    },
+                    "ФИОИП" => if let Some(ref mut document) = document {
+                        let parts: Vec<String> = ["Фамилия", "Имя", "Отчество"]
+                            .iter()
+                            .filter_map(|key| attr(*key))
+                            .filter(|part| !part.is_empty())
+                            .collect();
+                        document.name = parts.join(" ");
+                    },
+                    _ => {}
+                }
+            }
+            XmlEvent::EndElement { ref name } if name.local_name == "Документ" => {
+                if let Some(document) = document.take() {
+                    let date = document.date;
+                    if dump.date.is_some() && dump.date != date {
+                        return Err(error::Error::MspError(format!(
+                            "The document {} is of another date than the dump",
+                            document.id
+                        )));
+                    }
+                    dump.date = date;
+                    dump.records.push(document.into_record()?);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(dump)
+}
+
+/// Loads the file of the dump, or every `*.xml` file of the directory
+/// with the extracted archive, into the index.
+///
+/// Returns the number of the records loaded. The archive itself is not
+/// read, it has to be extracted first. The dumps of several dates may be
+/// loaded into the same index, each in full.
+///
+/// Every file is read before the index is changed, and the files of the
+/// directory have to be of one date: the dump is stored with one `put_all`,
+/// so a bad file leaves the index as it was.
+pub fn load_dump<I, P>(index: &I, path: P) -> Result<usize>
+where
+    I: MspIndex + ?Sized,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let files = if path.is_dir() {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let file = entry?.path();
+            let is_xml = file.extension()
+                .and_then(|ext| ext.to_str())
+                .map_or(false, |ext| ext.eq_ignore_ascii_case("xml"));
+            if is_xml {
+                files.push(file);
+            }
+        }
+        files.sort();
+        files
+    } else {
+        vec![PathBuf::from(path)]
+    };
+
+    let mut loaded = Dump {
+        date: None,
+        records: vec![],
+    };
+    for file in files {
+        debug!("Reading the MSP register dump {}", file.display());
+        let dump = read_dump(File::open(&file)?)?;
+        if dump.date.is_none() {
+            continue;
+        }
+        if loaded.date.is_some() && loaded.date != dump.date {
+            return Err(error::Error::MspError(format!(
+                "The file {} is of another date than the dump",
+                file.display()
+            )));
+        }
+
+        loaded.date = dump.date;
+        loaded.records.extend(dump.records);
+    }
+
+    match loaded.date {
+        Some(date) => {
+            index.put_all(date, &loaded.records)?;
+            Ok(loaded.records.len())
+        }
+        None => Ok(0),
+    }
+}
+
+impl Document {
+    fn into_record(self) -> Result<MspRecord> {
+        let id = self.id.clone();
+        let missing = |what: &str| {
+            error::Error::MspError(format!("Missing {} in the document {}", what, id))
+        };
+        if self.inn.is_empty() {
+            return Err(missing("INN"));
+        }
+        if self.date.is_none() {
+            return Err(missing("date of the state"));
+        }
+        let category = self.category.ok_or_else(|| missing("category"))?;
+        let included = self.included.ok_or_else(|| missing("date of the inclusion"))?;
+
+        Ok(MspRecord {
+            inn: self.inn,
+            ogrn: self.ogrn,
+            name: self.name,
+            category: category,
+            included: included,
+            excluded: None,
+        })
+    }
+}
+
+/// Decodes the file in windows-1251 if declared so, and drops the
+/// declaration the XML reader would check against the decoded text.
+fn decode(bytes: &[u8]) -> String {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(200)]).to_lowercase();
+    let text = if head.contains("windows-1251") {
+        WINDOWS_1251.decode(bytes).0.into_owned()
+    } else {
+        String::from_utf8_lossy(bytes).into_owned()
+    };
+
+    let text = text.trim_left_matches('\u{feff}');
+    match (text.starts_with("<?xml"), text.find("?>")) {
+        (true, Some(end)) => text[end + 2..].to_string(),
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+    use msp::MemoryIndex;
+
+    const DUMP: &'static str = r#"<?xml version="1.0" encoding="windows-1251"?>
+<Файл ИдФайл="VO_RRMSPSV_0000_9965_20200710_0001" ВерсФорм="4.01" ТипИнф="РЕЕСТРМСП" КолДок="2">
+  <ИдОтпр><ФИООтв Фамилия="Иванов" Имя="Иван"/></ИдОтпр>
+  <Документ ИдДок="0001" ДатаСост="10.07.2020" ДатаВклМСП="10.08.2016" ВидСубМСП="1" КатСубМСП="2" ПризНовМСП="2">
+    <ОргВклМСП НаимОрг="ОБЩЕСТВО С ОГРАНИЧЕННОЙ ОТВЕТСТВЕННОСТЬЮ &quot;РОМАШКА&quot;" ИННЮЛ="6648185610" ОГРН="1069648001080"/>
+    <СведМН КодРегион="66"/>
+  </Документ>
+  <Документ ИдДок="0002" ДатаСост="10.07.2020" ДатаВклМСП="01.08.2016" ВидСубМСП="2" КатСубМСП="1" ПризНовМСП="2">
+    <ИПВклМСП ИННФЛ="500100732259" ОГРНИП="304500116000157">
+      <ФИОИП Фамилия="ПЕТРОВ" Имя="ПЕТР" Отчество="ПЕТРОВИЧ"/>
+    </ИПВклМСП>
+  </Документ>
+</Файл>"#;
+
+    #[test]
+    fn reads_the_windows_1251_dump() {
+        let bytes = WINDOWS_1251.encode(DUMP).0.into_owned();
+        let dump = read_dump(&bytes[..]).unwrap();
+        let records = dump.records;
+
+        assert_eq!(dump.date, Some(NaiveDate::from_ymd(2020, 7, 10)));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].inn, "6648185610");
+        assert_eq!(records[0].ogrn, Some("1069648001080".to_string()));
+        assert_eq!(
+            records[0].name,
+            "ОБЩЕСТВО С ОГРАНИЧЕННОЙ ОТВЕТСТВЕННОСТЬЮ \"РОМАШКА\""
+        );
+        assert_eq!(records[0].category, MspCategory::Small);
+        assert_eq!(records[0].included, NaiveDate::from_ymd(2016, 8, 10));
+        assert_eq!(records[1].inn, "500100732259");
+        assert_eq!(records[1].name, "ПЕТРОВ ПЕТР ПЕТРОВИЧ");
+        assert_eq!(records[1].category, MspCategory::Micro);
+        assert!(records[1].excluded.is_none());
+    }
+
+    #[test]
+    fn rejects_the_document_without_category() {
+        let dump = DUMP.replace(" КатСубМСП=\"1\"", "");
+
+        assert!(read_dump(dump.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_the_documents_of_different_dates() {
+        let dump = DUMP.replacen("ДатаСост=\"10.07.2020\"", "ДатаСост=\"10.06.2020\"", 1);
+
+        assert!(read_dump(dump.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn loads_the_directory() {
+        let dir = env::temp_dir().join(format!("npchk-msp-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("data-0001.xml"), WINDOWS_1251.encode(DUMP).0).unwrap();
+        fs::write(dir.join("readme.txt"), "not a part of the dump").unwrap();
+
+        let index = MemoryIndex::new();
+        let count = load_dump(&index, &dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(count.unwrap(), 2);
+        assert_eq!(index.dates().unwrap(), vec![NaiveDate::from_ymd(2020, 7, 10)]);
+        let date = NaiveDate::from_ymd(2020, 1, 1);
+        let record = index.get_on("500100732259", date).unwrap().unwrap();
+        assert_eq!(record.category, MspCategory::Micro);
+    }
+
+    #[test]
+    fn bad_file_leaves_the_index_unchanged() {
+        let dir = env::temp_dir().join(format!("npchk-msp-bad-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let bad = DUMP.replace(" КатСубМСП=\"1\"", "");
+        fs::write(dir.join("data-0001.xml"), WINDOWS_1251.encode(DUMP).0).unwrap();
+        fs::write(dir.join("data-0002.xml"), WINDOWS_1251.encode(&bad).0).unwrap();
+
+        let index = MemoryIndex::new();
+        let result = load_dump(&index, &dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+        assert!(index.dates().unwrap().is_empty());
+        assert_eq!(index.get("6648185610").unwrap(), None);
+    }
+
+    #[test]
+    fn files_of_different_dates_are_rejected() {
+        let dir = env::temp_dir().join(format!("npchk-msp-dates-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let older = DUMP.replace("ДатаСост=\"10.07.2020\"", "ДатаСост=\"10.06.2020\"");
+        fs::write(dir.join("data-0001.xml"), WINDOWS_1251.encode(DUMP).0).unwrap();
+        fs::write(dir.join("data-0002.xml"), WINDOWS_1251.encode(&older).0).unwrap();
+
+        let index = MemoryIndex::new();
+        let result = load_dump(&index, &dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+        assert!(index.dates().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,428 @@
+//! Lookups in the register of small and medium-sized businesses (MSP)
+//! [https://rmsp.nalog.ru/](https://rmsp.nalog.ru/)
+//!
+//! Available with the `msp` feature. `MspClient` looks up the current record
+//! of the taxpayer in the service. For offline bulk lookups the open-data
+//! dumps of the register are loaded into an `MspIndex` by `load_dump`, either
+//! kept in memory or, with the `sqlite` feature, in an SQLite database.
+//! The index keeps the records of every loaded dump, so the category of
+//! the taxpayer on a past date is taken from the dump of that time.
+
+mod dump;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use self::dump::{load_dump, read_dump, Dump};
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteIndex;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+
+use chrono::prelude::*;
+use serde_json::Value;
+
+use super::{error, Result};
+use service::{self, parse_date, Call, Service, ServiceConfig};
+
+/// The default connection point of the service
+pub const MSP_URL: &'static str = "https://rmsp.nalog.ru";
+
+const METHOD: &'static str = "msp_search";
+
+/// Category of the business
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MspCategory {
+    /// 1 - micro-enterprise
+    Micro,
+    /// 2 - small enterprise
+    Small,
+    /// 3 - medium-sized enterprise
+    Medium,
+}
+
+/// Record of the register
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MspRecord {
+    pub inn: String,
+    /// OGRN of the legal entity or OGRNIP of the entrepreneur
+    pub ogrn: Option<String>,
+    /// Name of the legal entity or full name of the entrepreneur
+    pub name: String,
+    pub category: MspCategory,
+    /// Date of the inclusion in the register
+    pub included: NaiveDate,
+    /// Date of the exclusion from the register, if excluded
+    pub excluded: Option<NaiveDate>,
+}
+
+/// Storage of the records of the dumps of the register, keyed by INN
+/// and the date of the dump
+///
+/// Every dump lists the taxpayers in the register on its date, so the
+/// taxpayer missing from a later dump is considered excluded on the date
+/// of that dump. A dump has to be loaded in full, otherwise the taxpayers
+/// of the files not loaded look excluded.
+pub trait MspIndex {
+    /// Stores the record of the dump of the date, replacing the one
+    /// of the INN loaded from that dump before.
+    fn put(&self, date: NaiveDate, record: &MspRecord) -> Result<()>;
+
+    /// Stores the records of the dump of the date.
+    fn put_all(&self, date: NaiveDate, records: &[MspRecord]) -> Result<()> {
+        for record in records {
+            self.put(date, record)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the dates of the loaded dumps in ascending order.
+    fn dates(&self) -> Result<Vec<NaiveDate>>;
+
+    /// Returns the records of the taxpayer with the dates of their dumps
+    /// in ascending order.
+    fn history(&self, inn: &str) -> Result<Vec<(NaiveDate, MspRecord)>>;
+
+    /// Returns the record of the taxpayer from the latest dump listing it.
+    ///
+    /// Unless the record has one, `excluded` is the date of the first
+    /// later dump without the taxpayer.
+    fn get(&self, inn: &str) -> Result<Option<MspRecord>> {
+        Ok(record_on(self.history(inn)?, &self.dates()?, None))
+    }
+
+    /// Returns the record of the taxpayer if it was in the register
+    /// on the date.
+    ///
+    /// The record, and so the category, is taken from the latest dump
+    /// on or before the date, or from the earliest one if the date
+    /// precedes every dump listing the taxpayer.
+    fn get_on(&self, inn: &str, date: NaiveDate) -> Result<Option<MspRecord>> {
+        Ok(record_on(self.history(inn)?, &self.dates()?, Some(date))
+            .and_then(|record| if record.is_included_on(date) {
+                Some(record)
+            } else {
+                None
+            }))
+    }
+}
+
+/// Index kept in memory for the lifetime of the process
+#[derive(Debug, Default)]
+pub struct MemoryIndex {
+    dumps: Mutex<BTreeMap<NaiveDate, HashMap<String, MspRecord>>>,
+}
+
+/// Answer of the search, as sent by the service
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Reply {
+    data: Vec<Row>,
+}
+
+/// Record of the register, as sent by the service
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Row {
+    inn: String,
+    ogrn: Option<String>,
+    name_ex: String,
+    /// Code of the category, a number or a string
+    category: Value,
+    dtregistry: Option<String>,
+    dtdelete: Option<String>,
+}
+
+/// Client of the MSP register service
+#[derive(Debug)]
+pub struct MspClient {
+    service: Service,
+}
+
+/// Builder of the `MspClient`
+#[derive(Debug, Clone)]
+pub struct MspClientBuilder {
+    config: ServiceConfig,
+}
+
+impl MspCategory {
+    /// The category by the code of the register, `1` to `3`.
+    pub fn from_code(code: u8) -> Option<MspCategory> {
+        match code {
+            1 => Some(MspCategory::Micro),
+            2 => Some(MspCategory::Small),
+            3 => Some(MspCategory::Medium),
+            _ => None,
+        }
+    }
+
+    /// The code of the category in the register.
+    pub fn code(&self) -> u8 {
+        match *self {
+            MspCategory::Micro => 1,
+            MspCategory::Small => 2,
+            MspCategory::Medium => 3,
+        }
+    }
+
+    /// Human-readable name of the category.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            MspCategory::Micro => "Micro-enterprise",
+            MspCategory::Small => "Small enterprise",
+            MspCategory::Medium => "Medium-sized enterprise",
+        }
+    }
+}
+
+impl fmt::Display for MspCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} - {}", self.code(), self.description())
+    }
+}
+
+impl MspRecord {
+    /// Returns `true` if the taxpayer was in the register on the date.
+    pub fn is_included_on(&self, date: NaiveDate) -> bool {
+        self.included <= date && self.excluded.map_or(true, |excluded| date < excluded)
+    }
+
+    fn from_row(row: Row) -> Result<MspRecord> {
+        let category = match row.category {
+            Value::Number(ref n) => n.as_u64().map(|n| n as u8),
+            Value::String(ref s) => s.trim().parse().ok(),
+            _ => None,
+        };
+        let category = category.and_then(MspCategory::from_code).ok_or_else(|| {
+            error::Error::MspError(format!("Unknown category {} of {}", row.category, row.inn))
+        })?;
+        let included = row.dtregistry
+            .as_ref()
+            .and_then(|date| parse_date(date))
+            .ok_or_else(|| {
+                error::Error::MspError(format!("Missing date of the inclusion of {}", row.inn))
+            })?;
+
+        Ok(MspRecord {
+            category: category,
+            included: included,
+            excluded: row.dtdelete.as_ref().and_then(|date| parse_date(date)),
+            ogrn: row.ogrn.and_then(|ogrn| if ogrn.is_empty() { None } else { Some(ogrn) }),
+            name: row.name_ex,
+            inn: row.inn,
+        })
+    }
+}
+
+impl MemoryIndex {
+    pub fn new() -> MemoryIndex {
+        MemoryIndex::default()
+    }
+
+    /// The number of the records of all the dumps.
+    pub fn len(&self) -> usize {
+        self.dumps
+            .lock()
+            .unwrap()
+            .values()
+            .map(|records| records.len())
+            .sum()
+    }
+
+    /// Returns `true` if there are no records.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl MspIndex for MemoryIndex {
+    fn put(&self, date: NaiveDate, record: &MspRecord) -> Result<()> {
+        self.dumps
+            .lock()
+            .unwrap()
+            .entry(date)
+            .or_insert_with(HashMap::new)
+            .insert(record.inn.clone(), record.clone());
+        Ok(())
+    }
+
+    fn dates(&self) -> Result<Vec<NaiveDate>> {
+        Ok(self.dumps.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn history(&self, inn: &str) -> Result<Vec<(NaiveDate, MspRecord)>> {
+        Ok(self.dumps
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(date, records)| records.get(inn).map(|record| (*date, record.clone())))
+            .collect())
+    }
+}
+
+impl Default for MspClientBuilder {
+    fn default() -> MspClientBuilder {
+        MspClientBuilder {
+            config: ServiceConfig::new(MSP_URL),
+        }
+    }
+}
+
+service_client!(MspClient, MspClientBuilder);
+
+impl MspClientBuilder {
+    /// Create the client.
+    pub fn build(self) -> Result<MspClient> {
+        Ok(MspClient {
+            service: self.config.connect()?,
+        })
+    }
+}
+
+impl MspClient {
+    /// The record of the taxpayer in the register, `None` if the
+    /// taxpayer is not in it.
+    pub fn lookup(&self, inn: &str) -> Result<Option<MspRecord>> {
+        let rsp = self.search(inn);
+        if let Err(ref e) = rsp {
+            self.service.metrics.error(METHOD, e);
+        }
+
+        rsp
+    }
+
+    /// The record of the taxpayer if it was in the register on the date.
+    ///
+    /// The service returns only the current record: the date is checked
+    /// against the dates of the inclusion and of the exclusion, but the
+    /// category is the current one. Use an `MspIndex` loaded with the dumps
+    /// of the time for the category on a past date.
+    pub fn lookup_on(&self, inn: &str, date: NaiveDate) -> Result<Option<MspRecord>> {
+        Ok(self.lookup(inn)?
+            .and_then(|record| if record.is_included_on(date) {
+                Some(record)
+            } else {
+                None
+            }))
+    }
+
+    fn search(&self, inn: &str) -> Result<Option<MspRecord>> {
+        service::check_inn(inn, error::Error::MspError)?;
+
+        let form = [
+            ("mode", "quick"),
+            ("query", inn),
+            ("page", "1"),
+            ("pageSize", "10"),
+        ];
+        let reply: Reply = self.service.call_json(
+            METHOD,
+            "/search-proc.json",
+            Call::Form(&form),
+            |body| service::reported_error(body).map(error::Error::MspError),
+        )?;
+
+        match reply.data.into_iter().find(|row| row.inn == inn) {
+            Some(row) => Ok(Some(MspRecord::from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The record of the dump nearest to the date, or of the latest dump if
+/// the date is `None`, excluded by the first later dump without the taxpayer.
+fn record_on(
+    mut history: Vec<(NaiveDate, MspRecord)>,
+    dates: &[NaiveDate],
+    date: Option<NaiveDate>,
+) -> Option<MspRecord> {
+    if history.is_empty() {
+        return None;
+    }
+
+    let listed: Vec<NaiveDate> = history.iter().map(|&(dumped, _)| dumped).collect();
+    let position = match date {
+        Some(date) => listed
+            .iter()
+            .rposition(|dumped| *dumped <= date)
+            .unwrap_or(0),
+        None => listed.len() - 1,
+    };
+    let (dumped, mut record) = history.swap_remove(position);
+    if record.excluded.is_none() {
+        record.excluded = dates
+            .iter()
+            .cloned()
+            .find(|later| *later > dumped && !listed.contains(later));
+    }
+
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::MockMspServer;
+
+    fn record() -> MspRecord {
+        MspRecord {
+            inn: "6648185610".into(),
+            ogrn: Some("1069648001080".into()),
+            name: "ООО \"РОМАШКА\"".into(),
+            category: MspCategory::Small,
+            included: NaiveDate::from_ymd(2016, 8, 10),
+            excluded: Some(NaiveDate::from_ymd(2020, 7, 10)),
+        }
+    }
+
+    #[test]
+    fn looks_up_the_record() {
+        let server = MockMspServer::start().unwrap();
+        server.add_record(record());
+        let client = server.client().unwrap();
+
+        assert_eq!(client.lookup("6648185610").unwrap(), Some(record()));
+        assert!(client.lookup("7707083893").unwrap().is_none());
+
+        let date = NaiveDate::from_ymd(2018, 1, 1);
+        assert_eq!(client.lookup_on("6648185610", date).unwrap(), Some(record()));
+        let date = NaiveDate::from_ymd(2020, 7, 10);
+        assert!(client.lookup_on("6648185610", date).unwrap().is_none());
+    }
+
+    #[test]
+    fn memory_index_keeps_the_records_of_every_dump() {
+        let index = MemoryIndex::new();
+        let small = MspRecord {
+            excluded: None,
+            ..record()
+        };
+        let medium = MspRecord {
+            category: MspCategory::Medium,
+            ..small.clone()
+        };
+        let dropped = MspRecord {
+            inn: "500100732259".into(),
+            ..small.clone()
+        };
+        let first = NaiveDate::from_ymd(2019, 7, 10);
+        let second = NaiveDate::from_ymd(2020, 7, 10);
+
+        index.put_all(first, &[small.clone(), dropped.clone()]).unwrap();
+        index.put_all(second, &[medium.clone()]).unwrap();
+
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.dates().unwrap(), vec![first, second]);
+        assert_eq!(index.get("6648185610").unwrap(), Some(medium.clone()));
+        let on = |inn: &str, y, m, d| index.get_on(inn, NaiveDate::from_ymd(y, m, d)).unwrap();
+        assert_eq!(on("6648185610", 2019, 12, 31), Some(small));
+        assert_eq!(on("6648185610", 2020, 8, 1), Some(medium));
+        assert!(on("6648185610", 2016, 8, 9).is_none());
+
+        let excluded = index.get("500100732259").unwrap().unwrap().excluded;
+        assert_eq!(excluded, Some(second));
+        assert!(on("500100732259", 2018, 1, 1).is_some());
+        assert!(on("500100732259", 2020, 8, 1).is_none());
+    }
+}
@@ -0,0 +1,209 @@
+//! Index of the register stored in an SQLite database.
+
+use chrono::prelude::*;
+use rusqlite::{Connection, Row};
+
+use super::{MspCategory, MspIndex, MspRecord};
+use super::super::{error, Result};
+use sqlite::transaction;
+
+/// Index stored in an SQLite database file
+///
+/// Available with the `msp` and `sqlite` features. Keeps the loaded dumps
+/// between the runs, so every dump is loaded once per its publication.
+pub struct SqliteIndex {
+    conn: Connection,
+}
+
+sqlite_store!(SqliteIndex);
+
+impl SqliteIndex {
+    fn from_connection(conn: Connection) -> Result<SqliteIndex> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS msp_dumps (
+                date TEXT NOT NULL PRIMARY KEY
+            );
+            CREATE TABLE IF NOT EXISTS msp_records (
+                inn TEXT NOT NULL,
+                date TEXT NOT NULL,
+                ogrn TEXT,
+                name TEXT NOT NULL,
+                category INTEGER NOT NULL,
+                included TEXT NOT NULL,
+                excluded TEXT,
+                PRIMARY KEY (inn, date)
+            );",
+        )?;
+
+        Ok(SqliteIndex { conn: conn })
+    }
+
+    fn insert(&self, date: NaiveDate, records: &[MspRecord]) -> Result<()> {
+        let date = date.to_string();
+        self.conn.execute(
+            "INSERT OR IGNORE INTO msp_dumps (date) VALUES (?1)",
+            &[&date],
+        )?;
+
+        let mut stmt = self.conn.prepare(
+            "INSERT OR REPLACE INTO msp_records
+             (inn, date, ogrn, name, category, included, excluded)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for record in records {
+            stmt.execute(&[
+                &record.inn,
+                &date,
+                &record.ogrn,
+                &record.name,
+                &(record.category.code() as i32),
+                &record.included.to_string(),
+                &record.excluded.map(|date| date.to_string()),
+            ])?;
+        }
+
+        Ok(())
+    }
+}
+
+impl MspIndex for SqliteIndex {
+    fn put(&self, date: NaiveDate, record: &MspRecord) -> Result<()> {
+        self.put_all(date, &[record.clone()])
+    }
+
+    /// Stores the records in one transaction.
+    fn put_all(&self, date: NaiveDate, records: &[MspRecord]) -> Result<()> {
+        transaction(&self.conn, || self.insert(date, records))
+    }
+
+    fn dates(&self) -> Result<Vec<NaiveDate>> {
+        let mut stmt = self.conn.prepare("SELECT date FROM msp_dumps ORDER BY date")?;
+        let rows = stmt.query_map(&[], |row| row.get::<_, String>(0))?;
+
+        let mut dates = vec![];
+        for row in rows {
+            dates.push(row?.parse::<NaiveDate>()?);
+        }
+
+        Ok(dates)
+    }
+
+    fn history(&self, inn: &str) -> Result<Vec<(NaiveDate, MspRecord)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT date, ogrn, name, category, included, excluded FROM msp_records
+             WHERE inn = ?1 ORDER BY date",
+        )?;
+        let rows = stmt.query_map(&[&inn], read_row)?;
+
+        let mut records = vec![];
+        for row in rows {
+            let (date, ogrn, name, category, included, excluded) = row?;
+            let record = MspRecord {
+                inn: inn.into(),
+                ogrn: ogrn,
+                name: name,
+                category: MspCategory::from_code(category as u8).ok_or_else(|| {
+                    error::Error::MspError(format!("Unknown category {} of {}", category, inn))
+                })?,
+                included: included.parse::<NaiveDate>()?,
+                excluded: match excluded {
+                    Some(date) => Some(date.parse::<NaiveDate>()?),
+                    None => None,
+                },
+            };
+            records.push((date.parse::<NaiveDate>()?, record));
+        }
+
+        Ok(records)
+    }
+}
+
+fn read_row(row: &Row) -> (String, Option<String>, String, i32, String, Option<String>) {
+    (
+        row.get(0),
+        row.get(1),
+        row.get(2),
+        row.get(3),
+        row.get(4),
+        row.get(5),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(inn: &str, category: MspCategory) -> MspRecord {
+        MspRecord {
+            inn: inn.into(),
+            ogrn: Some("1069648001080".into()),
+            name: "ООО \"РОМАШКА\"".into(),
+            category: category,
+            included: NaiveDate::from_ymd(2016, 8, 10),
+            excluded: None,
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let index = SqliteIndex::open_in_memory().unwrap();
+        let first = NaiveDate::from_ymd(2019, 7, 10);
+        let second = NaiveDate::from_ymd(2020, 7, 10);
+        assert_eq!(index.get("6648185610").unwrap(), None);
+
+        index
+            .put_all(
+                first,
+                &[
+                    record("6648185610", MspCategory::Small),
+                    record("500100732259", MspCategory::Micro),
+                ],
+            )
+            .unwrap();
+        index
+            .put(second, &record("6648185610", MspCategory::Medium))
+            .unwrap();
+
+        assert_eq!(index.dates().unwrap(), vec![first, second]);
+        assert_eq!(
+            index.history("6648185610").unwrap(),
+            vec![
+                (first, record("6648185610", MspCategory::Small)),
+                (second, record("6648185610", MspCategory::Medium)),
+            ]
+        );
+        let date = NaiveDate::from_ymd(2020, 1, 1);
+        assert_eq!(
+            index.get_on("6648185610", date).unwrap(),
+            Some(record("6648185610", MspCategory::Small))
+        );
+        let dropped = index.get("500100732259").unwrap().unwrap();
+        assert_eq!(dropped.excluded, Some(second));
+    }
+
+    #[test]
+    fn failed_put_all_is_rolled_back() {
+        let index = SqliteIndex::open_in_memory().unwrap();
+        index
+            .conn
+            .execute_batch(
+                "CREATE TRIGGER reject BEFORE INSERT ON msp_records
+                 WHEN NEW.inn = '500100732259'
+                 BEGIN SELECT RAISE(ABORT, 'rejected'); END;",
+            )
+            .unwrap();
+
+        let date = NaiveDate::from_ymd(2020, 7, 10);
+        let result = index.put_all(
+            date,
+            &[
+                record("6648185610", MspCategory::Small),
+                record("500100732259", MspCategory::Micro),
+            ],
+        );
+
+        assert!(result.is_err());
+        assert!(index.dates().unwrap().is_empty());
+        assert_eq!(index.get("6648185610").unwrap(), None);
+    }
+}
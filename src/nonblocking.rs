@@ -0,0 +1,310 @@
+//! Asynchronous counterparts of the checks, built on `futures` and `tokio-core`.
+//!
+//! Available with the `async` feature.
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{future, Future};
+use futures::future::{Either, Loop};
+use hyper;
+use hyper::client::HttpConnector;
+use tokio_core::reactor::{CoreId, Handle, Timeout};
+
+use super::{client, error, http, rpser, FromElement, NdsResponse, NpchkClient, NpchkClientBuilder,
+            Partner, Result, RetryPolicy, MAX_RECORDS};
+use metrics::Metrics;
+use retry::{Attempt, Next};
+
+/// Future resolving to the service response
+pub type ResponseFuture = Box<Future<Item = NdsResponse<'static>, Error = error::Error>>;
+
+/// Client of the service running on the `tokio-core` reactor
+///
+/// Holds one HTTP client reused by every call and follows the timeout
+/// and the retry policy of the builder. Requests are sent directly,
+/// a proxy is not supported.
+#[derive(Clone)]
+pub struct AsyncNpchkClient {
+    inner: Rc<Inner>,
+}
+
+/// Future of the attempt of the call, resolving to whether to repeat it
+type AttemptFuture = Box<Future<Item = Loop<rpser::Response, Vec<Attempt>>, Error = error::Error>>;
+
+struct Inner {
+    url: String,
+    request_uri: String,
+    namespace: String,
+    user_agent: String,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    metrics: Arc<Metrics>,
+    http: hyper::Client<HttpConnector>,
+    handle: Handle,
+}
+
+thread_local! {
+    /// Clients of the public service created by `check_fns`, one per reactor
+    static CLIENTS: RefCell<Vec<(CoreId, AsyncNpchkClient)>> = RefCell::new(vec![]);
+}
+
+/// Checks of contractors through the service
+/// [http://npchk.nalog.ru/](http://npchk.nalog.ru/)
+///
+/// The client of the public service is created on the first call
+/// and reused by the next calls on the same reactor.
+pub fn check_fns(handle: &Handle, partners: Vec<Partner>) -> ResponseFuture {
+    let client = CLIENTS.with(|clients| -> Result<AsyncNpchkClient> {
+        let mut clients = clients.borrow_mut();
+        if let Some(&(_, ref client)) = clients.iter().find(|&&(id, _)| id == handle.id()) {
+            return Ok(client.clone());
+        }
+
+        let client = NpchkClient::builder().build_async(handle)?;
+        clients.push((handle.id(), client.clone()));
+        Ok(client)
+    });
+
+    match client {
+        Ok(client) => client.check_fns(partners),
+        Err(e) => Box::new(future::err(e)),
+    }
+}
+
+/// Checks the 1st of the contractor using the service
+/// [http://npchk.nalog.ru/](http://npchk.nalog.ru/)
+pub fn check_fns_partner(handle: &Handle, p: Partner) -> ResponseFuture {
+    check_fns(handle, vec![p])
+}
+
+impl NpchkClientBuilder {
+    /// Create the client running on the reactor.
+    ///
+    /// Fails if a proxy is set, the asynchronous client does not support it.
+    pub fn build_async(self, handle: &Handle) -> Result<AsyncNpchkClient> {
+        if let Some(ref proxy) = self.proxy {
+            return Err(error::Error::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The asynchronous client does not support the proxy {}", proxy),
+            )));
+        }
+
+        Ok(AsyncNpchkClient {
+            inner: Rc::new(Inner {
+                url: self.url,
+                request_uri: self.request_uri,
+                namespace: self.namespace,
+                user_agent: self.user_agent,
+                timeout: self.timeout,
+                retry: self.retry,
+                metrics: self.metrics,
+                http: hyper::Client::new(handle),
+                handle: handle.clone(),
+            }),
+        })
+    }
+}
+
+impl AsyncNpchkClient {
+    /// Checks of contractors through the service without blocking the thread
+    pub fn check_fns(&self, partners: Vec<Partner>) -> ResponseFuture {
+        if partners.len() > MAX_RECORDS {
+            return Box::new(future::err(error::Error::TooManyRecords));
+        }
+
+        let inner = &self.inner;
+        let method = client::nds_request2(&inner.namespace, &partners);
+        let name = method.name.clone();
+        inner.metrics.batch(&name, method.args.len());
+        let envelope = method.as_xml(&inner.request_uri, &inner.namespace);
+
+        let metrics = inner.metrics.clone();
+        Box::new(
+            self.call(method.name, envelope)
+                .and_then(|response| NdsResponse::from_element(response.body))
+                .then(move |rsp| {
+                    client::record(&*metrics, &name, &rsp);
+                    rsp
+                }),
+        )
+    }
+
+    /// Checks the 1st of the contractor using the service without blocking the thread
+    pub fn check_fns_partner(&self, p: Partner) -> ResponseFuture {
+        self.check_fns(vec![p])
+    }
+
+    /// Calls a remote procedure through a Protocol `SOAP`,
+    /// repeating the call according to the retry policy
+    fn call(
+        &self,
+        action: String,
+        envelope: String,
+    ) -> Box<Future<Item = rpser::Response, Error = error::Error>> {
+        let inner = self.inner.clone();
+        let start = Instant::now();
+
+        Box::new(future::loop_fn(vec![], move |mut attempts: Vec<Attempt>| {
+            let inner = inner.clone();
+            let attempt = inner.call_once(&action, &envelope);
+            attempt.then(move |rsp| -> AttemptFuture {
+                let e = match rsp {
+                    Ok(response) => return Box::new(future::ok(Loop::Break(response))),
+                    Err(e) => e,
+                };
+
+                let next = inner.retry.after_failure(start, &mut attempts, e);
+                match next {
+                    Next::Fail(e) => Box::new(future::err(e)),
+                    Next::Retry(delay) => match Timeout::new(delay, &inner.handle) {
+                        Ok(timer) => {
+                            Box::new(timer.from_err().map(move |_| Loop::Continue(attempts)))
+                        }
+                        Err(e) => Box::new(future::err(e.into())),
+                    },
+                }
+            })
+        }))
+    }
+}
+
+impl Inner {
+    fn call_once(
+        &self,
+        action: &str,
+        envelope: &str,
+    ) -> Box<Future<Item = rpser::Response, Error = error::Error>> {
+        let metrics = self.metrics.clone();
+        let name = action.to_string();
+        let start = Instant::now();
+
+        let mut http_response =
+            http::soap_action_async(&self.http, &self.url, &self.user_agent, action, envelope);
+        if let Some(timeout) = self.timeout {
+            let timer = match Timeout::new(timeout, &self.handle) {
+                Ok(timer) => timer,
+                Err(e) => return Box::new(future::err(e.into())),
+            };
+            http_response = Box::new(http_response.select2(timer).then(|rsp| match rsp {
+                Ok(Either::A((response, _))) => Ok(response),
+                Ok(Either::B(_)) => Err(error::Error::IoError(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "The request timed out",
+                ))),
+                Err(Either::A((e, _))) => Err(e),
+                Err(Either::B((e, _))) => Err(e.into()),
+            }));
+        }
+
+        Box::new(
+            http_response
+                .then(move |http_response| {
+                    let size = http_response.as_ref().map_or(0, |response| response.body.len());
+                    metrics.http_request(&name, start.elapsed(), size);
+                    http_response
+                })
+                .and_then(client::soap_response),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use chrono::prelude::*;
+    use tokio_core::reactor::Core;
+
+    use super::*;
+    use mock::{Behavior, MockNpchkServer};
+    use {PartnerState, RetryPolicy};
+
+    fn partners() -> Vec<Partner<'static>> {
+        vec![
+            Partner::new("4205036750", "420501001", Utc::now()),
+            Partner::new("6648185610", "662301001", Utc::now()),
+        ]
+    }
+
+    #[test]
+    fn checks_through_one_client() {
+        let server = MockNpchkServer::start().unwrap();
+        server.set_state("6648185610", "662301001", 3);
+        let mut core = Core::new().unwrap();
+        let client = server.client_builder().build_async(&core.handle()).unwrap();
+
+        let rsp = core.run(client.check_fns(partners())).unwrap();
+        assert_eq!(rsp.partners[0].state, Some(PartnerState::Active));
+        assert_eq!(rsp.partners[1].state, Some(PartnerState::KppMismatch));
+
+        core.run(client.check_fns_partner(partners().remove(0))).unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn server_errors_are_retried() {
+        let server = MockNpchkServer::start().unwrap();
+        server.respond_once_with(Behavior::Status(503));
+        let retry = RetryPolicy {
+            initial_backoff: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        };
+        let mut core = Core::new().unwrap();
+        let client = server
+            .client_builder()
+            .retry(retry)
+            .build_async(&core.handle())
+            .unwrap();
+
+        let rsp = core.run(client.check_fns(partners())).unwrap();
+
+        assert_eq!(rsp.partners.len(), 2);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn slow_response_times_out() {
+        let server = MockNpchkServer::start().unwrap();
+        server.respond_with(Behavior::Slow(Duration::from_secs(2)));
+        let mut core = Core::new().unwrap();
+        let client = server
+            .client_builder()
+            .timeout(Duration::from_millis(200))
+            .build_async(&core.handle())
+            .unwrap();
+
+        let start = Instant::now();
+        assert!(core.run(client.check_fns(partners())).is_err());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn proxy_is_rejected() {
+        let core = Core::new().unwrap();
+
+        let client = NpchkClient::builder()
+            .proxy("http://127.0.0.1:3128")
+            .build_async(&core.handle());
+
+        assert!(client.is_err());
+    }
+
+    #[test]
+    fn free_function_reuses_the_client() {
+        let core = Core::new().unwrap();
+        let handle = core.handle();
+        let count = || CLIENTS.with(|clients| clients.borrow().len());
+
+        let _ = check_fns(&handle, vec![]);
+        let _ = check_fns_partner(&handle, partners().remove(0));
+        assert_eq!(count(), 1);
+
+        let other = Core::new().unwrap();
+        let _ = check_fns(&other.handle(), vec![]);
+        assert_eq!(count(), 2);
+    }
+}
@@ -0,0 +1,328 @@
+//! Checks of the professional income tax (NPD) status of the self-employed
+//! through the service [https://npd.nalog.ru/check-status/](https://npd.nalog.ru/check-status/)
+//!
+//! Available with the `npd` feature. The service answers whether the taxpayer
+//! with the INN was registered as self-employed on the date. It allows
+//! only two requests per minute from one IP address, so every call of
+//! `NpdClient` waits for its turn; `NpdClient::check_npd_batch` checks
+//! any number of taxpayers one by one within that limit.
+
+use std::time::Duration;
+
+use chrono::prelude::*;
+use serde_json;
+
+use super::{error, validate, Result};
+use service::{Call, Service, ServiceConfig};
+use throttle::Throttle;
+
+/// The default connection point of the service
+pub const NPD_API_URL: &'static str = "https://statusnpd.nalog.ru/api/v1/tracker/taxpayer_status";
+/// The number of requests the service accepts per `NPD_RATE_PERIOD` from one IP address
+pub const NPD_RATE_LIMIT: u32 = 2;
+/// The period of the rate limit, in seconds
+pub const NPD_RATE_PERIOD: u64 = 60;
+
+/// Code of the error reported when the rate limit is exceeded
+pub const LIMITED_ERROR: &'static str = "taxpayer.status.service.limited.error";
+/// Code of the error reported when the service is temporarily unavailable
+pub const UNAVAILABLE_ERROR: &'static str = "taxpayer.status.service.unavailable.error";
+/// Code of the error reported about the invalid request data
+pub const VALIDATION_ERROR: &'static str = "validation.failed";
+
+const METHOD: &'static str = "taxpayer_status";
+
+/// Request of the status of one taxpayer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NpdRequest {
+    /// INN of the individual, 12 digits
+    pub inn: String,
+    /// Date the status is checked on
+    pub date: NaiveDate,
+}
+
+/// Status of the taxpayer reported by the service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NpdStatus {
+    /// `true` if the taxpayer pays the professional income tax on the date
+    pub status: bool,
+    /// Explanation of the status by the service
+    pub message: String,
+}
+
+/// Answer for one taxpayer of the batch
+#[derive(Debug)]
+pub struct NpdCheck {
+    pub request: NpdRequest,
+    pub result: Result<NpdStatus>,
+}
+
+/// Body of the request, as sent to the service
+#[derive(Debug, Serialize)]
+struct TaxpayerStatusRequest<'a> {
+    inn: &'a str,
+    #[serde(rename = "requestDate")]
+    request_date: String,
+}
+
+/// Body of the error answer of the service
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    code: String,
+    message: String,
+}
+
+/// Client of the NPD status service
+#[derive(Debug)]
+pub struct NpdClient {
+    service: Service,
+}
+
+/// Builder of the `NpdClient`
+#[derive(Debug, Clone)]
+pub struct NpdClientBuilder {
+    config: ServiceConfig,
+    rate_limit: Option<(u32, Duration)>,
+}
+
+impl NpdRequest {
+    pub fn new<S>(inn: S, date: NaiveDate) -> NpdRequest
+    where
+        S: Into<String>,
+    {
+        NpdRequest {
+            inn: inn.into(),
+            date: date,
+        }
+    }
+
+    /// Checks the INN locally, the service accepts only valid INN
+    /// of individuals.
+    fn validate(&self) -> Result<()> {
+        let state = if self.inn.len() == 12 {
+            validate::validate_inn(&self.inn)
+        } else {
+            Some(::PartnerState::InvalidInnLength)
+        };
+
+        match state {
+            Some(state) => Err(error::Error::NpdError {
+                code: VALIDATION_ERROR.into(),
+                message: state.description().into(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for NpdClientBuilder {
+    fn default() -> NpdClientBuilder {
+        NpdClientBuilder {
+            config: ServiceConfig::new(NPD_API_URL),
+            rate_limit: Some((NPD_RATE_LIMIT, Duration::from_secs(NPD_RATE_PERIOD))),
+        }
+    }
+}
+
+service_client!(NpdClient, NpdClientBuilder);
+
+impl NpdClientBuilder {
+    /// Send at most `requests` requests in any period of `per`.
+    ///
+    /// By default the limit of the public service is used,
+    /// `NPD_RATE_LIMIT` requests per `NPD_RATE_PERIOD` seconds.
+    pub fn rate_limit(mut self, requests: u32, per: Duration) -> Self {
+        self.rate_limit = Some((requests, per));
+        self
+    }
+
+    /// Do not limit the rate of the requests.
+    pub fn no_rate_limit(mut self) -> Self {
+        self.rate_limit = None;
+        self
+    }
+
+    /// Create the client.
+    pub fn build(self) -> Result<NpdClient> {
+        let mut service = self.config.connect()?;
+        if let Some((requests, per)) = self.rate_limit {
+            service.throttle = Throttle::new(requests, per);
+        }
+
+        Ok(NpdClient { service: service })
+    }
+}
+
+impl NpdClient {
+    /// Checks the status of the taxpayer on the date.
+    ///
+    /// An invalid INN is `Error::NpdError` with the code `VALIDATION_ERROR`.
+    pub fn check_npd(&self, request: &NpdRequest) -> Result<NpdStatus> {
+        let rsp = request.validate().and_then(|_| {
+            let body = serde_json::to_string(&TaxpayerStatusRequest {
+                inn: &request.inn,
+                request_date: request.date.format("%Y-%m-%d").to_string(),
+            })?;
+
+            self.service
+                .call_json(METHOD, "", Call::Json(&body), |body| {
+                    serde_json::from_str::<ErrorBody>(body)
+                        .ok()
+                        .map(|e| error::Error::NpdError {
+                            code: e.code,
+                            message: e.message,
+                        })
+                })
+        });
+        if let Err(ref e) = rsp {
+            self.service.metrics.error(METHOD, e);
+        }
+
+        rsp
+    }
+
+    /// Checks the status of the taxpayer today.
+    pub fn check_npd_today(&self, inn: &str) -> Result<NpdStatus> {
+        self.check_npd(&NpdRequest::new(inn, Local::today().naive_local()))
+    }
+
+    /// Checks any number of taxpayers one by one, waiting
+    /// for the rate limit between the requests.
+    ///
+    /// A failed check does not abort the run, its error is returned
+    /// in place of the status.
+    pub fn check_npd_batch(&self, requests: Vec<NpdRequest>) -> Vec<NpdCheck> {
+        self.service.metrics.batch(METHOD, requests.len());
+
+        requests
+            .into_iter()
+            .map(|request| {
+                let result = self.check_npd(&request);
+                NpdCheck {
+                    request: request,
+                    result: result,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use error::Error;
+    use {NpchkClientBuilder, RetryPolicy};
+    use mock::{MockNpdServer, NpdBehavior};
+
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd(2019, 5, 1)
+    }
+
+    #[test]
+    fn checks_the_status() {
+        let server = MockNpdServer::start().unwrap();
+        server.set_status("027714145906", true);
+        let client = server.client().unwrap();
+
+        let status = client
+            .check_npd(&NpdRequest::new("027714145906", date()))
+            .unwrap();
+        assert!(status.status);
+        let status = client
+            .check_npd(&NpdRequest::new("500100732259", date()))
+            .unwrap();
+        assert!(!status.status);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0].body,
+            r#"{"inn":"027714145906","requestDate":"2019-05-01"}"#
+        );
+    }
+
+    #[test]
+    fn reports_service_errors() {
+        let server = MockNpdServer::start().unwrap();
+        server.respond_with(NpdBehavior::Error {
+            status: 422,
+            code: "request.date.incorrect".into(),
+            message: "Incorrect date".into(),
+        });
+
+        match server
+            .client()
+            .unwrap()
+            .check_npd(&NpdRequest::new("027714145906", date()))
+        {
+            Err(Error::NpdError { ref code, .. }) => assert_eq!(code, "request.date.incorrect"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn batch_respects_the_rate_limit() {
+        let server = MockNpdServer::start().unwrap();
+        server.set_rate_limit(2, Duration::from_millis(250));
+        server.set_status("027714145906", true);
+        let client = server
+            .client_builder()
+            .rate_limit(2, Duration::from_millis(300))
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        let checks = client.check_npd_batch(vec![
+            NpdRequest::new("027714145906", date()),
+            NpdRequest::new("500100732259", date()),
+            NpdRequest::new("027714145906", date()),
+        ]);
+
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert_eq!(checks.len(), 3);
+        let statuses: Vec<bool> = checks
+            .into_iter()
+            .map(|check| check.result.unwrap().status)
+            .collect();
+        assert_eq!(statuses, vec![true, false, true]);
+    }
+
+    #[test]
+    fn shares_the_npchk_settings() {
+        let builder = NpchkClientBuilder::new()
+            .user_agent("erp/1.0")
+            .timeout(Duration::from_secs(5));
+        let server = MockNpdServer::start().unwrap();
+        let client = NpdClientBuilder::from(&builder)
+            .url(server.url())
+            .no_rate_limit()
+            .build()
+            .unwrap();
+
+        client
+            .check_npd(&NpdRequest::new("027714145906", date()))
+            .unwrap();
+        assert_eq!(server.requests()[0].header("User-Agent"), Some("erp/1.0"));
+    }
+
+    #[test]
+    fn rate_limit_errors_are_retried() {
+        let server = MockNpdServer::start().unwrap();
+        server.respond_once_with(NpdBehavior::Limited);
+        let retry = RetryPolicy {
+            initial_backoff: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        };
+        let client = server.client_builder().retry(retry).build().unwrap();
+
+        assert!(
+            !client
+                .check_npd(&NpdRequest::new("027714145906", date()))
+                .unwrap()
+                .status
+        );
+        assert_eq!(server.requests().len(), 2);
+    }
+}
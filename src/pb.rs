@@ -0,0 +1,460 @@
+//! Risk signals of the service "Transparent business"
+//! [https://pb.nalog.ru/](https://pb.nalog.ru/)
+//!
+//! Available with the `pb` feature. For a company or an entrepreneur found
+//! by INN the service reports the signals the tax authorities consider risky:
+//! a mass-registration address, a mass director or founder, a disqualified
+//! director, unreliable records in the register and tax arrears.
+//!
+//! `PbClient::check_fns_with_risks` collects the state of the VAT register
+//! and the risk signals of the partners in one run.
+
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{error, CheckOptions, NpchkClient, Partner, Result, MAX_RECORDS};
+use service::{self, Call, Service, ServiceConfig};
+
+/// The default connection point of the service
+pub const PB_URL: &'static str = "https://pb.nalog.ru";
+/// Tax arrears over this amount, in roubles, raise `RiskFlags::tax_arrears`
+pub const ARREARS_THRESHOLD: f64 = 1000.0;
+
+const SEARCH: &'static str = "pb_search";
+const COMPANY: &'static str = "pb_company";
+
+/// Risk signals of the taxpayer
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct RiskFlags {
+    pub inn: String,
+    pub name: Option<String>,
+    /// The address is used by many legal entities
+    pub mass_address: bool,
+    /// The director heads many legal entities
+    pub mass_director: bool,
+    /// The founder participates in many legal entities
+    pub mass_founder: bool,
+    /// The director is in the register of disqualified persons
+    pub disqualified: bool,
+    /// Some records of the register are marked unreliable
+    pub unreliable: bool,
+    /// The taxpayer has tax arrears over `ARREARS_THRESHOLD` roubles
+    pub tax_arrears: bool,
+    /// Amount of the arrears, if the service reports it
+    pub arrears_amount: Option<f64>,
+}
+
+/// Partner checked by both `npchk` and "Transparent business"
+#[derive(Debug)]
+pub struct PartnerReport<'a> {
+    /// The partner with the state of the VAT register
+    pub partner: Partner<'a>,
+    /// The risk signals, `None` if the service does not know the INN
+    /// or the check failed
+    pub risks: Option<RiskFlags>,
+    /// The error of the risk check
+    pub risk_error: Option<error::Error>,
+}
+
+/// Any answer of the service, only the fields of the step are set
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Reply {
+    ul: Option<Found>,
+    ip: Option<Found>,
+    id: Option<String>,
+    token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Found {
+    data: Vec<FoundItem>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FoundItem {
+    token: String,
+    inn: String,
+}
+
+/// Card of the company, as sent by the service
+///
+/// The signals are sent as lists of the related records, counts or
+/// flags depending on the kind of the taxpayer, so any non-empty value
+/// is treated as a raised signal. The arrears are sent as the amount,
+/// a number or a string, or as a flag.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Card {
+    inn: Option<String>,
+    namec: Option<String>,
+    masaddress: Value,
+    masruleader: Value,
+    masfounder: Value,
+    rdl: Value,
+    invalid: Value,
+    arrears: Value,
+}
+
+/// Client of the service "Transparent business"
+#[derive(Debug)]
+pub struct PbClient {
+    service: Service,
+    poll_interval: Duration,
+    poll_attempts: u32,
+}
+
+/// Builder of the `PbClient`
+#[derive(Debug, Clone)]
+pub struct PbClientBuilder {
+    config: ServiceConfig,
+    poll_interval: Duration,
+    poll_attempts: u32,
+}
+
+impl RiskFlags {
+    /// Returns `true` if any signal is raised.
+    pub fn has_risks(&self) -> bool {
+        self.mass_address || self.mass_director || self.mass_founder || self.disqualified
+            || self.unreliable || self.tax_arrears
+    }
+
+    fn from_card(inn: &str, card: Card) -> RiskFlags {
+        let arrears_amount = amount(&card.arrears);
+
+        RiskFlags {
+            inn: card.inn.unwrap_or_else(|| inn.to_string()),
+            name: card.namec,
+            mass_address: flag(&card.masaddress),
+            mass_director: flag(&card.masruleader),
+            mass_founder: flag(&card.masfounder),
+            disqualified: flag(&card.rdl),
+            unreliable: flag(&card.invalid),
+            tax_arrears: match arrears_amount {
+                Some(amount) => amount > ARREARS_THRESHOLD,
+                None => flag(&card.arrears),
+            },
+            arrears_amount: arrears_amount,
+        }
+    }
+}
+
+impl Default for PbClientBuilder {
+    fn default() -> PbClientBuilder {
+        PbClientBuilder {
+            config: ServiceConfig::new(PB_URL),
+            poll_interval: Duration::from_secs(1),
+            poll_attempts: 30,
+        }
+    }
+}
+
+service_client!(PbClient, PbClientBuilder);
+
+impl PbClientBuilder {
+    /// Poll the card that is not ready at most `attempts` times,
+    /// every `interval`; 30 times every second by default.
+    pub fn poll(mut self, interval: Duration, attempts: u32) -> Self {
+        self.poll_interval = interval;
+        self.poll_attempts = attempts;
+        self
+    }
+
+    /// Create the client.
+    pub fn build(self) -> Result<PbClient> {
+        Ok(PbClient {
+            service: self.config.connect()?,
+            poll_interval: self.poll_interval,
+            poll_attempts: self.poll_attempts,
+        })
+    }
+}
+
+impl PbClient {
+    /// The risk signals of the taxpayer, `None` if the service
+    /// does not know the INN.
+    pub fn risk_flags(&self, inn: &str) -> Result<Option<RiskFlags>> {
+        let rsp = self.find(inn);
+        if let Err(ref e) = rsp {
+            self.service.metrics.error(COMPANY, e);
+        }
+
+        rsp
+    }
+
+    /// Checks the partners through `npchk` and collects the risk
+    /// signals of every partner, whatever its state.
+    ///
+    /// Partners are sent to `npchk` in requests of at most `MAX_RECORDS`
+    /// partners. Fails only if the check of `npchk` fails; the failed risk
+    /// checks are reported in `PartnerReport::risk_error`.
+    pub fn check_fns_with_risks<'a>(
+        &self,
+        client: &NpchkClient,
+        partners: Vec<Partner<'a>>,
+        options: &CheckOptions,
+    ) -> Result<Vec<PartnerReport<'a>>> {
+        let mut checked = vec![];
+        let mut partners = partners.into_iter().peekable();
+        while partners.peek().is_some() {
+            let chunk: Vec<Partner<'a>> = partners.by_ref().take(MAX_RECORDS).collect();
+            checked.extend(client.check_fns_with_options(chunk, options)?.partners);
+        }
+
+        let mut known: HashMap<String, Option<RiskFlags>> = HashMap::new();
+        let reports = checked
+            .into_iter()
+            .map(|partner| {
+                if let Some(risks) = known.get(&*partner.inn) {
+                    return PartnerReport {
+                        risks: risks.clone(),
+                        partner: partner,
+                        risk_error: None,
+                    };
+                }
+
+                match self.risk_flags(&partner.inn) {
+                    Ok(risks) => {
+                        known.insert(partner.inn.to_string(), risks.clone());
+                        PartnerReport {
+                            partner: partner,
+                            risks: risks,
+                            risk_error: None,
+                        }
+                    }
+                    Err(e) => PartnerReport {
+                        partner: partner,
+                        risks: None,
+                        risk_error: Some(e),
+                    },
+                }
+            })
+            .collect();
+
+        Ok(reports)
+    }
+
+    fn find(&self, inn: &str) -> Result<Option<RiskFlags>> {
+        service::check_inn(inn, error::Error::PbError)?;
+
+        let reply: Reply = self.call(
+            SEARCH,
+            "/search-proc.json",
+            &[
+                ("mode", "search-all"),
+                ("queryAll", inn),
+                ("page", "1"),
+                ("pageSize", "10"),
+            ],
+        )?;
+        let found = reply
+            .ul
+            .into_iter()
+            .chain(reply.ip)
+            .flat_map(|found| found.data)
+            .find(|item| item.inn == inn);
+        let token = match found {
+            Some(item) => item.token,
+            None => return Ok(None),
+        };
+
+        let reply: Reply = self.call(
+            COMPANY,
+            "/company-proc.json",
+            &[("token", token.as_str()), ("method", "get-request")],
+        )?;
+        let id = reply
+            .id
+            .ok_or_else(|| error::Error::PbError("The service returned no request id".into()))?;
+        let token = reply.token.unwrap_or(token);
+
+        for attempt in 0..self.poll_attempts.max(1) {
+            if attempt > 0 {
+                thread::sleep(self.poll_interval);
+            }
+
+            let card: Card = self.call(
+                COMPANY,
+                "/company-proc.json",
+                &[
+                    ("token", token.as_str()),
+                    ("id", id.as_str()),
+                    ("method", "get-response"),
+                ],
+            )?;
+            if card.inn.is_some() {
+                return Ok(Some(RiskFlags::from_card(inn, card)));
+            }
+            debug!("The card of {} is not ready", inn);
+        }
+
+        Err(error::Error::PbError(format!(
+            "The card is not ready after {} attempts",
+            self.poll_attempts
+        )))
+    }
+
+    fn call<T>(&self, method: &str, path: &str, form: &[(&str, &str)]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.service.call_json(method, path, Call::Form(form), |body| {
+            service::reported_error(body).map(error::Error::PbError)
+        })
+    }
+}
+
+/// Returns `true` for a raised signal: `true`, a non-zero number,
+/// a non-empty string, list or object.
+fn flag(value: &Value) -> bool {
+    match *value {
+        Value::Null => false,
+        Value::Bool(b) => b,
+        Value::Number(ref n) => n.as_f64().map_or(false, |n| n != 0.0),
+        Value::String(ref s) => !s.is_empty(),
+        Value::Array(ref a) => !a.is_empty(),
+        Value::Object(ref o) => !o.is_empty(),
+    }
+}
+
+/// The amount sent as a number or as a string, with any spaces
+/// between the digits and a decimal comma or point.
+fn amount(value: &Value) -> Option<f64> {
+    match *value {
+        Value::Number(ref n) => n.as_f64(),
+        Value::String(ref s) => s.chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| if c == ',' { '.' } else { c })
+            .collect::<String>()
+            .parse()
+            .ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::prelude::*;
+    use serde_json;
+
+    use super::*;
+    use mock::{MockNpchkServer, MockPbServer};
+
+    fn risky() -> RiskFlags {
+        RiskFlags {
+            inn: "6648185610".into(),
+            name: Some("ООО \"РОМАШКА\"".into()),
+            mass_address: true,
+            disqualified: true,
+            tax_arrears: true,
+            arrears_amount: Some(15000.5),
+            ..RiskFlags::default()
+        }
+    }
+
+    #[test]
+    fn returns_the_flags() {
+        let server = MockPbServer::start().unwrap();
+        server.set_flags(risky());
+        server.set_pending(2);
+        let client = server.client().unwrap();
+
+        let flags = client.risk_flags("6648185610").unwrap().unwrap();
+        assert_eq!(flags, risky());
+        assert!(flags.has_risks());
+
+        assert!(client.risk_flags("7707083893").unwrap().is_none());
+    }
+
+    #[test]
+    fn arrears_over_the_threshold_are_a_risk() {
+        let flags = |arrears: Value| {
+            let card = json!({"inn": "6648185610", "arrears": arrears});
+            RiskFlags::from_card("6648185610", serde_json::from_value(card).unwrap())
+        };
+
+        assert!(!flags(json!(1000)).tax_arrears);
+        assert!(flags(json!(1000.01)).tax_arrears);
+        assert!(!flags(json!("500")).tax_arrears);
+        let parsed = flags(json!("15 000,50"));
+        assert_eq!(parsed.arrears_amount, Some(15000.5));
+        assert!(parsed.tax_arrears);
+        let flagged = flags(json!(true));
+        assert_eq!(flagged.arrears_amount, None);
+        assert!(flagged.tax_arrears);
+        assert!(!flags(Value::Null).tax_arrears);
+    }
+
+    #[test]
+    fn gives_up_polling() {
+        let server = MockPbServer::start().unwrap();
+        server.set_flags(risky());
+        server.set_pending(10);
+        let client = server
+            .client_builder()
+            .poll(Duration::from_millis(1), 3)
+            .build()
+            .unwrap();
+
+        assert!(client.risk_flags("6648185610").is_err());
+    }
+
+    #[test]
+    fn collects_the_state_and_the_risks() {
+        let npchk = MockNpchkServer::start().unwrap();
+        npchk.set_inn_state("7707083893", 4);
+        let pb = MockPbServer::start().unwrap();
+        pb.set_flags(risky());
+
+        let partners = vec![
+            Partner::new("6648185610", "662301001", Utc::now()),
+            Partner::new("7707083893", "773601001", Utc::now()),
+            Partner::new("6648185610", "662301001", Utc::now()),
+        ];
+        let reports = pb.client()
+            .unwrap()
+            .check_fns_with_risks(&npchk.client().unwrap(), partners, &CheckOptions::default())
+            .unwrap();
+
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].risks, Some(risky()));
+        assert!(reports[1].risks.is_none());
+        assert!(reports[1].risk_error.is_none());
+        assert_eq!(reports[2].risks, Some(risky()));
+        // The partner missing from the VAT register is searched too,
+        // the card of the repeated INN is requested once
+        let count = |step: &str| {
+            pb.requests()
+                .iter()
+                .filter(|request| request.body.contains(step))
+                .count()
+        };
+        assert_eq!(count("search-all"), 2);
+        assert_eq!(count("get-response"), 1);
+    }
+
+    #[test]
+    fn risks_of_more_than_max_records_are_checked_in_chunks() {
+        let npchk = MockNpchkServer::start().unwrap();
+        let pb = MockPbServer::start().unwrap();
+        pb.set_flags(risky());
+        let partners: Vec<Partner> = (0..MAX_RECORDS + 1)
+            .map(|_| Partner::new("6648185610", "662301001", Utc::now()))
+            .collect();
+
+        let reports = pb.client()
+            .unwrap()
+            .check_fns_with_risks(&npchk.client().unwrap(), partners, &CheckOptions::default())
+            .unwrap();
+
+        assert_eq!(reports.len(), MAX_RECORDS + 1);
+        assert_eq!(reports[MAX_RECORDS].risks, Some(risky()));
+        assert_eq!(npchk.requests().len(), 2);
+    }
+}
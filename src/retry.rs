@@ -0,0 +1,335 @@
+//! Retry of the calls failed with transient errors.
+
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand;
+
+use super::{error, Result};
+
+/// Policy of repeating the calls failed with transient errors
+///
+/// Only errors for which `Error::is_transient` returns `true` are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the second attempt
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between attempts
+    pub max_backoff: Duration,
+    /// Factor the delay is multiplied by after each attempt
+    pub multiplier: f64,
+    /// Fraction of the delay randomly added or subtracted, from 0.0 to 1.0
+    pub jitter: f64,
+    /// Total time after which no new attempt is started
+    pub deadline: Option<Duration>,
+}
+
+/// The failed attempt of a call
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    /// One-based number of the attempt
+    pub number: u32,
+    /// Time from the start of the first attempt to the failure
+    pub elapsed: Duration,
+    /// Description of the error of the attempt
+    pub error: String,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            deadline: Some(Duration::from_secs(120)),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy that makes only one attempt.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Delay after the failed attempt with the specified one-based number.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let base = as_secs_f64(self.initial_backoff) * self.multiplier.powi(attempt as i32 - 1);
+        let base = base.min(as_secs_f64(self.max_backoff));
+        let jitter = base * self.jitter * (rand::random::<f64>() * 2.0 - 1.0);
+
+        from_secs_f64((base + jitter).max(0.0))
+    }
+
+    /// Runs the call until it succeeds, fails with a permanent error
+    /// or the policy is exhausted.
+    ///
+    /// If the call was repeated, the final error is `Error::RetriesExhausted`
+    /// with the history of the attempts, whether the policy is exhausted
+    /// or the last attempt failed with a permanent error.
+    pub fn run<T, F>(&self, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let start = Instant::now();
+        let mut attempts: Vec<Attempt> = vec![];
+
+        loop {
+            let e = match call() {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+
+            match self.after_failure(start, &mut attempts, e) {
+                Next::Retry(delay) => thread::sleep(delay),
+                Next::Fail(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Records the failed attempt and decides whether to repeat the call.
+    pub(crate) fn after_failure(
+        &self,
+        start: Instant,
+        attempts: &mut Vec<Attempt>,
+        e: error::Error,
+    ) -> Next {
+        let number = attempts.len() as u32 + 1;
+        attempts.push(Attempt {
+            number: number,
+            elapsed: start.elapsed(),
+            error: e.to_string(),
+        });
+
+        let delay = self.backoff(number);
+        let out_of_time = match self.deadline {
+            Some(deadline) => start.elapsed() + delay >= deadline,
+            None => false,
+        };
+
+        if !e.is_transient() || number >= self.max_attempts || out_of_time {
+            if attempts.len() == 1 {
+                return Next::Fail(e);
+            }
+
+            return Next::Fail(error::Error::RetriesExhausted {
+                attempts: attempts.split_off(0),
+                last: Box::new(e),
+            });
+        }
+
+        warn!("Attempt {} failed, retrying in {:?}: {}", number, delay, e);
+        Next::Retry(delay)
+    }
+}
+
+/// What to do after the failed attempt
+pub(crate) enum Next {
+    /// Repeat the call after the delay
+    Retry(Duration),
+    /// Give up with the error
+    Fail(error::Error),
+}
+
+impl fmt::Display for Attempt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "attempt {} after {:?}: {}",
+            self.number, self.elapsed, self.error
+        )
+    }
+}
+
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+fn from_secs_f64(secs: f64) -> Duration {
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1_000_000_000.0) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use reqwest::StatusCode;
+
+    use super::*;
+    use error::Error;
+    use rpser::RpcError;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(8),
+            multiplier: 2.0,
+            jitter: 0.0,
+            deadline: None,
+        }
+    }
+
+    fn transient() -> Error {
+        Error::HttpStatus(StatusCode::ServiceUnavailable)
+    }
+
+    fn fault(code: &str) -> Error {
+        Error::RpcError(RpcError::Fault {
+            fault_code: code.into(),
+            fault_string: "Fault".into(),
+            fault_detail: None,
+        })
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_limit() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(8),
+            ..policy()
+        };
+
+        let delays: Vec<u64> = (1..7).map(|attempt| policy.backoff(attempt).as_secs()).collect();
+
+        assert_eq!(delays, vec![1, 2, 4, 8, 8, 8]);
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            jitter: 0.5,
+            ..policy()
+        };
+
+        for _ in 0..1000 {
+            let delay = policy.backoff(2);
+            assert!(delay >= Duration::from_millis(99), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(301), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn transient_errors() {
+        assert!(transient().is_transient());
+        assert!(Error::IoError(io::Error::new(io::ErrorKind::TimedOut, "timeout")).is_transient());
+        assert!(fault("soap:Server").is_transient());
+        for kind in vec![io::ErrorKind::ConnectionReset, io::ErrorKind::Interrupted] {
+            assert!(Error::IoError(io::Error::new(kind, "reset")).is_transient());
+        }
+
+        assert!(!Error::IoError(io::Error::new(io::ErrorKind::NotFound, "none")).is_transient());
+        assert!(!Error::IoError(io::Error::new(io::ErrorKind::InvalidData, "bad")).is_transient());
+
+        assert!(!Error::HttpStatus(StatusCode::BadRequest).is_transient());
+        assert!(!fault("soap:Client").is_transient());
+        assert!(!Error::FnsError("Too many records".into()).is_transient());
+        assert!(!Error::TooManyRecords.is_transient());
+    }
+
+    #[test]
+    fn retries_until_success() {
+        let mut calls = 0;
+
+        let result = policy().run(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(transient())
+            } else {
+                Ok(calls)
+            }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn exhausted_retries_keep_the_history() {
+        let mut calls = 0;
+
+        let result: Result<()> = policy().run(|| {
+            calls += 1;
+            Err(transient())
+        });
+
+        assert_eq!(calls, 5);
+        match result {
+            Err(Error::RetriesExhausted { ref attempts, ref last }) => {
+                assert_eq!(attempts.len(), 5);
+                assert_eq!(attempts[4].number, 5);
+                assert!(last.is_transient());
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn permanent_error_is_not_wrapped() {
+        let result: Result<()> = policy().run(|| Err(fault("soap:Client")));
+
+        match result {
+            Err(Error::RpcError(RpcError::Fault { ref fault_code, .. })) => {
+                assert_eq!(fault_code, "soap:Client")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn permanent_error_keeps_the_history() {
+        let mut calls = 0;
+
+        let result: Result<()> = policy().run(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(transient())
+            } else {
+                Err(fault("soap:Client"))
+            }
+        });
+
+        assert_eq!(calls, 3);
+        match result {
+            Err(Error::RetriesExhausted { ref attempts, ref last }) => {
+                assert_eq!(attempts.len(), 3);
+                assert!(attempts[0].error.contains("503"), "{}", attempts[0].error);
+                match **last {
+                    Error::RpcError(RpcError::Fault { ref fault_code, .. }) => {
+                        assert_eq!(fault_code, "soap:Client")
+                    }
+                    ref other => panic!("unexpected error {:?}", other),
+                }
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deadline_stops_the_retries() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_millis(50),
+            deadline: Some(Duration::from_millis(120)),
+            ..policy()
+        };
+        let mut calls = 0;
+
+        let result: Result<()> = policy.run(|| {
+            calls += 1;
+            Err(transient())
+        });
+
+        assert!(result.is_err());
+        assert!(calls < 5, "{} calls", calls);
+    }
+}
@@ -79,7 +79,7 @@ impl Response {
                     .get_at_path(&["faultstring"])?
                     .text
                     .unwrap_or(String::new()),
-                fault_detail: element.get_at_path(&["detail"])?,
+                fault_detail: element.get_at_path(&["detail"]).ok(),
             });
         }
 
@@ -99,7 +99,7 @@ pub enum RpcError {
     Fault {
         fault_code: String,
         fault_string: String,
-        fault_detail: Element,
+        fault_detail: Option<Element>,
     },
     XmlError {
         error: self::xml::Error,
@@ -192,4 +192,136 @@ impl From<xmltree::ParseError> for RpcError {
 pub type Result<T> = result::Result<T, RpcError>;
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+    use super::xml::Error as XmlError;
+
+    macro_rules! fixture {
+        ($name:expr) => (include_str!(concat!("../../tests/fixtures/", $name)))
+    }
+
+    /// The expected outcome of parsing a fixture
+    enum Expected {
+        Body(&'static str),
+        Fault(&'static str, &'static str, bool),
+        UnexpectedElement(&'static str),
+        NotFoundAtPath(&'static [&'static str]),
+        ExpectedNotEmpty(&'static str),
+        XmlTreeError,
+    }
+
+    #[test]
+    fn response_from_xml_fixtures() {
+        let cases = vec![
+            ("nds_response.xml", fixture!("nds_response.xml"), Expected::Body("NdsResponse2")),
+            (
+                "nds_response_empty.xml",
+                fixture!("nds_response_empty.xml"),
+                Expected::Body("NdsResponse2"),
+            ),
+            (
+                "nds_response_err_msg.xml",
+                fixture!("nds_response_err_msg.xml"),
+                Expected::Body("NdsResponse2"),
+            ),
+            (
+                "fault_with_detail.xml",
+                fixture!("fault_with_detail.xml"),
+                Expected::Fault("soap:Server", "Internal server error", true),
+            ),
+            (
+                "fault_without_detail.xml",
+                fixture!("fault_without_detail.xml"),
+                Expected::Fault(
+                    "soap:Client",
+                    "Unmarshalling Error: unexpected element NdsRequest",
+                    false,
+                ),
+            ),
+            (
+                "nds_response_wire.xml",
+                fixture!("nds_response_wire.xml"),
+                Expected::Body("NdsResponse2"),
+            ),
+            (
+                "fault_wire.xml",
+                fixture!("fault_wire.xml"),
+                Expected::Fault(
+                    "soap:Client",
+                    "Unmarshalling Error: cvc-pattern-valid: Value '77070838' is not facet-valid \
+                     with respect to pattern '[0-9]{10}|[0-9]{12}' for type 'INNType'.",
+                    false,
+                ),
+            ),
+            (
+                "unexpected_root.xml",
+                fixture!("unexpected_root.xml"),
+                Expected::UnexpectedElement("html"),
+            ),
+            (
+                "missing_body.xml",
+                fixture!("missing_body.xml"),
+                Expected::NotFoundAtPath(&["Body"]),
+            ),
+            (
+                "empty_body.xml",
+                fixture!("empty_body.xml"),
+                Expected::ExpectedNotEmpty("Body"),
+            ),
+            ("malformed.xml", fixture!("malformed.xml"), Expected::XmlTreeError),
+        ];
+
+        for (name, xml, expected) in cases {
+            match (Response::from_xml(xml), expected) {
+                (Ok(response), Expected::Body(tag)) => assert_eq!(response.body.name, tag, "{}", name),
+                (
+                    Err(RpcError::Fault {
+                        fault_code,
+                        fault_string,
+                        fault_detail,
+                    }),
+                    Expected::Fault(code, string, has_detail),
+                ) => {
+                    assert_eq!(fault_code, code, "{}", name);
+                    assert_eq!(fault_string, string, "{}", name);
+                    assert_eq!(fault_detail.is_some(), has_detail, "{}", name);
+                }
+                (Err(RpcError::UnexpectedElement { tag }), Expected::UnexpectedElement(expected)) => {
+                    assert_eq!(tag, expected, "{}", name)
+                }
+                (
+                    Err(RpcError::XmlError {
+                        error: XmlError::NotFoundAtPath { path },
+                    }),
+                    Expected::NotFoundAtPath(expected),
+                ) => assert_eq!(path, expected, "{}", name),
+                (
+                    Err(RpcError::XmlError {
+                        error: XmlError::ExpectedNotEmpty { parent },
+                    }),
+                    Expected::ExpectedNotEmpty(expected),
+                ) => assert_eq!(parent, expected, "{}", name),
+                (Err(RpcError::XmlTreeError { .. }), Expected::XmlTreeError) => {}
+                (result, _) => panic!("{}: unexpected result {:?}", name, result),
+            }
+        }
+    }
+
+    #[test]
+    fn method_as_xml_builds_envelope() {
+        let method = Method::new("NdsRequest2").with(
+            Element::node("req:NP")
+                .with_attr("INN", "4205036750")
+                .with_attr("KPP", "420501001"),
+        );
+
+        let envelope = method.as_xml("http://ws.unisoft/FNSNDSCAWS2/Request", "req");
+        let mut bytes = envelope.as_bytes();
+        let element = Element::parse(&mut bytes).unwrap();
+
+        assert_eq!(element.name, "Envelope");
+        let body = element.get_at_path(&["Body", "NdsRequest2", "NP"]).unwrap();
+        assert_eq!(body.get_attr("INN"), "4205036750");
+        assert_eq!(body.get_attr("KPP"), "420501001");
+    }
+}
@@ -374,3 +374,70 @@ fn get_typed_string(element: &Element, value_type: &str) -> Result<String, Error
         }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tree() -> Element {
+        let xml = include_str!("../../tests/fixtures/fault_with_detail.xml");
+        let mut bytes = xml.as_bytes();
+        Element::parse(&mut bytes).unwrap()
+    }
+
+    #[test]
+    fn descend_returns_element_at_path() {
+        let fault = tree().descend(&["Body", "Fault"]).unwrap();
+
+        assert_eq!(fault.name, "Fault");
+        assert_eq!(fault.children.len(), 3);
+    }
+
+    #[test]
+    fn descend_reports_full_missing_path() {
+        match tree().descend(&["Body", "Fault", "faultactor"]) {
+            Err(Error::NotFoundAtPath { path }) => {
+                assert_eq!(path, vec!["Body", "Fault", "faultactor"])
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn descend_first_fails_on_empty_element() {
+        let message = tree()
+            .descend(&["Body", "Fault", "detail", "message"])
+            .unwrap();
+
+        match message.descend_first() {
+            Err(Error::ExpectedNotEmpty { parent }) => assert_eq!(parent, "message"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn get_at_path_keeps_parent() {
+        let tree = tree();
+
+        let detail = tree.get_at_path(&["Body", "Fault", "detail", "message"])
+            .unwrap();
+        assert_eq!(
+            detail.text,
+            Some("Database is not available".to_string())
+        );
+
+        match tree.get_at_path(&["Body", "Header"]) {
+            Err(Error::NotFoundAtPath { path }) => assert_eq!(path, vec!["Body", "Header"]),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(tree.name, "Envelope");
+    }
+
+    #[test]
+    fn get_attr_returns_empty_string_for_missing_attribute() {
+        let element = Element::node("NP").with_attr("INN", "4205036750");
+
+        assert_eq!(element.get_attr("INN"), "4205036750");
+        assert_eq!(element.get_attr("KPP"), "");
+    }
+}
@@ -0,0 +1,64 @@
+//! Typed messages of the `FNSNDSCAWS_2` service.
+//!
+//! Generated by the build script from `wsdl/FNSNDSCAWS_2.wsdl`, so a change
+//! of the schema shows up as a compile error in the code using the messages.
+//! Every element of the schemas is a struct with a field per attribute and
+//! per child element, named in snake case: `DTActFL` is `dt_act_fl`. Nested
+//! elements are named after their parent, e.g. `NdsRequest2Np`.
+//!
+//! Every struct implements `FromElement` and has `to_element`; the input
+//! messages of the operations also have `to_method`.
+
+use xmltree::Element;
+
+use super::{FromElement, Result};
+use rpser::Method;
+use rpser::xml::BuildElement;
+
+include!(concat!(env!("OUT_DIR"), "/fnsndscaws2.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpser;
+
+    #[test]
+    fn request_is_built() {
+        let request = NdsRequest2 {
+            np: vec![
+                NdsRequest2Np {
+                    inn: "7707083893".into(),
+                    kpp: Some("773601001".into()),
+                    dt: Some("01.02.2018".into()),
+                },
+                NdsRequest2Np {
+                    inn: "500100732259".into(),
+                    kpp: None,
+                    dt: None,
+                },
+            ],
+        };
+
+        let method = request.to_method("req");
+        assert_eq!(method.name, "NdsRequest2");
+        assert_eq!(method.args.len(), 2);
+        assert_eq!(method.args[0].name, "req:NP");
+        assert_eq!(method.args[0].get_attr("KPP"), "773601001");
+        assert!(!method.args[1].attributes.contains_key("KPP"));
+    }
+
+    #[test]
+    fn response_is_parsed() {
+        let xml = include_str!("../tests/fixtures/nds_response.xml");
+        let response = rpser::Response::from_xml(xml).unwrap();
+        let response = NdsResponse2::from_element(response.body).unwrap();
+
+        assert_eq!(response.dt_act_fl, "17.10.2017");
+        assert_eq!(response.err_msg, None);
+        assert_eq!(response.np.len(), 3);
+        assert_eq!(response.np[1].state, 3);
+        assert_eq!(response.np[1].kpp, Some("662301002".into()));
+        assert_eq!(response.np[2].kpp, Some("".into()));
+        assert_eq!(NDS_REQUEST2_NP_MAX_OCCURS, 10_000);
+    }
+}
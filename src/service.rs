@@ -0,0 +1,384 @@
+//! Settings and calls shared by the clients of the JSON services of the
+//! tax service: NPD, EGRUL, "Transparent business", bank account blocking
+//! and the MSP register.
+//!
+//! The clients validate their input locally before calling the service:
+//! the INN, the BIK and the OGRN by their length and control digits.
+//! Invalid input is reported as the error of the service of the client,
+//! such as `Error::PbError`, and is never sent: the service would reject
+//! it anyway, and every request counts against its rate limit.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+use reqwest;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use super::{error, http, validate, NpchkClientBuilder, Result, RetryPolicy};
+use metrics::{Metrics, NoMetrics};
+use throttle::Throttle;
+
+/// Implements the settings shared by the builders of the clients, kept
+/// in the `config: ServiceConfig` field of the builder, and the
+/// constructors of the client, which keeps the `service: Service` built
+/// from them.
+///
+/// The builder implements `Default` with the settings of the public service.
+macro_rules! service_client {
+    ($client:ident, $builder:ident) => {
+        impl<'a> From<&'a $crate::NpchkClientBuilder> for $builder {
+            /// Builder with the `User-Agent`, timeout, proxy, retry policy
+            /// and metrics of the `npchk` client, and the public service.
+            fn from(other: &'a $crate::NpchkClientBuilder) -> $builder {
+                let mut builder = $builder::default();
+                builder.config.share(other);
+                builder
+            }
+        }
+
+        impl $builder {
+            /// Create new builder with the settings of the public service.
+            pub fn new() -> $builder {
+                $builder::default()
+            }
+
+            /// Set the connection point of the service; the base URL
+            /// without the trailing slash if the service has several.
+            pub fn url<S>(mut self, url: S) -> Self
+            where
+                S: Into<String>,
+            {
+                self.config.url = url.into();
+                self
+            }
+
+            /// Set the `User-Agent` header sent with every request.
+            pub fn user_agent<S>(mut self, user_agent: S) -> Self
+            where
+                S: Into<String>,
+            {
+                self.config.user_agent = user_agent.into();
+                self
+            }
+
+            /// Set the timeout of a request.
+            pub fn timeout(mut self, timeout: ::std::time::Duration) -> Self {
+                self.config.timeout = Some(timeout);
+                self
+            }
+
+            /// Send every request through the proxy.
+            pub fn proxy<S>(mut self, proxy: S) -> Self
+            where
+                S: Into<String>,
+            {
+                self.config.proxy = Some(proxy.into());
+                self
+            }
+
+            /// Set the policy of repeating the requests failed with transient errors.
+            ///
+            /// By default a failed request is not repeated.
+            pub fn retry(mut self, retry: $crate::RetryPolicy) -> Self {
+                self.config.retry = retry;
+                self
+            }
+
+            /// Set the receiver of the measurements of the calls.
+            ///
+            /// By default nothing is recorded.
+            pub fn metrics(mut self, metrics: ::std::sync::Arc<$crate::metrics::Metrics>) -> Self {
+                self.config.metrics = metrics;
+                self
+            }
+        }
+
+        impl $client {
+            /// Create new client with the settings of the public service.
+            pub fn new() -> $crate::Result<$client> {
+                $builder::new().build()
+            }
+
+            /// Create new builder of the client.
+            pub fn builder() -> $builder {
+                $builder::new()
+            }
+
+            /// The connection point of the service.
+            pub fn url(&self) -> &str {
+                &self.service.url
+            }
+        }
+    };
+}
+
+/// Settings of the connection to a service
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    pub url: String,
+    pub user_agent: String,
+    pub timeout: Option<Duration>,
+    pub proxy: Option<String>,
+    pub retry: RetryPolicy,
+    pub metrics: Arc<Metrics>,
+}
+
+/// Connection to a service
+#[derive(Debug)]
+pub struct Service {
+    pub url: String,
+    pub user_agent: String,
+    pub retry: RetryPolicy,
+    pub metrics: Arc<Metrics>,
+    /// Limit of the rate of the requests, unlimited by default
+    pub throttle: Throttle,
+    pub http: reqwest::Client,
+}
+
+/// Request sent by `Service::call_json`
+#[derive(Debug, Clone, Copy)]
+pub enum Call<'a> {
+    Get,
+    Form(&'a [(&'a str, &'a str)]),
+    Json(&'a str),
+}
+
+/// Errors the services report in the body of the answer
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ErrorReply {
+    #[serde(rename = "ERROR")]
+    error: Option<String>,
+    #[serde(rename = "ERRORS")]
+    errors: Option<BTreeMap<String, Vec<String>>>,
+}
+
+impl ServiceConfig {
+    /// Settings of the public service at the URL, without a timeout,
+    /// a proxy or repeated requests.
+    pub fn new(url: &str) -> ServiceConfig {
+        ServiceConfig {
+            url: url.into(),
+            user_agent: concat!("npchk/", env!("CARGO_PKG_VERSION")).into(),
+            timeout: None,
+            proxy: None,
+            retry: RetryPolicy::none(),
+            metrics: Arc::new(NoMetrics),
+        }
+    }
+
+    /// Takes the `User-Agent`, timeout, proxy, retry policy and metrics
+    /// of the `npchk` client, keeping the URL.
+    pub fn share(&mut self, other: &NpchkClientBuilder) {
+        self.user_agent = other.user_agent.clone();
+        self.timeout = other.timeout;
+        self.proxy = other.proxy.clone();
+        self.retry = other.retry.clone();
+        self.metrics = other.metrics.clone();
+    }
+
+    /// Creates the HTTP client with the settings.
+    pub fn connect(self) -> Result<Service> {
+        let http = http::client(self.timeout, self.proxy.as_ref().map(|s| s.as_str()))?;
+
+        Ok(Service {
+            url: self.url,
+            user_agent: self.user_agent,
+            retry: self.retry,
+            metrics: self.metrics,
+            throttle: Throttle::unlimited(),
+            http: http,
+        })
+    }
+}
+
+impl Service {
+    /// Sends the request to the path under the URL of the service,
+    /// repeating it by the retry policy, and parses the JSON answer.
+    ///
+    /// `service_error` returns the error the service reported in the body,
+    /// if any; other unsuccessful answers are `Error::HttpStatus`.
+    pub fn call_json<T, F>(
+        &self,
+        method: &str,
+        path: &str,
+        call: Call,
+        service_error: F,
+    ) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn(&str) -> Option<error::Error>,
+    {
+        let url = format!("{}{}", self.url, path);
+
+        self.retry.run(|| {
+            self.throttle.wait();
+
+            let start = Instant::now();
+            let response = match call {
+                Call::Get => http::get_with(&self.http, &url, &self.user_agent),
+                Call::Form(form) => http::post_form(&self.http, &url, &self.user_agent, form),
+                Call::Json(json) => http::post_json(&self.http, &url, &self.user_agent, None, json),
+            };
+            let size = response.as_ref().map_or(0, |response| response.body.len());
+            self.metrics.http_request(method, start.elapsed(), size);
+            let response = response?;
+
+            if let Some(e) = service_error(&response.body) {
+                return Err(e);
+            }
+            if !response.status.is_success() {
+                return Err(error::Error::HttpStatus(response.status));
+            }
+
+            Ok(serde_json::from_str(&response.body)?)
+        })
+    }
+}
+
+/// The error the service reported in the `ERROR` or `ERRORS` field
+/// of the answer, the messages joined with `; `.
+pub fn reported_error(body: &str) -> Option<String> {
+    match serde_json::from_str::<ErrorReply>(body) {
+        Ok(ErrorReply {
+            errors: Some(errors),
+            ..
+        }) => {
+            let messages: Vec<String> = errors.into_iter().flat_map(|(_, m)| m).collect();
+            Some(messages.join("; "))
+        }
+        Ok(ErrorReply { error: Some(e), .. }) => Some(e),
+        _ => None,
+    }
+}
+
+/// Checks the INN locally, `error` makes the error of the service
+/// of the description of the problem.
+pub fn check_inn<F>(inn: &str, error: F) -> Result<()>
+where
+    F: FnOnce(String) -> error::Error,
+{
+    match validate::validate_inn(inn) {
+        Some(state) => Err(error(state.description().into())),
+        None => Ok(()),
+    }
+}
+
+/// Parses the date sent as `dd.mm.yyyy` or `yyyy-mm-dd`, ignoring the time if any.
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+    let date = value.trim().split_whitespace().next().unwrap_or("");
+    NaiveDate::parse_from_str(date, "%d.%m.%Y")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y-%m-%d"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::prelude::*;
+
+    use super::*;
+    use error::Error;
+
+    /// Asserts that every call fails with the error matching the pattern
+    /// and the mock receives no request.
+    macro_rules! assert_not_sent {
+        ($server:expr, $pattern:pat, $($call:expr),+) => {{
+            $(match $call {
+                Err($pattern) => {}
+                other => panic!("unexpected result of {}: {:?}", stringify!($call), other),
+            })+
+            assert!($server.requests().is_empty());
+        }};
+    }
+
+    #[test]
+    fn valid_inn_passes() {
+        assert!(check_inn("7707083893", Error::FnsError).is_ok());
+        assert!(check_inn("500100732259", Error::FnsError).is_ok());
+        match check_inn("7707083890", Error::FnsError) {
+            Err(Error::FnsError(ref msg)) => assert!(!msg.is_empty()),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "npd")]
+    #[test]
+    fn invalid_npd_inn_is_not_sent() {
+        use mock::MockNpdServer;
+        use npd::{NpdRequest, VALIDATION_ERROR};
+
+        let server = MockNpdServer::start().unwrap();
+        let client = server.client().unwrap();
+        let date = NaiveDate::from_ymd(2019, 1, 10);
+
+        // A valid INN of a legal entity, the service checks individuals only
+        match client.check_npd(&NpdRequest::new("7707083893", date)) {
+            Err(Error::NpdError { ref code, .. }) => assert_eq!(code, VALIDATION_ERROR),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_not_sent!(
+            server,
+            Error::NpdError { .. },
+            client.check_npd(&NpdRequest::new("027714145907", date))
+        );
+    }
+
+    #[cfg(feature = "egrul")]
+    #[test]
+    fn invalid_egrul_query_is_not_sent() {
+        use mock::MockEgrulServer;
+
+        let server = MockEgrulServer::start().unwrap();
+        let client = server.client().unwrap();
+
+        assert_not_sent!(
+            server,
+            Error::EgrulError(_),
+            client.search("7707083890"),
+            client.search("1027700132196"),
+            client.search("ООО")
+        );
+    }
+
+    #[cfg(feature = "pb")]
+    #[test]
+    fn invalid_pb_inn_is_not_sent() {
+        use mock::MockPbServer;
+
+        let server = MockPbServer::start().unwrap();
+        let client = server.client().unwrap();
+
+        assert_not_sent!(server, Error::PbError(_), client.risk_flags("6648185611"));
+    }
+
+    #[cfg(feature = "bi")]
+    #[test]
+    fn invalid_bi_request_is_not_sent() {
+        use bi::BlockingRequest;
+        use mock::MockBiServer;
+
+        let server = MockBiServer::start().unwrap();
+        let client = server.client().unwrap();
+
+        assert_not_sent!(
+            server,
+            Error::BiError(_),
+            client.check_blocking(&BlockingRequest::new("6648185611", "044525225")),
+            client.check_blocking(&BlockingRequest::new("6648185610", "04452522"))
+        );
+    }
+
+    #[cfg(feature = "msp")]
+    #[test]
+    fn invalid_msp_inn_is_not_sent() {
+        use mock::MockMspServer;
+
+        let server = MockMspServer::start().unwrap();
+        let client = server.client().unwrap();
+
+        assert_not_sent!(server, Error::MspError(_), client.lookup("6648185611"));
+    }
+}
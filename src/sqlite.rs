@@ -0,0 +1,49 @@
+//! Helpers shared by the stores kept in SQLite databases.
+//!
+//! Available with the `sqlite` feature.
+
+use chrono::prelude::*;
+use rusqlite::Connection;
+
+use super::Result;
+
+/// Implements `open` and `open_in_memory` of the store on top of its
+/// `from_connection`, which creates the schema.
+macro_rules! sqlite_store {
+    ($store:ident) => {
+        impl $store {
+            /// Opens the database file, creating it if it does not exist.
+            pub fn open<P: AsRef<::std::path::Path>>(path: P) -> $crate::Result<$store> {
+                $store::from_connection(::rusqlite::Connection::open(path)?)
+            }
+
+            /// Opens the database kept in memory.
+            pub fn open_in_memory() -> $crate::Result<$store> {
+                $store::from_connection(::rusqlite::Connection::open_in_memory()?)
+            }
+        }
+    };
+}
+
+/// Runs `f` in a transaction, rolling it back if `f` fails.
+pub fn transaction<T, F>(conn: &Connection, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    conn.execute_batch("BEGIN")?;
+    match f() {
+        Ok(value) => {
+            conn.execute_batch("COMMIT")?;
+            Ok(value)
+        }
+        Err(e) => {
+            let _ = conn.execute_batch("ROLLBACK");
+            Err(e)
+        }
+    }
+}
+
+/// Parses the time stored as RFC 3339.
+pub fn parse_datetime(value: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(value)?.with_timezone(&Utc))
+}
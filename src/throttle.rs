@@ -0,0 +1,77 @@
+//! Limit of the request rate imposed by the services.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Limits the calls to `requests` in any window of `per`,
+/// blocking the callers until a call is allowed
+///
+/// The limit is shared by every thread using the same throttle.
+#[derive(Debug)]
+pub struct Throttle {
+    requests: usize,
+    per: Duration,
+    /// Start times of the calls in the current window
+    calls: Mutex<VecDeque<Instant>>,
+}
+
+impl Throttle {
+    pub fn new(requests: u32, per: Duration) -> Throttle {
+        Throttle {
+            requests: requests.max(1) as usize,
+            per: per,
+            calls: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Throttle that never blocks.
+    pub fn unlimited() -> Throttle {
+        Throttle::new(u32::max_value(), Duration::from_secs(0))
+    }
+
+    /// Waits until the call is allowed and counts it.
+    pub fn wait(&self) {
+        loop {
+            let delay = {
+                let mut calls = self.calls.lock().unwrap();
+                let now = Instant::now();
+                while calls
+                    .front()
+                    .map_or(false, |start| now.duration_since(*start) >= self.per)
+                {
+                    calls.pop_front();
+                }
+
+                if calls.len() < self.requests {
+                    calls.push_back(now);
+                    return;
+                }
+
+                self.per - now.duration_since(calls[0])
+            };
+
+            debug!("Rate limit reached, waiting {:?}", delay);
+            thread::sleep(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spaces_the_calls() {
+        let throttle = Throttle::new(2, Duration::from_millis(200));
+        let start = Instant::now();
+
+        throttle.wait();
+        throttle.wait();
+        assert!(start.elapsed() < Duration::from_millis(200));
+
+        throttle.wait();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
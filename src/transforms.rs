@@ -1,12 +1,10 @@
 use xmltree::Element;
-use super::{error, NdsResponse, Partner, Result};
-use super::rpser::xml::BuildElement;
+use super::{error, schema, NdsResponse, Partner, Result};
+use super::models::partner_state::{PartnerKind, PartnerState};
 
 use chrono::prelude::*;
 use chrono::ParseResult;
 
-use std::str::FromStr;
-
 /// The trait to convert the server response xml to structure
 pub trait FromElement {
     fn from_element(element: Element) -> Result<Self>
@@ -18,21 +16,31 @@ fn get_datetime(value: &str) -> ParseResult<DateTime<Utc>> {
     Utc.datetime_from_str(&format!("{} 00:00:00", value), "%d.%m.%Y %H:%M:%S")
 }
 
+fn partner<'a>(np: schema::NdsResponse2Np) -> Result<Partner<'a>> {
+    Ok(Partner {
+        state: Some(PartnerState::from_code(PartnerKind::from_inn(&np.inn), np.state)),
+        dt: get_datetime(&np.dt)?,
+        inn: np.inn.into(),
+        kpp: np.kpp.unwrap_or_default().into(),
+    })
+}
+
 impl<'a> FromElement for NdsResponse<'a> {
     fn from_element(element: Element) -> Result<NdsResponse<'a>> {
-        let err_msg: String = element.get_attr("errMsg");
+        let err_msg = element.attributes.get("errMsg").cloned().unwrap_or_default();
         if !err_msg.is_empty() {
             return Err(error::Error::FnsError(err_msg));
         }
 
+        let response = schema::NdsResponse2::from_element(element)?;
         let mut rsp: NdsResponse = NdsResponse {
-            dtact_fl: get_datetime(&element.get_attr("DTActFL"))?,
-            dtact_ul: get_datetime(&element.get_attr("DTActUL"))?,
+            dtact_fl: get_datetime(&response.dt_act_fl)?,
+            dtact_ul: get_datetime(&response.dt_act_ul)?,
             partners: vec![],
         };
 
-        for elm in element.children {
-            rsp.partners.push(Partner::from_element(elm)?);
+        for np in response.np {
+            rsp.partners.push(partner(np)?);
         }
 
         Ok(rsp)
@@ -41,11 +49,118 @@ impl<'a> FromElement for NdsResponse<'a> {
 
 impl<'a> FromElement for Partner<'a> {
     fn from_element(element: Element) -> Result<Partner<'a>> {
-        Ok(Partner {
-            inn: element.get_attr("INN").into(),
-            kpp: element.get_attr("KPP").into(),
-            dt: get_datetime(&element.get_attr("DT"))?,
-            state: i32::from_str(&element.get_attr("State"))?,
-        })
+        partner(schema::NdsResponse2Np::from_element(element)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rpser;
+
+    macro_rules! fixture {
+        ($name:expr) => (include_str!(concat!("../tests/fixtures/", $name)))
+    }
+
+    fn parse(xml: &str) -> Result<NdsResponse<'static>> {
+        let response = rpser::Response::from_xml(xml)?;
+        NdsResponse::from_element(response.body)
+    }
+
+    #[test]
+    fn nds_response_is_parsed() {
+        let rsp = parse(fixture!("nds_response.xml")).unwrap();
+
+        assert_eq!(rsp.dtact_fl, Utc.ymd(2017, 10, 17).and_hms(0, 0, 0));
+        assert_eq!(rsp.dtact_ul, Utc.ymd(2017, 10, 16).and_hms(0, 0, 0));
+
+        let partners: Vec<_> = rsp.partners
+            .iter()
+            .map(|p| (&*p.inn, &*p.kpp, p.dt.date(), p.state))
+            .collect();
+        assert_eq!(
+            partners,
+            vec![
+                (
+                    "4205036750",
+                    "420501001",
+                    Utc.ymd(2017, 10, 18),
+                    Some(PartnerState::Active),
+                ),
+                (
+                    "6648185610",
+                    "662301002",
+                    Utc.ymd(2017, 10, 18),
+                    Some(PartnerState::KppMismatch),
+                ),
+                (
+                    "500100732259",
+                    "",
+                    Utc.ymd(2017, 9, 1),
+                    Some(PartnerState::Inactive),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn wire_format_is_parsed() {
+        let rsp = parse(fixture!("nds_response_wire.xml")).unwrap();
+
+        assert_eq!(rsp.dtact_fl, Utc.ymd(2018, 2, 15).and_hms(0, 0, 0));
+        assert_eq!(rsp.dtact_ul, Utc.ymd(2018, 2, 16).and_hms(0, 0, 0));
+        let partners: Vec<_> = rsp.partners
+            .iter()
+            .map(|p| (&*p.inn, &*p.kpp, p.state))
+            .collect();
+        assert_eq!(
+            partners,
+            vec![
+                ("7707083893", "773601001", Some(PartnerState::Active)),
+                ("7707083893", "773601002", Some(PartnerState::KppMismatch)),
+                ("000000000000", "", Some(PartnerState::NotRegistered)),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_partner_list_is_parsed() {
+        let rsp = parse(fixture!("nds_response_empty.xml")).unwrap();
+
+        assert!(rsp.partners.is_empty());
+    }
+
+    #[test]
+    fn err_msg_is_fns_error() {
+        match parse(fixture!("nds_response_err_msg.xml")) {
+            Err(error::Error::FnsError(msg)) => {
+                assert_eq!(msg, "Превышено допустимое количество запросов")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_state_is_parse_int_error() {
+        match parse(fixture!("missing_state.xml")) {
+            Err(error::Error::ParseIntError(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_actuality_date_is_parse_date_error() {
+        match parse(fixture!("missing_actuality.xml")) {
+            Err(error::Error::ParseDateTimeError(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fault_is_rpc_error() {
+        match parse(fixture!("fault_with_detail.xml")) {
+            Err(error::Error::RpcError(rpser::RpcError::Fault { .. })) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
     }
 }
@@ -0,0 +1,177 @@
+//! Local validation of the request data.
+//!
+//! Reproduces the checks the service performs on the input and reports
+//! the same state codes, so that obviously invalid partners can be rejected
+//! without a round-trip to the service.
+
+use chrono::prelude::*;
+
+use super::{Partner, PartnerKind, PartnerState};
+
+const INN10_WEIGHTS: [u32; 9] = [2, 4, 10, 3, 5, 9, 4, 6, 8];
+const INN12_WEIGHTS_11: [u32; 10] = [7, 2, 4, 10, 3, 5, 9, 4, 6, 8];
+const INN12_WEIGHTS_12: [u32; 11] = [3, 7, 2, 4, 10, 3, 5, 9, 4, 6, 8];
+
+/// Checks the partner the same way the service does.
+///
+/// Returns the state the service would report for the invalid partner,
+/// or `None` if the partner passes the local validation.
+pub fn validate(partner: &Partner) -> Option<PartnerState> {
+    if let Some(state) = validate_inn(&partner.inn) {
+        return Some(state);
+    }
+
+    // A legal entity without the KPP is answered by the service with 3,
+    // the KPP does not match or was not specified
+    if partner.kind() == PartnerKind::LegalEntity && !partner.kpp.is_empty() {
+        if let Some(state) = validate_kpp(&partner.kpp) {
+            return Some(state);
+        }
+    }
+
+    validate_date(&partner.dt)
+}
+
+/// Checks the length, characters and control digits
+/// of the Taxpayer identification number.
+pub fn validate_inn(inn: &str) -> Option<PartnerState> {
+    let length = inn.chars().count();
+    if length != 10 && length != 12 {
+        return Some(PartnerState::InvalidInnLength);
+    }
+
+    if !inn.chars().all(|c| c.is_ascii_digit()) {
+        return Some(PartnerState::InvalidInnCharacters);
+    }
+
+    let digits: Vec<u32> = inn.chars().filter_map(|c| c.to_digit(10)).collect();
+    let valid = if digits.len() == 10 {
+        control_digit(&digits, &INN10_WEIGHTS) == digits[9]
+    } else {
+        control_digit(&digits, &INN12_WEIGHTS_11) == digits[10]
+            && control_digit(&digits, &INN12_WEIGHTS_12) == digits[11]
+    };
+
+    if valid {
+        None
+    } else {
+        Some(PartnerState::IncorrectInn)
+    }
+}
+
+/// Checks the length and characters of the reason code of registration.
+///
+/// The code has the `NNNNPPNNN` format, where `N` is a digit and `P`
+/// is a digit or a capital latin letter.
+pub fn validate_kpp(kpp: &str) -> Option<PartnerState> {
+    if kpp.chars().count() != 9 {
+        return Some(PartnerState::InvalidKppLength);
+    }
+
+    let valid = kpp.chars().enumerate().all(|(i, c)| match i {
+        4 | 5 => c.is_ascii_digit() || c.is_ascii_uppercase(),
+        _ => c.is_ascii_digit(),
+    });
+
+    if valid {
+        None
+    } else {
+        Some(PartnerState::InvalidKppCharacters)
+    }
+}
+
+/// Checks that the date is not earlier than 01.01.1991
+/// and not later than the current date.
+pub fn validate_date(dt: &DateTime<Utc>) -> Option<PartnerState> {
+    let date = dt.date();
+    if date < Utc.ymd(1991, 1, 1) || date > Utc::today() {
+        Some(PartnerState::IncorrectDate)
+    } else {
+        None
+    }
+}
+
+/// Checks the date in the `dd.mm.yyyy` format used by the service.
+pub fn validate_date_str(value: &str) -> Option<PartnerState> {
+    match NaiveDate::parse_from_str(value, "%d.%m.%Y") {
+        Ok(date) => validate_date(&Utc.from_utc_date(&date).and_hms(0, 0, 0)),
+        Err(_) => Some(PartnerState::IncorrectDateFormat),
+    }
+}
+
+fn control_digit(digits: &[u32], weights: &[u32]) -> u32 {
+    let sum: u32 = digits
+        .iter()
+        .zip(weights.iter())
+        .map(|(d, w)| d * w)
+        .sum();
+
+    sum % 11 % 10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn inn_control_digits() {
+        assert_eq!(validate_inn("7707083893"), None);
+        assert_eq!(validate_inn("6648185610"), None);
+        assert_eq!(validate_inn("500100732259"), None);
+        assert_eq!(validate_inn("7707083890"), Some(PartnerState::IncorrectInn));
+        assert_eq!(validate_inn("500100732250"), Some(PartnerState::IncorrectInn));
+        assert_eq!(validate_inn("500100732209"), Some(PartnerState::IncorrectInn));
+    }
+
+    #[test]
+    fn inn_length_and_characters() {
+        assert_eq!(validate_inn(""), Some(PartnerState::InvalidInnLength));
+        assert_eq!(validate_inn("770708389"), Some(PartnerState::InvalidInnLength));
+        assert_eq!(validate_inn("77070838931"), Some(PartnerState::InvalidInnLength));
+        assert_eq!(validate_inn("77070838A3"), Some(PartnerState::InvalidInnCharacters));
+        assert_eq!(validate_inn("77070838 3"), Some(PartnerState::InvalidInnCharacters));
+        assert_eq!(validate_inn("АБВГДЕЖЗИК"), Some(PartnerState::InvalidInnCharacters));
+        assert_eq!(validate_inn("７７０７０８３８９３"), Some(PartnerState::InvalidInnCharacters));
+    }
+
+    #[test]
+    fn kpp_format() {
+        assert_eq!(validate_kpp("773601001"), None);
+        assert_eq!(validate_kpp("7736AZ001"), None);
+        assert_eq!(validate_kpp("77360100"), Some(PartnerState::InvalidKppLength));
+        assert_eq!(validate_kpp("7736010011"), Some(PartnerState::InvalidKppLength));
+        assert_eq!(validate_kpp(""), Some(PartnerState::InvalidKppLength));
+        assert_eq!(validate_kpp("7736az001"), Some(PartnerState::InvalidKppCharacters));
+        assert_eq!(validate_kpp("773601A01"), Some(PartnerState::InvalidKppCharacters));
+        assert_eq!(validate_kpp("7736ЖЖ001"), Some(PartnerState::InvalidKppCharacters));
+    }
+
+    #[test]
+    fn date_range() {
+        assert_eq!(validate_date(&Utc::now()), None);
+        assert_eq!(validate_date(&Utc.ymd(1991, 1, 1).and_hms(0, 0, 0)), None);
+        assert_eq!(
+            validate_date(&Utc.ymd(1990, 12, 31).and_hms(23, 59, 59)),
+            Some(PartnerState::IncorrectDate)
+        );
+        assert_eq!(
+            validate_date(&(Utc::now() + Duration::days(2))),
+            Some(PartnerState::IncorrectDate)
+        );
+
+        assert_eq!(validate_date_str("01.02.2018"), None);
+        assert_eq!(validate_date_str("31.12.1990"), Some(PartnerState::IncorrectDate));
+        assert_eq!(validate_date_str("2018-02-01"), Some(PartnerState::IncorrectDateFormat));
+        assert_eq!(validate_date_str("31.02.2018"), Some(PartnerState::IncorrectDateFormat));
+    }
+
+    #[test]
+    fn kpp_is_not_checked_for_entrepreneurs() {
+        let entrepreneur = Partner::new("500100732259", "", Utc::now());
+        let entity = Partner::new("7707083893", "", Utc::now());
+
+        assert_eq!(validate(&entrepreneur), None);
+        assert_eq!(validate(&entity), None);
+    }
+}
@@ -0,0 +1,293 @@
+//! Re-checking of a watchlist of partners on a schedule.
+//!
+//! Available with the `watch` feature. The schedule is a cron expression
+//! with seconds, e.g. `0 0 9 * * Mon-Fri` for 9:00 on working days.
+//! The results of the last run and the time of the next one are kept
+//! in a state file, so a restarted watcher continues the schedule and
+//! reports changes against the run made before the restart.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+
+use chrono::prelude::*;
+use cron::Schedule;
+use serde_json;
+
+use super::{error, CheckOptions, NdsResponse, NpchkClient, Partner, Result};
+use history::{diff_runs, ChangeEvent};
+
+/// Partner to watch, checked on the date of each run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEntry {
+    pub inn: String,
+    pub kpp: String,
+}
+
+/// Receiver of the changes found by the watcher
+pub trait EventSink {
+    /// Called after every run with the changes against the previous run.
+    fn on_changes(&self, events: &[ChangeEvent], rsp: &NdsResponse);
+}
+
+impl<F> EventSink for F
+where
+    F: Fn(&[ChangeEvent], &NdsResponse),
+{
+    fn on_changes(&self, events: &[ChangeEvent], rsp: &NdsResponse) {
+        self(events, rsp)
+    }
+}
+
+/// The state of the watcher saved between restarts
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WatchState {
+    pub last_run: Option<DateTime<Utc>>,
+    pub next_run: Option<DateTime<Utc>>,
+    /// Results of the last successful run
+    pub last_response: Option<NdsResponse<'static>>,
+}
+
+/// Re-checks the watchlist on the schedule
+pub struct Watcher {
+    client: NpchkClient,
+    watchlist: Vec<WatchEntry>,
+    schedule: Schedule,
+    options: CheckOptions,
+    state_path: PathBuf,
+    state: WatchState,
+}
+
+/// Reads the watchlist file: one INN and optional KPP per line,
+/// separated by spaces, commas or semicolons. Empty lines and lines
+/// starting with `#` are skipped.
+pub fn load_watchlist<P: AsRef<Path>>(path: P) -> Result<Vec<WatchEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut watchlist = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.split(|c: char| c == ';' || c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty());
+        watchlist.push(WatchEntry {
+            inn: parts.next().unwrap_or("").to_string(),
+            kpp: parts.next().unwrap_or("").to_string(),
+        });
+    }
+
+    Ok(watchlist)
+}
+
+impl WatchState {
+    /// Reads the state file, the empty state if the file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<WatchState> {
+        if !path.as_ref().exists() {
+            return Ok(WatchState::default());
+        }
+
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    /// Writes the state file, replacing it atomically.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        serde_json::to_writer_pretty(File::create(&tmp)?, self)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+}
+
+impl Watcher {
+    /// Creates the watcher, restoring its state from the state file.
+    pub fn new<P: Into<PathBuf>>(
+        client: NpchkClient,
+        watchlist: Vec<WatchEntry>,
+        schedule: &str,
+        state_path: P,
+    ) -> Result<Watcher> {
+        let schedule = Schedule::from_str(schedule)
+            .map_err(|e| error::Error::ScheduleError(format!("{}: {}", schedule, e)))?;
+        let state_path = state_path.into();
+        let state = WatchState::load(&state_path)?;
+
+        Ok(Watcher {
+            client: client,
+            watchlist: watchlist,
+            schedule: schedule,
+            options: CheckOptions::default(),
+            state_path: state_path,
+            state: state,
+        })
+    }
+
+    /// Set the options of the check.
+    pub fn with_options(mut self, options: CheckOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The saved state of the watcher.
+    pub fn state(&self) -> &WatchState {
+        &self.state
+    }
+
+    /// Time of the next scheduled run.
+    ///
+    /// A run missed while the watcher was stopped is due immediately.
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        match self.state.next_run {
+            Some(next_run) => Some(next_run),
+            None => self.schedule.upcoming(Utc).next(),
+        }
+    }
+
+    /// Checks the watchlist now and saves the results.
+    ///
+    /// Returns the changes against the previous successful run. If the
+    /// service failed to check some partners, the results are not saved
+    /// and the error of the first failed chunk is returned.
+    pub fn run_once(&mut self) -> Result<Vec<ChangeEvent>> {
+        let now = Utc::now();
+        let dt = now.date().and_hms(0, 0, 0);
+        let partners: Vec<Partner<'static>> = self.watchlist
+            .iter()
+            .map(|entry| Partner::new(entry.inn.clone(), entry.kpp.clone(), dt))
+            .collect();
+
+        let mut batch = self.client.check_fns_batched(partners, &self.options);
+
+        self.state.last_run = Some(now);
+        self.state.next_run = self.schedule.after(&now).next();
+
+        if !batch.failures.is_empty() {
+            self.state.save(&self.state_path)?;
+            return Err(batch.failures.remove(0).error);
+        }
+
+        let rsp = match batch.response {
+            Some(rsp) => rsp,
+            None => return Ok(vec![]),
+        };
+        let events = match self.state.last_response {
+            Some(ref previous) => diff_runs(previous, &rsp),
+            None => vec![],
+        };
+
+        self.state.last_response = Some(rsp);
+        self.state.save(&self.state_path)?;
+
+        Ok(events)
+    }
+
+    /// Runs the watchlist on the schedule until the process is stopped,
+    /// passing the changes of every run to the sink.
+    ///
+    /// Errors of a run are logged and do not stop the watcher.
+    pub fn run<S: EventSink>(&mut self, sink: &S) -> Result<()> {
+        loop {
+            let next_run = match self.next_run() {
+                Some(next_run) => next_run,
+                None => return Ok(()),
+            };
+
+            if let Ok(delay) = next_run.signed_duration_since(Utc::now()).to_std() {
+                info!("Next check of the watchlist at {}", next_run);
+                thread::sleep(delay);
+            }
+
+            match self.run_once() {
+                Ok(events) => {
+                    if let Some(ref rsp) = self.state.last_response {
+                        sink.on_changes(&events, rsp);
+                    }
+                }
+                Err(e) => error!("Check of the watchlist failed: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+    use history::ChangeKind;
+    use mock::{Behavior, MockNpchkServer};
+
+    fn state_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("npchk-watch-{}-{}.json", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn watchlist() -> Vec<WatchEntry> {
+        vec![
+            WatchEntry {
+                inn: "7707083893".into(),
+                kpp: "773601001".into(),
+            },
+        ]
+    }
+
+    #[test]
+    fn reports_changes_across_restarts() {
+        let server = MockNpchkServer::start().unwrap();
+        let path = state_path("restart");
+
+        let mut watcher =
+            Watcher::new(server.client().unwrap(), watchlist(), "0 0 9 * * *", &*path).unwrap();
+        assert!(watcher.run_once().unwrap().is_empty());
+        let next_run = watcher.next_run();
+        assert!(next_run.unwrap() > Utc::now());
+        drop(watcher);
+
+        server.set_state("7707083893", "773601001", 3);
+        let mut watcher =
+            Watcher::new(server.client().unwrap(), watchlist(), "0 0 9 * * *", &*path).unwrap();
+        assert_eq!(watcher.next_run(), next_run);
+
+        let events = watcher.run_once().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, ChangeKind::KppMismatch);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keeps_results_of_failed_run() {
+        let server = MockNpchkServer::start().unwrap();
+        let path = state_path("failed");
+
+        let mut watcher =
+            Watcher::new(server.client().unwrap(), watchlist(), "0 0 9 * * *", &*path).unwrap();
+        watcher.run_once().unwrap();
+
+        server.respond_once_with(Behavior::Status(503));
+        assert!(watcher.run_once().is_err());
+        assert!(watcher.state().last_response.is_some());
+
+        let events = watcher.run_once().unwrap();
+        assert!(events.is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_schedule() {
+        let server = MockNpchkServer::start().unwrap();
+        let path = state_path("schedule");
+
+        assert!(Watcher::new(server.client().unwrap(), watchlist(), "every day", &*path).is_err());
+    }
+}
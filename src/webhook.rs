@@ -0,0 +1,500 @@
+//! Delivery of the partner state changes to webhooks.
+//!
+//! Available with the `webhook` feature. Every delivery is a `POST` of the
+//! `WebhookPayload` as JSON. If the webhook has a secret, the Unix time of
+//! the attempt is sent in the `X-Npchk-Timestamp` header, `<timestamp>.<body>`
+//! is signed with HMAC-SHA256 and the signature is sent in the
+//! `X-Npchk-Signature` header as `sha256=<hex>`. Receivers should reject
+//! timestamps too far from their clock, so a captured delivery can not be
+//! replayed later.
+//!
+//! Deliveries failed after all the attempts are appended to the dead-letter
+//! file, one JSON object per line, and can be sent again with
+//! `Notifier::redeliver` while their webhooks are configured.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::prelude::*;
+use hmac::{Hmac, Mac};
+use rand;
+use reqwest;
+use serde_json;
+use sha2::Sha256;
+
+use super::{error, http, Result, RetryPolicy};
+use history::{ChangeEvent, ChangeKind};
+
+/// Endpoint receiving the notifications
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature, the body is not signed if not set
+    pub secret: Option<String>,
+}
+
+/// Body of the notification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    /// Identifier of the notification, the same for every attempt
+    /// to deliver it
+    pub id: String,
+    /// Time the notification was created, RFC 3339
+    pub created_at: String,
+    pub events: Vec<ChangeEvent>,
+}
+
+/// Notification that could not be delivered
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub url: String,
+    /// Description of the last error
+    pub error: String,
+    /// Time of the last attempt, RFC 3339
+    pub failed_at: String,
+    pub payload: WebhookPayload,
+}
+
+/// Sends the partner state changes to the webhooks
+#[derive(Debug)]
+pub struct Notifier {
+    webhooks: Vec<Webhook>,
+    kinds: Option<Vec<ChangeKind>>,
+    user_agent: String,
+    retry: RetryPolicy,
+    dead_letter: Option<PathBuf>,
+    http: reqwest::Client,
+}
+
+/// Builder of the `Notifier`
+#[derive(Debug, Clone)]
+pub struct NotifierBuilder {
+    webhooks: Vec<Webhook>,
+    kinds: Option<Vec<ChangeKind>>,
+    user_agent: String,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    dead_letter: Option<PathBuf>,
+}
+
+impl Default for NotifierBuilder {
+    fn default() -> NotifierBuilder {
+        NotifierBuilder {
+            webhooks: vec![],
+            kinds: None,
+            user_agent: concat!("npchk/", env!("CARGO_PKG_VERSION")).into(),
+            timeout: Some(Duration::from_secs(30)),
+            retry: RetryPolicy::default(),
+            dead_letter: None,
+        }
+    }
+}
+
+impl NotifierBuilder {
+    pub fn new() -> NotifierBuilder {
+        NotifierBuilder::default()
+    }
+
+    /// Add the webhook.
+    pub fn webhook(mut self, webhook: Webhook) -> Self {
+        self.webhooks.push(webhook);
+        self
+    }
+
+    /// Send only the changes of the kinds, every change by default.
+    pub fn kinds(mut self, kinds: &[ChangeKind]) -> Self {
+        self.kinds = Some(kinds.to_vec());
+        self
+    }
+
+    /// Set the `User-Agent` header sent with every request.
+    pub fn user_agent<S>(mut self, user_agent: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Set the timeout of a request, 30 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the policy of repeating the failed deliveries.
+    ///
+    /// By default `RetryPolicy::default()` is used.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Append the failed deliveries to the file.
+    pub fn dead_letter<P>(mut self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.dead_letter = Some(path.into());
+        self
+    }
+
+    /// Create the notifier.
+    pub fn build(self) -> Result<Notifier> {
+        let http = http::client(self.timeout, None)?;
+
+        Ok(Notifier {
+            webhooks: self.webhooks,
+            kinds: self.kinds,
+            user_agent: self.user_agent,
+            retry: self.retry,
+            dead_letter: self.dead_letter,
+            http: http,
+        })
+    }
+}
+
+impl WebhookPayload {
+    /// Creates the notification with a new random identifier.
+    pub fn new(events: Vec<ChangeEvent>) -> WebhookPayload {
+        WebhookPayload {
+            id: format!("{:016x}", rand::random::<u64>()),
+            created_at: Utc::now().to_rfc3339(),
+            events: events,
+        }
+    }
+}
+
+/// Signature of the body sent at the Unix time `timestamp`,
+/// the value of the `X-Npchk-Signature` header.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts any key");
+    mac.input(timestamp.to_string().as_bytes());
+    mac.input(b".");
+    mac.input(body);
+
+    let hex: Vec<String> = mac.result()
+        .code()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", hex.concat())
+}
+
+/// Reads the dead-letter file, nothing if the file does not exist.
+pub fn read_dead_letters<P: AsRef<Path>>(path: P) -> Result<Vec<DeadLetter>> {
+    if !path.as_ref().exists() {
+        return Ok(vec![]);
+    }
+
+    let mut letters = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            letters.push(serde_json::from_str(&line)?);
+        }
+    }
+
+    Ok(letters)
+}
+
+impl Notifier {
+    /// Create new builder of the notifier.
+    pub fn builder() -> NotifierBuilder {
+        NotifierBuilder::new()
+    }
+
+    /// Sends the changes to every webhook.
+    ///
+    /// Returns the deliveries that failed after all the attempts;
+    /// they are also appended to the dead-letter file if it is set.
+    /// Nothing is sent if no change passes the filter of kinds.
+    pub fn notify(&self, events: &[ChangeEvent]) -> Result<Vec<DeadLetter>> {
+        let events: Vec<ChangeEvent> = events
+            .iter()
+            .filter(|event| match self.kinds {
+                Some(ref kinds) => kinds.contains(&event.kind),
+                None => true,
+            })
+            .cloned()
+            .collect();
+        if events.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let payload = WebhookPayload::new(events);
+        let mut failed = vec![];
+        for webhook in &self.webhooks {
+            if let Err(e) = self.deliver(webhook, &payload) {
+                error!("Delivery of {} to {} failed: {}", payload.id, webhook.url, e);
+                failed.push(DeadLetter {
+                    url: webhook.url.clone(),
+                    error: e.to_string(),
+                    failed_at: Utc::now().to_rfc3339(),
+                    payload: payload.clone(),
+                });
+            }
+        }
+
+        self.write_dead_letters(&failed, true)?;
+        Ok(failed)
+    }
+
+    /// Sends the notifications of the dead-letter file again.
+    ///
+    /// The notifications delivered are removed from the file. Notifications
+    /// for webhooks no longer configured are not sent and stay in the file,
+    /// their secrets are unknown. Returns the number of the notifications
+    /// delivered.
+    pub fn redeliver(&self) -> Result<usize> {
+        let path = match self.dead_letter {
+            Some(ref path) => path,
+            None => return Ok(0),
+        };
+
+        let letters = read_dead_letters(path)?;
+        let total = letters.len();
+        let mut failed = vec![];
+        for mut letter in letters {
+            let configured = self.webhooks.iter().find(|webhook| webhook.url == letter.url);
+            let webhook = match configured {
+                Some(webhook) => webhook,
+                None => {
+                    warn!(
+                        "Webhook {} is not configured, {} is kept",
+                        letter.url, letter.payload.id
+                    );
+                    failed.push(letter);
+                    continue;
+                }
+            };
+
+            if let Err(e) = self.deliver(webhook, &letter.payload) {
+                letter.error = e.to_string();
+                letter.failed_at = Utc::now().to_rfc3339();
+                failed.push(letter);
+            }
+        }
+
+        self.write_dead_letters(&failed, false)?;
+        Ok(total - failed.len())
+    }
+
+    /// Sends the notification to the webhook, repeating the attempts
+    /// failed with transient errors. Every attempt is signed with its own time.
+    pub fn deliver(&self, webhook: &Webhook, payload: &WebhookPayload) -> Result<()> {
+        let body = serde_json::to_string(payload)?;
+
+        self.retry.run(|| {
+            let signature = webhook.secret.as_ref().map(|secret| {
+                let timestamp = Utc::now().timestamp();
+                (timestamp, sign(secret, timestamp, body.as_bytes()))
+            });
+            let response = http::post_json(
+                &self.http,
+                &webhook.url,
+                &self.user_agent,
+                signature.as_ref().map(|&(timestamp, ref s)| (timestamp, s.as_str())),
+                &body,
+            )?;
+
+            if response.status.is_success() {
+                Ok(())
+            } else {
+                Err(error::Error::HttpStatus(response.status))
+            }
+        })
+    }
+
+    fn write_dead_letters(&self, letters: &[DeadLetter], append: bool) -> Result<()> {
+        let path = match self.dead_letter {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        if !append && letters.is_empty() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        for letter in letters {
+            writeln!(file, "{}", serde_json::to_string(letter)?)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "watch")]
+impl ::watch::EventSink for Notifier {
+    fn on_changes(&self, events: &[ChangeEvent], _: &::NdsResponse) {
+        if let Err(e) = self.notify(events) {
+            error!("Notification of the changes failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use mock::{HttpResponse, MockServer};
+    use PartnerState;
+
+    fn events() -> Vec<ChangeEvent> {
+        vec![
+            ChangeEvent {
+                inn: "7707083893".into(),
+                kpp: "773601001".into(),
+                dt: Utc.ymd(2018, 2, 1).and_hms(0, 0, 0),
+                kind: ChangeKind::BecameInactive,
+                previous: Some(PartnerState::Active),
+                current: Some(PartnerState::Inactive),
+            },
+        ]
+    }
+
+    fn fast_retry() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn signs_the_payload() {
+        let server = MockServer::start(|_| HttpResponse::new(200, "text/plain", "")).unwrap();
+        let notifier = Notifier::builder()
+            .webhook(Webhook {
+                url: server.url(),
+                secret: Some("secret".into()),
+            })
+            .build()
+            .unwrap();
+
+        assert!(notifier.notify(&events()).unwrap().is_empty());
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let timestamp: i64 = requests[0].header("X-Npchk-Timestamp").unwrap().parse().unwrap();
+        assert!((Utc::now().timestamp() - timestamp).abs() < 60);
+        let signature = requests[0].header("X-Npchk-Signature").unwrap();
+        assert_eq!(signature, sign("secret", timestamp, requests[0].body.as_bytes()));
+        assert_ne!(signature, sign("secret", timestamp + 1, requests[0].body.as_bytes()));
+
+        let payload: WebhookPayload = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(payload.events, events());
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let server = {
+            let calls = calls.clone();
+            MockServer::start(move |_| {
+                let status = if calls.fetch_add(1, Ordering::SeqCst) == 0 { 503 } else { 200 };
+                HttpResponse::new(status, "text/plain", "")
+            }).unwrap()
+        };
+        let notifier = Notifier::builder()
+            .webhook(Webhook {
+                url: server.url(),
+                secret: None,
+            })
+            .retry(fast_retry())
+            .build()
+            .unwrap();
+
+        assert!(notifier.notify(&events()).unwrap().is_empty());
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn keeps_failed_deliveries() {
+        let path = env::temp_dir().join(format!("npchk-dead-letter-{}.jsonl", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let server = MockServer::start(|_| HttpResponse::new(500, "text/plain", "")).unwrap();
+        let notifier = Notifier::builder()
+            .webhook(Webhook {
+                url: server.url(),
+                secret: None,
+            })
+            .retry(fast_retry())
+            .dead_letter(&*path)
+            .build()
+            .unwrap();
+
+        let failed = notifier.notify(&events()).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(read_dead_letters(&path).unwrap(), failed);
+
+        assert_eq!(notifier.redeliver().unwrap(), 0);
+        assert_eq!(read_dead_letters(&path).unwrap().len(), 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keeps_letters_of_removed_webhooks() {
+        let path = env::temp_dir().join(format!("npchk-removed-webhook-{}.jsonl", process::id()));
+        let removed = MockServer::start(|_| HttpResponse::new(200, "text/plain", "")).unwrap();
+        let letter = DeadLetter {
+            url: removed.url(),
+            error: "HTTP status 500".into(),
+            failed_at: Utc::now().to_rfc3339(),
+            payload: WebhookPayload::new(events()),
+        };
+        writeln!(File::create(&path).unwrap(), "{}", serde_json::to_string(&letter).unwrap())
+            .unwrap();
+
+        let server = MockServer::start(|_| HttpResponse::new(200, "text/plain", "")).unwrap();
+        let notifier = Notifier::builder()
+            .webhook(Webhook {
+                url: server.url(),
+                secret: Some("secret".into()),
+            })
+            .dead_letter(&*path)
+            .build()
+            .unwrap();
+
+        assert_eq!(notifier.redeliver().unwrap(), 0);
+        assert!(removed.requests().is_empty());
+        assert!(server.requests().is_empty());
+        assert_eq!(read_dead_letters(&path).unwrap(), vec![letter]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn skips_filtered_kinds() {
+        let server = MockServer::start(|_| HttpResponse::new(200, "text/plain", "")).unwrap();
+        let notifier = Notifier::builder()
+            .webhook(Webhook {
+                url: server.url(),
+                secret: None,
+            })
+            .kinds(&[ChangeKind::NotRegistered])
+            .build()
+            .unwrap();
+
+        notifier.notify(&events()).unwrap();
+        assert!(server.requests().is_empty());
+    }
+}
@@ -0,0 +1,624 @@
+//! Export of check results to an Excel workbook.
+//!
+//! Available with the `xlsx` feature. The workbook contains the sheet
+//! of results coloured by state through conditional formatting, so the
+//! colours follow the state codes edited in the sheet, and the summary
+//! sheet with the number of partners per state.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::path::Path;
+
+use chrono::prelude::*;
+use xmltree::Element;
+use zip::write::{FileOptions, ZipWriter};
+
+use super::{CorrelatedResponse, NdsResponse, Partner, Result};
+use rpser::xml::BuildElement;
+
+const SPREADSHEET_NS: &'static str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const RELATIONSHIPS_NS: &'static str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const PACKAGE_RELATIONSHIPS_NS: &'static str =
+    "http://schemas.openxmlformats.org/package/2006/relationships";
+
+/// Index of the cell format of the headers in `styles.xml`
+const STYLE_HEADER: u32 = 1;
+
+/// Fills of the results by state in the order of priority: the condition
+/// on the state code in the `STATE` cell and the background colour.
+/// Unknown codes and missing answers are grey, active partners green,
+/// registered ones yellow and the rest red.
+const STATE_FILLS: [(&'static str, &'static str); 4] = [
+    ("OR(NOT(ISNUMBER(STATE)),STATE<0,STATE>12)", "D9D9D9"),
+    ("STATE=0", "C6EFCE"),
+    ("STATE<=3", "FFEB9C"),
+    ("STATE<=12", "FFC7CE"),
+];
+
+struct Row<'r, 'a: 'r> {
+    key: Option<String>,
+    partner: &'r Partner<'a>,
+}
+
+struct Report<'r, 'a: 'r> {
+    dtact_fl: DateTime<Utc>,
+    dtact_ul: DateTime<Utc>,
+    checked_at: DateTime<Utc>,
+    with_keys: bool,
+    rows: Vec<Row<'r, 'a>>,
+}
+
+/// Writes the check results to the workbook.
+pub fn write_response<W: Write + Seek>(
+    writer: W,
+    rsp: &NdsResponse,
+    checked_at: DateTime<Utc>,
+) -> Result<()> {
+    let report = Report {
+        dtact_fl: rsp.dtact_fl,
+        dtact_ul: rsp.dtact_ul,
+        checked_at: checked_at,
+        with_keys: false,
+        rows: rsp.partners
+            .iter()
+            .map(|p| Row {
+                key: None,
+                partner: p,
+            })
+            .collect(),
+    };
+
+    report.write(writer)
+}
+
+/// Writes the correlated check results to the workbook,
+/// with the keys of the caller in the first column.
+///
+/// Partners the service did not answer for are written as submitted.
+pub fn write_correlated<W: Write + Seek, K: fmt::Display>(
+    writer: W,
+    rsp: &CorrelatedResponse<K>,
+    checked_at: DateTime<Utc>,
+) -> Result<()> {
+    let report = Report {
+        dtact_fl: rsp.dtact_fl,
+        dtact_ul: rsp.dtact_ul,
+        checked_at: checked_at,
+        with_keys: true,
+        rows: rsp.partners
+            .iter()
+            .map(|p| Row {
+                key: Some(p.key.to_string()),
+                partner: p.result.as_ref().unwrap_or(&p.request),
+            })
+            .collect(),
+    };
+
+    report.write(writer)
+}
+
+/// Saves the check results to the workbook file.
+pub fn save_response<P: AsRef<Path>>(
+    path: P,
+    rsp: &NdsResponse,
+    checked_at: DateTime<Utc>,
+) -> Result<()> {
+    write_response(File::create(path)?, rsp, checked_at)
+}
+
+impl<'r, 'a> Report<'r, 'a> {
+    fn write<W: Write + Seek>(&self, writer: W) -> Result<()> {
+        let mut zip = ZipWriter::new(writer);
+        let parts = vec![
+            ("[Content_Types].xml", content_types()),
+            ("_rels/.rels", package_rels()),
+            ("xl/workbook.xml", workbook()),
+            ("xl/_rels/workbook.xml.rels", workbook_rels()),
+            ("xl/styles.xml", styles()),
+            ("xl/worksheets/sheet1.xml", self.results_sheet()),
+            ("xl/worksheets/sheet2.xml", self.summary_sheet()),
+        ];
+
+        for (name, part) in parts {
+            zip.start_file(name, FileOptions::default())?;
+            zip.write_all(part.to_string().as_bytes())?;
+        }
+        zip.finish()?;
+
+        Ok(())
+    }
+
+    fn results_sheet(&self) -> Element {
+        let mut rows = vec![
+            row(
+                1,
+                vec![
+                    text_cell(0, 1, "Data actuality, individual entrepreneurs", STYLE_HEADER),
+                    text_cell(1, 1, &format_date(&self.dtact_fl), 0),
+                ],
+            ),
+            row(
+                2,
+                vec![
+                    text_cell(0, 2, "Data actuality, legal entities", STYLE_HEADER),
+                    text_cell(1, 2, &format_date(&self.dtact_ul), 0),
+                ],
+            ),
+            row(
+                3,
+                vec![
+                    text_cell(0, 3, "Checked at", STYLE_HEADER),
+                    text_cell(
+                        1,
+                        3,
+                        &self.checked_at.format("%d.%m.%Y %H:%M:%S UTC").to_string(),
+                        0,
+                    ),
+                ],
+            ),
+        ];
+
+        let mut headers = vec![];
+        if self.with_keys {
+            headers.push("Key");
+        }
+        headers.extend(&["INN", "KPP", "DT", "State", "Description"]);
+        rows.push(row(
+            5,
+            headers
+                .iter()
+                .enumerate()
+                .map(|(col, header)| text_cell(col, 5, header, STYLE_HEADER))
+                .collect(),
+        ));
+
+        let state_col = headers.len() - 2;
+        for (i, r) in self.rows.iter().enumerate() {
+            let number = i + 6;
+            let mut values: Vec<String> = vec![];
+            if self.with_keys {
+                values.push(r.key.clone().unwrap_or_default());
+            }
+            values.push(r.partner.inn.to_string());
+            values.push(r.partner.kpp.to_string());
+            values.push(format_date(&r.partner.dt));
+
+            let mut cells: Vec<Element> = values
+                .iter()
+                .enumerate()
+                .map(|(col, value)| text_cell(col, number, value, 0))
+                .collect();
+            let col = cells.len();
+            match r.partner.state {
+                Some(state) => {
+                    cells.push(number_cell(col, number, state.code() as i64, 0));
+                    cells.push(text_cell(col + 1, number, state.description(), 0));
+                }
+                None => {
+                    cells.push(text_cell(col, number, "", 0));
+                    cells.push(text_cell(col + 1, number, "", 0));
+                }
+            }
+
+            rows.push(row(number, cells));
+        }
+
+        let mut worksheet = sheet(rows);
+        if !self.rows.is_empty() {
+            let range = format!(
+                "{}:{}",
+                cell_ref(0, 6),
+                cell_ref(headers.len() - 1, self.rows.len() + 5)
+            );
+            let state = format!("${}", cell_ref(state_col, 6));
+            worksheet = worksheet.with_child(state_formatting(&range, &state));
+        }
+
+        worksheet
+    }
+
+    fn summary_sheet(&self) -> Element {
+        // The same code means different states for the kinds of taxpayers,
+        // so the states are counted by the code and the name of the state
+        let mut counts: BTreeMap<(i32, &'static str), (&'static str, i64)> = BTreeMap::new();
+        let mut unknown = 0;
+        for r in &self.rows {
+            match r.partner.state {
+                Some(state) => {
+                    let key = (state.code(), state.name());
+                    counts.entry(key).or_insert((state.description(), 0)).1 += 1
+                }
+                None => unknown += 1,
+            }
+        }
+
+        let mut rows = vec![row(
+            1,
+            vec![
+                text_cell(0, 1, "State", STYLE_HEADER),
+                text_cell(1, 1, "Description", STYLE_HEADER),
+                text_cell(2, 1, "Partners", STYLE_HEADER),
+            ],
+        )];
+
+        for (i, (&(code, _), &(description, count))) in counts.iter().enumerate() {
+            let number = i + 2;
+            rows.push(row(
+                number,
+                vec![
+                    number_cell(0, number, code as i64, 0),
+                    text_cell(1, number, description, 0),
+                    number_cell(2, number, count, 0),
+                ],
+            ));
+        }
+
+        let mut total = counts.len() + 2;
+        if unknown > 0 {
+            rows.push(row(
+                total,
+                vec![
+                    text_cell(0, total, "", 0),
+                    text_cell(1, total, "The service did not answer", 0),
+                    number_cell(2, total, unknown, 0),
+                ],
+            ));
+            total += 1;
+        }
+
+        rows.push(row(
+            total,
+            vec![
+                text_cell(1, total, "Total", STYLE_HEADER),
+                number_cell(2, total, self.rows.len() as i64, STYLE_HEADER),
+            ],
+        ));
+
+        sheet(rows)
+    }
+}
+
+/// Colours the rows of `range` by the state code, `state` is the reference
+/// to the state cell of the first row with the column fixed.
+fn state_formatting(range: &str, state: &str) -> Element {
+    let rules = STATE_FILLS.iter().enumerate().map(|(i, &(condition, _))| {
+        Element::node("cfRule")
+            .with_attr("type", "expression")
+            .with_attr("dxfId", i.to_string())
+            .with_attr("priority", (i + 1).to_string())
+            .with_attr("stopIfTrue", "1")
+            .with_child(Element::node("formula").with_text(condition.replace("STATE", state)))
+    });
+
+    Element::node("conditionalFormatting")
+        .with_attr("sqref", range)
+        .with_children(rules)
+}
+
+fn format_date(dt: &DateTime<Utc>) -> String {
+    dt.format("%d.%m.%Y").to_string()
+}
+
+fn cell_ref(col: usize, row: usize) -> String {
+    let mut letters = vec![];
+    let mut n = col + 1;
+    while n > 0 {
+        letters.insert(0, (b'A' + ((n - 1) % 26) as u8) as char);
+        n = (n - 1) / 26;
+    }
+    format!("{}{}", letters.into_iter().collect::<String>(), row)
+}
+
+fn text_cell(col: usize, row: usize, value: &str, style: u32) -> Element {
+    Element::node("c")
+        .with_attr("r", cell_ref(col, row))
+        .with_attr("s", style.to_string())
+        .with_attr("t", "inlineStr")
+        .with_child(Element::node("is").with_child(Element::node("t").with_text(value)))
+}
+
+fn number_cell(col: usize, row: usize, value: i64, style: u32) -> Element {
+    Element::node("c")
+        .with_attr("r", cell_ref(col, row))
+        .with_attr("s", style.to_string())
+        .with_child(Element::node("v").with_text(value.to_string()))
+}
+
+fn row(number: usize, cells: Vec<Element>) -> Element {
+    Element::node("row")
+        .with_attr("r", number.to_string())
+        .with_children(cells)
+}
+
+fn sheet(rows: Vec<Element>) -> Element {
+    Element::node("worksheet")
+        .with_attr("xmlns", SPREADSHEET_NS)
+        .with_child(Element::node("sheetData").with_children(rows))
+}
+
+fn content_types() -> Element {
+    let ns = "http://schemas.openxmlformats.org/package/2006/content-types";
+    let sheet_type = "application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml";
+
+    Element::node("Types").with_attr("xmlns", ns).with_children(vec![
+        Element::node("Default")
+            .with_attr("Extension", "rels")
+            .with_attr(
+                "ContentType",
+                "application/vnd.openxmlformats-package.relationships+xml",
+            ),
+        Element::node("Default")
+            .with_attr("Extension", "xml")
+            .with_attr("ContentType", "application/xml"),
+        Element::node("Override")
+            .with_attr("PartName", "/xl/workbook.xml")
+            .with_attr(
+                "ContentType",
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml",
+            ),
+        Element::node("Override")
+            .with_attr("PartName", "/xl/styles.xml")
+            .with_attr(
+                "ContentType",
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml",
+            ),
+        Element::node("Override")
+            .with_attr("PartName", "/xl/worksheets/sheet1.xml")
+            .with_attr("ContentType", sheet_type),
+        Element::node("Override")
+            .with_attr("PartName", "/xl/worksheets/sheet2.xml")
+            .with_attr("ContentType", sheet_type),
+    ])
+}
+
+fn relationship(id: &str, rel_type: &str, target: &str) -> Element {
+    Element::node("Relationship")
+        .with_attr("Id", id)
+        .with_attr("Type", format!("{}/{}", RELATIONSHIPS_NS, rel_type))
+        .with_attr("Target", target)
+}
+
+fn package_rels() -> Element {
+    Element::node("Relationships")
+        .with_attr("xmlns", PACKAGE_RELATIONSHIPS_NS)
+        .with_child(relationship("rId1", "officeDocument", "xl/workbook.xml"))
+}
+
+fn workbook_rels() -> Element {
+    Element::node("Relationships")
+        .with_attr("xmlns", PACKAGE_RELATIONSHIPS_NS)
+        .with_children(vec![
+            relationship("rId1", "worksheet", "worksheets/sheet1.xml"),
+            relationship("rId2", "worksheet", "worksheets/sheet2.xml"),
+            relationship("rId3", "styles", "styles.xml"),
+        ])
+}
+
+fn workbook() -> Element {
+    let sheet = |name: &str, id: &str, rel: &str| {
+        Element::node("sheet")
+            .with_attr("name", name)
+            .with_attr("sheetId", id)
+            .with_attr("r:id", rel)
+    };
+
+    Element::node("workbook")
+        .with_attr("xmlns", SPREADSHEET_NS)
+        .with_attr("xmlns:r", RELATIONSHIPS_NS)
+        .with_child(Element::node("sheets").with_children(vec![
+            sheet("Results", "1", "rId1"),
+            sheet("Summary", "2", "rId2"),
+        ]))
+}
+
+fn styles() -> Element {
+    let dxf = |rgb: &str| {
+        let pattern = Element::node("patternFill")
+            .with_attr("patternType", "solid")
+            .with_child(Element::node("bgColor").with_attr("rgb", format!("FF{}", rgb)));
+        Element::node("dxf").with_child(Element::node("fill").with_child(pattern))
+    };
+    let font = |bold: bool| {
+        let font = if bold {
+            Element::node("font").with_child(Element::node("b"))
+        } else {
+            Element::node("font")
+        };
+        font.with_child(Element::node("sz").with_attr("val", "11"))
+            .with_child(Element::node("name").with_attr("val", "Calibri"))
+    };
+    let xf = |font_id: u32| {
+        Element::node("xf")
+            .with_attr("numFmtId", "0")
+            .with_attr("fontId", font_id.to_string())
+            .with_attr("fillId", "0")
+            .with_attr("borderId", "0")
+            .with_attr("applyFont", "1")
+    };
+
+    Element::node("styleSheet")
+        .with_attr("xmlns", SPREADSHEET_NS)
+        .with_children(vec![
+            Element::node("fonts")
+                .with_attr("count", "2")
+                .with_children(vec![font(false), font(true)]),
+            Element::node("fills").with_attr("count", "2").with_children(vec![
+                Element::node("fill")
+                    .with_child(Element::node("patternFill").with_attr("patternType", "none")),
+                Element::node("fill")
+                    .with_child(Element::node("patternFill").with_attr("patternType", "gray125")),
+            ]),
+            Element::node("borders")
+                .with_attr("count", "1")
+                .with_child(Element::node("border")),
+            Element::node("cellStyleXfs")
+                .with_attr("count", "1")
+                .with_child(xf(0)),
+            Element::node("cellXfs")
+                .with_attr("count", "2")
+                .with_children(vec![xf(0), xf(1)]),
+            Element::node("dxfs")
+                .with_attr("count", STATE_FILLS.len().to_string())
+                .with_children(STATE_FILLS.iter().map(|&(_, rgb)| dxf(rgb))),
+        ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use zip::ZipArchive;
+
+    use super::*;
+    use PartnerState;
+
+    fn partner(inn: &str, state: Option<PartnerState>) -> Partner<'static> {
+        let mut partner = Partner::new(inn.to_string(), String::new(), Utc::now());
+        partner.state = state;
+        partner
+    }
+
+    fn workbook_parts(rsp: &NdsResponse) -> (Element, Element, Element) {
+        let mut output = Cursor::new(vec![]);
+        write_response(&mut output, rsp, Utc::now()).unwrap();
+
+        let mut archive = ZipArchive::new(Cursor::new(output.into_inner())).unwrap();
+        let mut part = |name: &str| Element::parse(archive.by_name(name).unwrap()).unwrap();
+        (
+            part("xl/worksheets/sheet1.xml"),
+            part("xl/worksheets/sheet2.xml"),
+            part("xl/styles.xml"),
+        )
+    }
+
+    fn cell_values(row: &Element) -> Vec<String> {
+        row.children
+            .iter()
+            .map(|cell| match cell.get_child("v") {
+                Some(value) => value.text.clone().unwrap_or_default(),
+                None => cell.get_child("is")
+                    .and_then(|is| is.get_child("t"))
+                    .and_then(|t| t.text.clone())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    fn response() -> NdsResponse<'static> {
+        NdsResponse {
+            dtact_fl: Utc::now(),
+            dtact_ul: Utc::now(),
+            partners: vec![
+                partner("7707083893", Some(PartnerState::Active)),
+                partner("6648185610", Some(PartnerState::Active)),
+                partner("500100732259", Some(PartnerState::NotRegistered)),
+                partner("4205036750", None),
+            ],
+        }
+    }
+
+    #[test]
+    fn results_are_coloured_by_conditional_formatting() {
+        let (results, _, styles) = workbook_parts(&response());
+
+        let formatting = results.get_child("conditionalFormatting").unwrap();
+        assert_eq!(formatting.attributes["sqref"], "A6:E9");
+        let formulas: Vec<String> = formatting
+            .children
+            .iter()
+            .map(|rule| rule.get_child("formula").unwrap().text.clone().unwrap())
+            .collect();
+        assert_eq!(formulas[0], "OR(NOT(ISNUMBER($E6)),$E6<0,$E6>12)");
+        assert_eq!(formulas[1], "$E6=0");
+
+        let dxfs = styles.get_child("dxfs").unwrap();
+        assert_eq!(dxfs.attributes["count"], "4");
+        assert_eq!(dxfs.children.len(), formulas.len());
+        assert_eq!(styles.get_child("cellXfs").unwrap().children.len(), 2);
+
+        let rows = &results.get_child("sheetData").unwrap().children;
+        let first = cell_values(&rows[4]);
+        assert_eq!(first[0], "7707083893");
+        assert_eq!(first[3], "0");
+    }
+
+    #[test]
+    fn summary_adds_up_to_the_total() {
+        let (_, summary, _) = workbook_parts(&response());
+
+        let rows: Vec<Vec<String>> = summary
+            .get_child("sheetData")
+            .unwrap()
+            .children
+            .iter()
+            .map(cell_values)
+            .collect();
+        assert_eq!(rows[1][0], "0");
+        assert_eq!(rows[1][2], "2");
+        assert_eq!(rows[2][0], "4");
+        assert_eq!(rows[2][2], "1");
+        assert_eq!(rows[3][1], "The service did not answer");
+        assert_eq!(rows[3][2], "1");
+        assert_eq!(rows[4], vec!["Total".to_string(), "4".to_string()]);
+
+        let counted: i64 = rows[1..4].iter().map(|row| row[2].parse::<i64>().unwrap()).sum();
+        assert_eq!(counted, 4);
+    }
+
+    #[test]
+    fn states_with_the_same_code_are_counted_apart() {
+        let rsp = NdsResponse {
+            partners: vec![
+                partner("7707083893", Some(PartnerState::KppMismatch)),
+                partner("500100732259", Some(PartnerState::Unknown(3))),
+                partner("6648185610", Some(PartnerState::KppMismatch)),
+            ],
+            ..response()
+        };
+
+        let (_, summary, _) = workbook_parts(&rsp);
+
+        let rows: Vec<Vec<String>> = summary
+            .get_child("sheetData")
+            .unwrap()
+            .children
+            .iter()
+            .map(cell_values)
+            .collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[1][0], "3");
+        assert_eq!(rows[2][0], "3");
+        assert_eq!(rows[1][1], PartnerState::KppMismatch.description());
+        assert_eq!(rows[1][2], "2");
+        assert_eq!(rows[2][1], PartnerState::Unknown(3).description());
+        assert_eq!(rows[2][2], "1");
+    }
+
+    #[test]
+    fn checked_at_is_labelled_with_the_zone() {
+        let mut output = Cursor::new(vec![]);
+        let checked_at = Utc.ymd(2018, 2, 1).and_hms(10, 20, 30);
+        write_response(&mut output, &response(), checked_at).unwrap();
+
+        let mut archive = ZipArchive::new(Cursor::new(output.into_inner())).unwrap();
+        let results = Element::parse(archive.by_name("xl/worksheets/sheet1.xml").unwrap()).unwrap();
+        let rows = &results.get_child("sheetData").unwrap().children;
+
+        assert_eq!(cell_values(&rows[2])[1], "01.02.2018 10:20:30 UTC");
+    }
+
+    #[test]
+    fn empty_results_have_no_formatting() {
+        let rsp = NdsResponse {
+            partners: vec![],
+            ..response()
+        };
+
+        let (results, _, _) = workbook_parts(&rsp);
+
+        assert!(results.get_child("conditionalFormatting").is_none());
+    }
+}